@@ -1,4 +1,140 @@
+use std::{env, fmt::Write, fs, path::Path};
+
+const ROMAJI_TSV: &str = "tables/romaji.tsv";
+const HALFWIDTH_KATAKANA_TSV: &str = "tables/halfwidth_katakana.tsv";
+
 fn main() {
-    let target = std::env::var("TARGET").unwrap();
+    let target = env::var("TARGET").unwrap();
     println!("cargo:rustc-env=BUILD_TARGET={}", target);
+
+    // 変換表はTSVから生成する（並べ替えと重複の検査はここで行う）
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", ROMAJI_TSV);
+    println!("cargo:rerun-if-changed={}", HALFWIDTH_KATAKANA_TSV);
+
+    let mut out = String::new();
+    let romaji = romaji_table();
+    writeln!(out, "pub const ROMAJI_TO_HIRAGANA: &[(&str, &str)] = &[").unwrap();
+    for (k, v) in &romaji {
+        writeln!(out, "    ({:?}, {:?}),", k, v).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    let katakana = halfwidth_katakana_table();
+    writeln!(
+        out,
+        "pub const HIRAGANA_TO_HALFWIDTH_KATAKANA: &[(char, &str)] = &["
+    )
+    .unwrap();
+    for (k, v) in &katakana {
+        writeln!(out, "    ({:?}, {:?}),", k, v).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("tables.rs");
+    fs::write(dest, out).unwrap();
+}
+
+// 押し戻す文字は、実行時の表と同じくかなの末尾に小文字1文字として付ける
+fn romaji_table() -> Vec<(String, String)> {
+    let mut table = Vec::new();
+    for (line, cols) in rows(ROMAJI_TSV) {
+        let (romaji, kana, pushback) = match cols.as_slice() {
+            [r, k] => (r, k, ""),
+            [r, k, p] => (r, k, p.as_str()),
+            _ => fail(ROMAJI_TSV, line, "expected romaji<TAB>kana[<TAB>pushback]"),
+        };
+        if romaji.is_empty() || kana.is_empty() {
+            fail(ROMAJI_TSV, line, "empty column");
+        }
+        if kana.ends_with(|c: char| c.is_ascii_lowercase()) {
+            fail(ROMAJI_TSV, line, "kana ends with a lowercase letter");
+        }
+        if pushback.len() > 1 || pushback.chars().any(|c| !c.is_ascii_lowercase()) {
+            fail(ROMAJI_TSV, line, "pushback must be one lowercase letter");
+        }
+        table.push((line, romaji.clone(), kana.clone() + pushback));
+    }
+    // search_lookup_tableは二分探索するのでバイト順に並べる
+    table.sort_by(|a, b| a.1.cmp(&b.1));
+    for w in table.windows(2) {
+        if w[0].1 == w[1].1 {
+            fail(
+                ROMAJI_TSV,
+                w[1].0,
+                &format!("duplicate romaji (also line {})", w[0].0),
+            );
+        }
+    }
+    table.into_iter().map(|(_, k, v)| (k, v)).collect()
+}
+
+fn halfwidth_katakana_table() -> Vec<(char, String)> {
+    let mut table = Vec::new();
+    for (line, cols) in rows(HALFWIDTH_KATAKANA_TSV) {
+        let [hiragana, katakana] = cols.as_slice() else {
+            fail(
+                HALFWIDTH_KATAKANA_TSV,
+                line,
+                "expected hiragana<TAB>katakana",
+            );
+        };
+        let mut chars = hiragana.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            fail(
+                HALFWIDTH_KATAKANA_TSV,
+                line,
+                "hiragana must be one character",
+            );
+        };
+        if katakana.is_empty() {
+            fail(HALFWIDTH_KATAKANA_TSV, line, "empty column");
+        }
+        table.push((line, c, katakana.clone()));
+    }
+    table.sort_by_key(|e| e.1);
+    for w in table.windows(2) {
+        if w[0].1 == w[1].1 {
+            let msg = format!("duplicate hiragana (also line {})", w[0].0);
+            fail(HALFWIDTH_KATAKANA_TSV, w[1].0, &msg);
+        }
+    }
+    table.into_iter().map(|(_, k, v)| (k, v)).collect()
+}
+
+// `#`で始まる行と空行を除き、(行番号, 列)を返す
+fn rows(path: &str) -> Vec<(usize, Vec<String>)> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .map(|(i, l)| {
+            (
+                i + 1,
+                l.split('\t').map(|c| unescape(path, i + 1, c)).collect(),
+            )
+        })
+        .collect()
+}
+
+// \t \n \\ だけを展開する（タブと改行は列や行の区切りなので）
+fn unescape(path: &str, line: usize, s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            _ => fail(path, line, "invalid escape"),
+        }
+    }
+    out
+}
+
+fn fail(path: &str, line: usize, msg: &str) -> ! {
+    panic!("{}:{}: {}", path, line, msg)
 }
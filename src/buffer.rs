@@ -6,6 +6,8 @@ pub struct Buffer {
     col: usize,
     selection_origin: Option<usize>,
     dirty: bool,
+    modified: bool, // 最後の送出以降に内容が変更されたか
+    bell: bool,     // 入力を受け付けなかったことを知らせる（ベルはフロントエンドが鳴らす）
 }
 
 impl Default for Buffer {
@@ -16,6 +18,8 @@ impl Default for Buffer {
             col: 0,
             selection_origin: None,
             dirty: false,
+            modified: false,
+            bell: false,
         }
     }
 }
@@ -39,6 +43,15 @@ impl Buffer {
         out
     }
 
+    // 行とカーソルの位置はそのままで、すべての文字をcにした写し（伏せ字の表示用）
+    pub fn masked(&self, c: char) -> Self {
+        let mut b = self.clone();
+        for line in &mut b.lines {
+            line.fill(c);
+        }
+        b
+    }
+
     pub fn cursor(&self) -> (usize, usize) {
         (self.row, self.col)
     }
@@ -97,6 +110,26 @@ impl Buffer {
         self.dirty
     }
 
+    pub fn ring_bell(&mut self) {
+        self.bell = true;
+    }
+
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.len() == 1 && self.lines[0].is_empty()
+    }
+
+    pub fn has_unsent(&self) -> bool {
+        self.modified && !self.is_empty()
+    }
+
+    pub fn mark_sent(&mut self) {
+        self.modified = false;
+    }
+
     // --- editing primitives ---
     pub fn clear(&mut self) {
         self.set_dirty();
@@ -109,13 +142,15 @@ impl Buffer {
 
     pub fn insert_char(&mut self, c: char) {
         self.set_dirty();
+        self.modified = true;
+        // 改行も選択範囲を置き換える（insert_textと同じ）
+        if self.selection_origin.is_some() {
+            self.delete_range();
+        }
         if c == '\n' {
             self.newline();
             return;
         }
-        if self.selection_origin.is_some() {
-            self.delete_range();
-        }
         let line = &mut self.lines[self.row];
         line.insert(self.col, c);
         self.col += 1;
@@ -128,6 +163,88 @@ impl Buffer {
         }
     }
 
+    pub fn insert_text(&mut self, s: &str) {
+        // 貼り付けなど大量の文字列向け：1文字ずつではなく行単位でまとめて挿入
+        self.set_dirty();
+        if s.is_empty() {
+            return;
+        }
+        self.modified = true;
+        if self.selection_origin.is_some() {
+            self.delete_range();
+        }
+        let mut parts = s.split('\n');
+        let line = &mut self.lines[self.row];
+        let right = line.split_off(self.col);
+        line.extend(parts.next().unwrap_or_default().chars());
+        self.col = line.len();
+
+        let mut new_lines: Vec<Vec<char>> = parts.map(|p| p.chars().collect()).collect();
+        if let Some(last) = new_lines.last_mut() {
+            self.col = last.len();
+        }
+        let n = new_lines.len();
+        match new_lines.last_mut() {
+            Some(last) => last.extend(right),
+            None => self.lines[self.row].extend(right),
+        }
+        self.lines.splice(self.row + 1..self.row + 1, new_lines);
+        self.row += n;
+    }
+
+    // 選択範囲か、カーソルのある段落（空行で区切られた行のまとまり）をwrapで組み直す
+    pub fn reflow(&mut self, wrap: impl Fn(&[char]) -> Vec<Vec<char>>) {
+        let (start, end, text, left, right) = if self.selection_origin.is_some() {
+            let ClosedInterval(l, r) = self.selection();
+            let line = &self.lines[self.row];
+            // 行末から選択すると右端が行の長さを指す
+            let (rest, right) = line.split_at((r + 1).min(line.len()));
+            let (left, text) = rest.split_at(l);
+            (
+                self.row,
+                self.row + 1,
+                text.to_vec(),
+                left.to_vec(),
+                right.to_vec(),
+            )
+        } else {
+            let blank = |l: &Vec<char>| l.iter().all(|c| c.is_whitespace());
+            if blank(&self.lines[self.row]) {
+                return;
+            }
+            let start = (0..self.row)
+                .rev()
+                .find(|&r| blank(&self.lines[r]))
+                .map_or(0, |r| r + 1);
+            let end = (self.row + 1..self.lines.len())
+                .find(|&r| blank(&self.lines[r]))
+                .unwrap_or(self.lines.len());
+            // 英単語どうしの改行は空白に、日本語どうしはそのままつなぐ
+            let mut joined: Vec<char> = Vec::new();
+            for line in &self.lines[start..end] {
+                if let (Some(a), Some(b)) = (joined.last(), line.first())
+                    && a.is_ascii_graphic()
+                    && b.is_ascii_graphic()
+                {
+                    joined.push(' ');
+                }
+                joined.extend(line);
+            }
+            (start, end, joined, Vec::new(), Vec::new())
+        };
+        self.set_dirty();
+        self.modified = true;
+        self.clear_selection_origin();
+        let mut lines = wrap(&text);
+        lines[0].splice(0..0, left);
+        // カーソルは組み直した範囲の末尾へ
+        let last = lines.len() - 1;
+        self.row = start + last;
+        self.col = lines[last].len();
+        lines[last].extend(right);
+        self.lines.splice(start..end, lines);
+    }
+
     pub fn backspace(&mut self) {
         // delete_rangeかdeleteでdirtyになる
         if self.selection_origin.is_some() {
@@ -236,6 +353,48 @@ impl Buffer {
         self.col = self.lines[self.row].len();
     }
 
+    // 次の単語（同じ種類の文字の並び）の先頭へ（行末では次の行の先頭へ）
+    pub fn word_right(&mut self) -> IsOperationDone {
+        if self.col >= self.lines[self.row].len() {
+            return self.move_right();
+        }
+        self.set_dirty();
+        self.clear_selection_origin();
+        let line = &self.lines[self.row];
+        let class = word_class(line[self.col]);
+        let mut col = self.col;
+        while col < line.len() && word_class(line[col]) == class {
+            col += 1;
+        }
+        while col < line.len() && line[col].is_whitespace() {
+            col += 1;
+        }
+        self.col = col;
+        true
+    }
+
+    // 単語の先頭へ（行頭では前の行の末尾へ）
+    pub fn word_left(&mut self) -> IsOperationDone {
+        if self.col == 0 {
+            return self.move_left();
+        }
+        self.set_dirty();
+        self.clear_selection_origin();
+        let line = &self.lines[self.row];
+        let mut col = self.col.min(line.len());
+        while col > 0 && line[col - 1].is_whitespace() {
+            col -= 1;
+        }
+        if let Some(&c) = col.checked_sub(1).and_then(|i| line.get(i)) {
+            let class = word_class(c);
+            while col > 0 && word_class(line[col - 1]) == class {
+                col -= 1;
+            }
+        }
+        self.col = col;
+        true
+    }
+
     pub fn select_right(&mut self) {
         self.set_dirty();
         if self.col < self.lines[self.row].len().saturating_sub(1) {
@@ -252,6 +411,55 @@ impl Buffer {
         }
     }
 
+    // queryの次（forwardでなければ前）の出現を選択する（端まで行けば反対の端から続ける）
+    // 行をまたぐ出現は探さない
+    pub fn find(&mut self, query: &[char], forward: bool) -> IsOperationDone {
+        if query.is_empty() {
+            return false;
+        }
+        let anchor = match self.selection_origin {
+            Some(_) => self.selection().0,
+            None => self.col,
+        };
+        let has_selection = self.selection_origin.is_some();
+        let n = self.lines.len();
+        for k in 0..=n {
+            let row = match forward {
+                true => (self.row + k) % n,
+                false => (self.row + n - k % n) % n,
+            };
+            let line = &self.lines[row];
+            let mut hits = (0..line.len().saturating_sub(query.len()) + 1)
+                .filter(|&i| line[i..].starts_with(query))
+                // カーソルのある行は、最初はカーソルより先（前）だけを見る
+                .filter(|&i| match (k, forward) {
+                    (0, true) => i > anchor || (i == anchor && !has_selection),
+                    (0, false) => i < anchor,
+                    _ => true,
+                });
+            let hit = match forward {
+                true => hits.next(),
+                false => hits.next_back(),
+            };
+            if let Some(i) = hit {
+                self.set_dirty();
+                self.row = row;
+                self.selection_origin = Some(i);
+                self.col = i + query.len() - 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    // row行目（0始まり、行数を超えれば最終行）の行頭へ
+    pub fn goto_line(&mut self, row: usize) {
+        self.set_dirty();
+        self.clear_selection_origin();
+        self.row = row.min(self.lines.len() - 1);
+        self.col = 0;
+    }
+
     // --- helpers ---
     fn set_dirty(&mut self) {
         self.dirty = true;
@@ -271,6 +479,7 @@ impl Buffer {
         let line = &mut self.lines[self.row];
         if self.col < line.len() {
             line.remove(self.col);
+            self.modified = true;
             true
         } else {
             false
@@ -282,6 +491,7 @@ impl Buffer {
             let next = self.lines.remove(self.row + 1);
             let cur = &mut self.lines[self.row];
             cur.extend(next);
+            self.modified = true;
         }
     }
 
@@ -306,3 +516,28 @@ impl Buffer {
         self.selection_origin = None;
     }
 }
+
+#[derive(PartialEq)]
+enum WordClass {
+    Space,
+    Alnum, // 半角・全角の英数字と_
+    Hiragana,
+    Katakana, // ーを含む
+    Kanji,    // 々を含む
+    Other,
+}
+
+// 単語単位の移動で、ひと続きとみなす文字の種類
+fn word_class(c: char) -> WordClass {
+    match c {
+        _ if c.is_whitespace() => WordClass::Space,
+        _ if c.is_alphanumeric() && (c.is_ascii() || ('\u{ff10}'..='\u{ff5a}').contains(&c)) => {
+            WordClass::Alnum
+        }
+        '_' => WordClass::Alnum,
+        'ぁ'..='ゖ' | 'ゝ' | 'ゞ' => WordClass::Hiragana,
+        'ァ'..='ヺ' | 'ー' | 'ヽ' | 'ヾ' | 'ｦ'..='ﾟ' => WordClass::Katakana,
+        '一'..='鿿' | '㐀'..='䶿' | '々' | '〆' => WordClass::Kanji,
+        _ => WordClass::Other,
+    }
+}
@@ -0,0 +1,35 @@
+//! 辞書になくても出す組み込みの候補（今日の日付、今の時刻、読みのカタカナ）
+//!
+//! 候補はLispの式（[`crate::gadget`]）なので、学習したものも変換するたびにその時点の値になる。
+
+use crate::kana::convert_to_katakana;
+
+pub const TAG: &str = "builtin"; // 候補の出所（CANDIDATE_SOURCE=show）
+
+const DATE: &[&str] = &[
+    "(format-time-string \"%Y年%-m月%-d日\")",
+    "(skk-current-date)",
+    "(unskk-wareki-date)",
+    "(format-time-string \"%Y-%m-%d\")",
+    "(format-time-string \"%Y/%m/%d\")",
+];
+const TIME: &[&str] = &[
+    "(format-time-string \"%-H時%-M分\")",
+    "(format-time-string \"%H:%M\")",
+    "(format-time-string \"%Y-%m-%d %H:%M\")",
+];
+
+/// 読み（半角英字に正規化したもの）に対応する組み込みの候補
+pub fn candidates(yomi: &str) -> &'static [&'static str] {
+    match yomi {
+        "きょう" | "today" => DATE,
+        "いま" | "now" => TIME,
+        _ => &[],
+    }
+}
+
+/// ひらがなだけの読みのカタカナ（KATAKANA_CANDIDATE、送りありの見出し語や英字の読みはNone）
+pub fn katakana(yomi: &str) -> Option<String> {
+    let is_kana = |c: char| matches!(c, 'ぁ'..='ゖ' | 'ー');
+    (!yomi.is_empty() && yomi.chars().all(is_kana)).then(|| convert_to_katakana(yomi))
+}
@@ -0,0 +1,244 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    process::{self, Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Config, Exec},
+    hook::EngineEvent,
+    transform,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const READ_CHUNK: usize = 8192;
+const PROGRESS_STEP: usize = 64 * 1024;
+const SNIFF_BYTES: usize = 4096;
+const BINARY_PERCENT: usize = 10; // 不正なUTF-8や制御文字がこの割合を超えたらバイナリとみなす
+const STDERR_MAX: u64 = 4096; // ステータス行に出すのは最初の行だけ
+
+static STDERR_FILES: AtomicUsize = AtomicUsize::new(0);
+
+pub type CommandResult = Result<(), String>;
+
+// 送出は別スレッドで行い、完了時にon_doneを呼ぶ（UIを止めない）
+pub fn copy_to_command<F>(text: String, config: &Config, on_done: F)
+where
+    F: FnOnce(CommandResult) + Send + 'static,
+{
+    let timeout = config.copy_timeout;
+    let text = transform::apply(&config.cpy_to_transform, &text);
+    let (stderr, err_file) = stderr_to_file();
+    let spawned = build_command(&config.exec, &config.cpy_to)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => return on_done(Err(format!("CPY_TO failed: {}", e))),
+    };
+    let stdin = child.stdin.take();
+    thread::spawn(move || {
+        // 入力を読まないコマンドでもタイムアウトできるよう、書き込みはさらに別スレッド
+        if let Some(mut stdin) = stdin {
+            thread::spawn(move || {
+                let _ = stdin.write_all(text.as_bytes());
+            });
+        }
+        let r = wait_with_timeout(&mut child, timeout, "CPY_TO");
+        on_done(r.map_err(|msg| with_stderr(msg, &read_stderr(err_file))));
+    });
+}
+
+// 貼り付け元の出力を上限つきで逐次読み込み、読み込んだバイト数をon_progressで通知する
+pub fn copy_from_command<P, F>(config: &Config, on_progress: P, on_done: F)
+where
+    P: Fn(usize) + Send + 'static,
+    F: FnOnce(Result<String, String>) + Send + 'static,
+{
+    let timeout = config.copy_timeout;
+    let max_bytes = config.paste_max_bytes;
+    let (stderr, err_file) = stderr_to_file();
+    let spawned = build_command(&config.exec, &config.cpy_from)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(stderr)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => return on_done(Err(format!("CPY_FROM failed: {}", e))),
+    };
+    let stdout = child.stdout.take();
+    thread::spawn(move || {
+        let reader = thread::spawn(move || {
+            let mut out = Vec::new();
+            let Some(mut stdout) = stdout else {
+                return Ok(out);
+            };
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut next_report = PROGRESS_STEP;
+            loop {
+                match stdout.read(&mut chunk) {
+                    Ok(0) => return Ok(out),
+                    Ok(n) => out.extend_from_slice(&chunk[..n]),
+                    Err(e) => return Err(format!("CPY_FROM failed: {}", e)),
+                }
+                if out.len() > max_bytes {
+                    // stdoutを閉じればコマンドは書き込みに失敗して終了する
+                    return Err(format!("CPY_FROM failed: exceeds {} bytes", max_bytes));
+                }
+                if out.len() >= next_report {
+                    on_progress(out.len());
+                    next_report += PROGRESS_STEP;
+                }
+            }
+        });
+        let r = wait_with_timeout(&mut child, timeout, "CPY_FROM");
+        let out = reader
+            .join()
+            .unwrap_or_else(|_| Err(String::from("CPY_FROM failed")));
+        on_done(match (out, r) {
+            (Err(msg), _) => Err(msg),
+            (Ok(_), Err(msg)) => Err(with_stderr(msg, &read_stderr(err_file))),
+            (Ok(out), Ok(())) if is_binary(&out) => {
+                Err(String::from("CPY_FROM failed: binary data"))
+            }
+            (Ok(out), Ok(())) => Ok(String::from_utf8_lossy(&out).into_owned()),
+        });
+    });
+}
+
+// EVENT_HOOKのコマンドを実行して終わるまで待つ（出来事は環境変数で渡す）
+pub fn run_event_hook(
+    exec: &Exec,
+    cmd: &str,
+    timeout: Duration,
+    event: &EngineEvent,
+) -> CommandResult {
+    let (stderr, err_file) = stderr_to_file();
+    let spawned = build_command(exec, cmd)
+        .env("UNSKK_EVENT", event.name())
+        .env("UNSKK_EVENT_TEXT", event.text())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => return Err(format!("EVENT_HOOK failed: {}", e)),
+    };
+    let r = wait_with_timeout(&mut child, timeout, "EVENT_HOOK");
+    r.map_err(|msg| with_stderr(msg, &read_stderr(err_file)))
+}
+
+// 先頭だけを見て、テキストでなさそうなデータ（画像など）を判定する
+fn is_binary(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(SNIFF_BYTES)];
+    if head.contains(&0) {
+        return true;
+    }
+    let text = String::from_utf8_lossy(head);
+    let total = text.chars().count();
+    let bad = text
+        .chars()
+        .filter(|&c| c == '\u{FFFD}' || (c.is_control() && !matches!(c, '\n' | '\r' | '\t')))
+        .count();
+    // 末尾で切れたマルチバイト文字は1文字分の誤差として許容
+    bad.saturating_sub(1) * 100 > total * BINARY_PERCENT
+}
+
+fn build_command(exec: &Exec, cmd: &str) -> Command {
+    match exec {
+        Exec::Shell(shell) => {
+            let mut c = Command::new(shell);
+            c.arg("-c").arg(cmd);
+            c
+        }
+        Exec::Direct => {
+            let mut argv = cmd.split_whitespace();
+            let mut c = Command::new(argv.next().unwrap_or_default());
+            c.args(argv);
+            c
+        }
+    }
+}
+
+// stderrはパイプでなく名前を消した一時ファイルで受け、終了（またはkill）の後に読む
+// フォークして残るプロセス（xclipなど）がstderrを開いたままでも、EOFを待たずに済む
+fn stderr_to_file() -> (Stdio, Option<File>) {
+    match temp_file().and_then(|f| Ok((f.try_clone()?, f))) {
+        Ok((clone, file)) => (Stdio::from(clone), Some(file)),
+        Err(_) => (Stdio::null(), None),
+    }
+}
+
+fn temp_file() -> io::Result<File> {
+    let n = STDERR_FILES.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("unskk-stderr-{}-{}", process::id(), n));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let _ = fs::remove_file(&path);
+    Ok(file)
+}
+
+// 読めたところまで（後から書き足されても待たない）
+fn read_stderr(file: Option<File>) -> String {
+    let mut err = Vec::new();
+    if let Some(mut file) = file
+        && file.seek(SeekFrom::Start(0)).is_ok()
+    {
+        let _ = file.take(STDERR_MAX).read_to_end(&mut err);
+    }
+    String::from_utf8_lossy(&err).into_owned()
+}
+
+// ステータス行は1行なので、stderrは最初の空でない行のみ
+fn with_stderr(mut msg: String, stderr: &str) -> String {
+    if let Some(line) = stderr.lines().map(str::trim).find(|l| !l.is_empty()) {
+        msg.push_str(": ");
+        msg.push_str(line);
+    }
+    msg
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Duration, label: &str) -> CommandResult {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("{} failed: {}", label, status)),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} failed: timed out", label));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("{} failed: {}", label, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stderr_of_a_forking_command_does_not_block() {
+        // 背景のsleepがstderrを開いたまま残る（xclipのフォークと同じ）
+        let exec = Exec::Shell(String::from("sh"));
+        let cmd = "sleep 3 & echo oops >&2; exit 1";
+        let (timeout, event) = (Duration::from_secs(2), EngineEvent::Mode("kana"));
+        let started = Instant::now();
+        let e = run_event_hook(&exec, cmd, timeout, &event).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(e, "EVENT_HOOK failed: exit status: 1: oops");
+    }
+}
@@ -0,0 +1,273 @@
+use std::{env, time::Duration};
+
+use crate::{
+    jisyo::{CandidateOrder, JisyoLookup, KatakanaCandidate},
+    romaji::{FailurePolicy, RomajiPreset, TimeoutAction},
+    status::Segment,
+    transform::Transform,
+};
+
+const ENV_ERR: &str = "missing environment variable: ";
+const ENV_INVALID: &str = "invalid environment variable: ";
+const DEFAULT_CPY_TIMEOUT_SEC: usize = 5;
+const DEFAULT_PASTE_MAX_BYTES: usize = 1024 * 1024;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const DEFAULT_TERM_SIZE: (usize, usize) = (80, 24);
+const DEFAULT_WRAP_WIDTH: usize = 72;
+
+// CPY_TO / CPY_FROM の実行方法
+#[derive(Clone)]
+pub enum Exec {
+    Shell(String), // <shell> -c <cmd>
+    Direct,        // <cmd> <args...>（空白区切り、クォートなし）
+}
+
+// Ctrl+Qで未送出の内容があるとき
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum QuitAction {
+    Confirm, // 終了してよいか確かめる
+    Send,    // CPY_TOへ送出してから終了する
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub exec: Exec,
+    pub cpy_to: String,
+    pub cpy_from: String,
+    pub cpy_to_transform: Vec<Transform>,
+    pub jisyo_path: String,
+    pub symbol_jisyo_path: Option<String>,
+    pub palette_path: Option<String>,
+    pub user_jisyo_path: Option<String>,
+    pub romaji_table_path: Option<String>,
+    pub romaji_preset: RomajiPreset,
+    pub romaji_timeout: Option<Duration>,
+    pub romaji_timeout_action: TimeoutAction,
+    pub romaji_failure: FailurePolicy,
+    pub learn_half_life: Option<Duration>,
+    pub candidate_order: CandidateOrder,
+    pub jisyo_lookup: JisyoLookup,
+    pub yomi_variants: bool,
+    pub katakana_candidate: KatakanaCandidate,
+    pub auto_send: Option<Duration>,
+    pub copy_timeout: Duration,
+    pub paste_max_bytes: usize,
+    pub fallback_term_size: (usize, usize),
+    pub status_segments: Vec<Segment>,
+    pub ruler_width: Option<usize>,
+    pub wrap_width: usize,
+    pub unknown_key_log: Option<String>,
+    pub control_socket: Option<String>,
+    pub quit_action: QuitAction,
+    pub show_candidate_source: bool,
+    pub candidate_preview: usize,
+    pub event_hook: Option<String>,
+    pub event_log: Option<String>,
+    // 以下はコマンドライン引数で指定する
+    pub print_on_exit: bool,
+    pub single_line: bool,
+    pub masked: bool,
+    pub view_only: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            exec: exec_from_env(),
+            cpy_to: required("CPY_TO"),
+            cpy_from: required("CPY_FROM"),
+            cpy_to_transform: transform_from_env(),
+            jisyo_path: required("JISYO_PATH"),
+            symbol_jisyo_path: optional("SYMBOL_JISYO_PATH"),
+            palette_path: optional("PALETTE_PATH"),
+            user_jisyo_path: optional("USER_JISYO_PATH"),
+            romaji_table_path: optional("ROMAJI_TABLE_PATH"),
+            romaji_preset: romaji_preset_from_env(),
+            romaji_timeout: optional_usize("ROMAJI_TIMEOUT_MS")
+                .map(|ms| Duration::from_millis(ms as u64)),
+            romaji_timeout_action: timeout_action_from_env(),
+            romaji_failure: failure_policy_from_env(),
+            learn_half_life: optional_usize("LEARN_HALF_LIFE_DAYS")
+                .map(|d| Duration::from_secs(d as u64 * SECS_PER_DAY)),
+            candidate_order: candidate_order_from_env(),
+            jisyo_lookup: jisyo_lookup_from_env(),
+            yomi_variants: yomi_variants_from_env(),
+            katakana_candidate: katakana_candidate_from_env(),
+            auto_send: optional_usize("AUTO_SEND_SEC").map(|s| Duration::from_secs(s as u64)),
+            copy_timeout: Duration::from_secs(
+                optional_usize("CPY_TIMEOUT_SEC").unwrap_or(DEFAULT_CPY_TIMEOUT_SEC) as u64,
+            ),
+            paste_max_bytes: optional_usize("PASTE_MAX_BYTES").unwrap_or(DEFAULT_PASTE_MAX_BYTES),
+            fallback_term_size: fallback_term_size_from_env(),
+            status_segments: status_segments_from_env(),
+            ruler_width: optional_usize("RULER_WIDTH"),
+            // 折り返しの幅：未指定ならルーラーの位置
+            wrap_width: optional_usize("WRAP_WIDTH")
+                .or(optional_usize("RULER_WIDTH"))
+                .unwrap_or(DEFAULT_WRAP_WIDTH),
+            unknown_key_log: optional("UNKNOWN_KEY_LOG"),
+            control_socket: optional("CONTROL_SOCKET"),
+            quit_action: quit_action_from_env(),
+            show_candidate_source: show_candidate_source_from_env(),
+            candidate_preview: optional_usize("CANDIDATE_PREVIEW").unwrap_or(0),
+            event_hook: optional("EVENT_HOOK"),
+            event_log: optional("EVENT_LOG"),
+            print_on_exit: false,
+            single_line: false,
+            masked: false,
+            view_only: false,
+        }
+    }
+}
+
+// 空（空白のみ）も未指定とみなす（空のCPY_TOでは実行するコマンドがない）
+fn required(key: &str) -> String {
+    env::var(key)
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| panic!("{}{}", ENV_ERR, key))
+}
+
+fn optional(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+// シェルはCPY_SHELLで明示した場合のみ使う（既定は直接実行）
+// $SHELLはログインシェル（fishなど-cの文法が違うこともある）なので、送出には使わない
+fn exec_from_env() -> Exec {
+    match env::var("CPY_SHELL") {
+        Ok(sh) if !sh.is_empty() => Exec::Shell(sh),
+        _ => Exec::Direct,
+    }
+}
+
+pub fn candidate_order_from_env() -> CandidateOrder {
+    match env::var("CANDIDATE_ORDER").as_deref() {
+        Err(_) | Ok("") | Ok("dictionary") => CandidateOrder::Dictionary,
+        Ok("length") => CandidateOrder::Length,
+        Ok("frequency") => CandidateOrder::Frequency,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "CANDIDATE_ORDER"),
+    }
+}
+
+pub fn jisyo_lookup_from_env() -> JisyoLookup {
+    match env::var("JISYO_LOOKUP").as_deref() {
+        Err(_) | Ok("") | Ok("merge") => JisyoLookup::Merge,
+        Ok("first") => JisyoLookup::First,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "JISYO_LOOKUP"),
+    }
+}
+
+pub fn yomi_variants_from_env() -> bool {
+    match env::var("YOMI_VARIANTS").as_deref() {
+        Err(_) | Ok("") | Ok("off") => false,
+        Ok("on") => true,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "YOMI_VARIANTS"),
+    }
+}
+
+fn katakana_candidate_from_env() -> KatakanaCandidate {
+    match env::var("KATAKANA_CANDIDATE").as_deref() {
+        Err(_) | Ok("") | Ok("off") => KatakanaCandidate::Off,
+        Ok("fallback") => KatakanaCandidate::Fallback,
+        Ok("always") => KatakanaCandidate::Always,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "KATAKANA_CANDIDATE"),
+    }
+}
+
+// --check-romajiでも使う（他の環境変数なしで読めるよう単独で公開）
+pub fn romaji_preset_from_env() -> RomajiPreset {
+    match env::var("ROMAJI_PRESET").as_deref() {
+        Err(_) | Ok("") | Ok("qwerty") => RomajiPreset::Qwerty,
+        Ok("dvorak") => RomajiPreset::Dvorak,
+        Ok("colemak") => RomajiPreset::Colemak,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "ROMAJI_PRESET"),
+    }
+}
+
+fn timeout_action_from_env() -> TimeoutAction {
+    match env::var("ROMAJI_TIMEOUT_ACTION").as_deref() {
+        Err(_) | Ok("") | Ok("flush") => TimeoutAction::Flush,
+        Ok("discard") => TimeoutAction::Discard,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "ROMAJI_TIMEOUT_ACTION"),
+    }
+}
+
+fn failure_policy_from_env() -> FailurePolicy {
+    match env::var("ROMAJI_FAILURE").as_deref() {
+        Err(_) | Ok("") | Ok("drop") => FailurePolicy::Drop,
+        Ok("insert") => FailurePolicy::Insert,
+        Ok("beep") => FailurePolicy::Beep,
+        Ok("pending") => FailurePolicy::Pending,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "ROMAJI_FAILURE"),
+    }
+}
+
+fn quit_action_from_env() -> QuitAction {
+    match env::var("QUIT_ACTION").as_deref() {
+        Err(_) | Ok("") | Ok("confirm") => QuitAction::Confirm,
+        Ok("send") => QuitAction::Send,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "QUIT_ACTION"),
+    }
+}
+
+fn show_candidate_source_from_env() -> bool {
+    match env::var("CANDIDATE_SOURCE").as_deref() {
+        Err(_) | Ok("") | Ok("hide") => false,
+        Ok("show") => true,
+        Ok(_) => panic!("{}{}", ENV_INVALID, "CANDIDATE_SOURCE"),
+    }
+}
+
+// CPY_TO_TRANSFORM：カンマ区切りで、書いた順に適用する
+fn transform_from_env() -> Vec<Transform> {
+    let Some(val) = optional("CPY_TO_TRANSFORM") else {
+        return Vec::new();
+    };
+    val.split(',')
+        .map(|s| {
+            Transform::from_name(s.trim())
+                .unwrap_or_else(|| panic!("{}{}", ENV_INVALID, "CPY_TO_TRANSFORM"))
+        })
+        .collect()
+}
+
+fn status_segments_from_env() -> Vec<Segment> {
+    let Some(val) = optional("STATUS_SEGMENTS") else {
+        return Vec::new();
+    };
+    val.split(',')
+        .map(|s| match s.trim() {
+            "clock" => Segment::Clock,
+            "battery" => Segment::Battery,
+            _ => panic!("{}{}", ENV_INVALID, "STATUS_SEGMENTS"),
+        })
+        .collect()
+}
+
+// 端末サイズを取得できないときの大きさ：COLUMNS / LINES、なければDEFAULT_TERM_SIZE（<幅>x<高さ>）
+fn fallback_term_size_from_env() -> (usize, usize) {
+    let (w, h) = match optional("DEFAULT_TERM_SIZE") {
+        Some(v) => v
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .filter(|&(w, h)| w > 0 && h > 0)
+            .unwrap_or_else(|| panic!("{}{}", ENV_INVALID, "DEFAULT_TERM_SIZE")),
+        None => DEFAULT_TERM_SIZE,
+    };
+    // シェルが設定する変数なので、読めなければ無視する
+    let shell_var = |key| env::var(key).ok()?.parse().ok().filter(|&n| n > 0);
+    (
+        shell_var("COLUMNS").unwrap_or(w),
+        shell_var("LINES").unwrap_or(h),
+    )
+}
+
+fn optional_usize(key: &str) -> Option<usize> {
+    let val = env::var(key).ok()?;
+    match val.parse::<usize>() {
+        Ok(0) => None, // 0は無効扱い
+        Ok(n) => Some(n),
+        Err(_) => panic!("{}{}", ENV_INVALID, key),
+    }
+}
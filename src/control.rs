@@ -0,0 +1,162 @@
+//! 起動中のunskkを外から操作する制御用ソケット（`CONTROL_SOCKET`）
+//!
+//! UNIXドメインソケットで1行に1つのコマンドを受け取り、`ok`または`error`で始まる1行で応答する。
+//! 本文の改行などは`\n`のようにエスケープしてやりとりする。
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Error, ErrorKind, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::Arc,
+    thread,
+};
+
+#[derive(Clone)]
+pub enum Request {
+    Insert(String), // insert <text>：カーソル位置に挿入する
+    GetBuffer,      // get-buffer：バッファの内容
+    GetMode,        // get-mode：入力モードの名前
+    SetMode(Mode),  // set-mode <kana|katakana|latin>
+}
+
+#[derive(Clone, Copy)]
+pub enum Mode {
+    Kana,
+    Katakana,
+    Latin,
+}
+
+pub type Reply = Result<String, String>;
+
+// 閉じるときにソケットのファイルを消す
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// ソケットを作る（前のunskkが残したファイルは消す。使用中ならエラー）
+pub fn bind(path: &str) -> io::Result<ControlSocket> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("{}: in use by another instance", path),
+        ));
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    // 他のユーザーから操作されないように
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(ControlSocket {
+        path: PathBuf::from(path),
+        listener,
+    })
+}
+
+impl ControlSocket {
+    /// 接続ごとのスレッドでコマンドを読み、handleの結果を応答する
+    pub fn serve<F>(&self, handle: F) -> io::Result<()>
+    where
+        F: Fn(Request) -> Reply + Send + Sync + 'static,
+    {
+        let listener = self.listener.try_clone()?;
+        let handle = Arc::new(handle);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handle = Arc::clone(&handle);
+                thread::spawn(move || serve_connection(stream, &*handle));
+            }
+        });
+        Ok(())
+    }
+}
+
+fn serve_connection(stream: UnixStream, handle: &dyn Fn(Request) -> Reply) {
+    let Ok(mut out) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        let reply = match parse(line) {
+            Ok(req) => handle(req),
+            Err(e) => Err(e),
+        };
+        let reply = format!("{}\n", reply_line(&reply));
+        if out.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// 応答の1行（改行は含まない）
+pub fn reply_line(reply: &Reply) -> String {
+    match reply {
+        Ok(s) if s.is_empty() => String::from("ok"),
+        Ok(s) => format!("ok {}", escape(s)),
+        Err(e) => format!("error {}", escape(e)),
+    }
+}
+
+/// コマンドの1行を読む
+pub fn parse(line: &str) -> Result<Request, String> {
+    let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+    match (cmd, arg) {
+        ("insert", text) => Ok(Request::Insert(unescape(text)?)),
+        ("get-buffer", "") => Ok(Request::GetBuffer),
+        ("get-mode", "") => Ok(Request::GetMode),
+        ("set-mode", "kana") => Ok(Request::SetMode(Mode::Kana)),
+        ("set-mode", "katakana") => Ok(Request::SetMode(Mode::Katakana)),
+        ("set-mode", "latin") => Ok(Request::SetMode(Mode::Latin)),
+        ("set-mode", mode) => Err(format!("unknown mode: {} (kana, katakana or latin)", mode)),
+        _ => Err(format!("unknown command: {}", cmd)),
+    }
+}
+
+// 応答を1行にする（\ と改行、タブ、CR）
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            e => return Err(format!("invalid escape: \\{}", e.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
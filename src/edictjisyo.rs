@@ -0,0 +1,140 @@
+//! EDICT（和英辞書）の読み込み（`format=edict`）
+//!
+//! `林檎 [りんご] /(n) apple/(P)/`のようなEDICT・EDICT2の行を、1語の訳語を見出し語にして
+//! `apple /林檎;apple/`のようなSKK辞書の行に書き直す。
+//! Abbrev（`/apple`）で英単語から和語を引け、訳語は註釈として表示され、確定するときには除かれる。
+
+use std::collections::HashMap;
+
+use crate::jisyo::{encode_candidate, join_annotation};
+
+const GLOSS_MAX: usize = 3; // 註釈に載せる訳語の数（残りは…にする）
+
+type Candidate = (bool, String, String); // よく使う語か、語、註釈を付けた候補
+
+// EDICTの1行（見出しと読み、タグを除いた訳語、よく使う語か、かなで書く語か）
+struct Entry<'a> {
+    word: &'a str,
+    kana: &'a str,
+    glosses: Vec<&'a str>,
+    common: bool,
+    usually_kana: bool,
+}
+
+/// EDICTの辞書をSKK辞書の行（UTF-8）に変換する（EDICTの書式でない行は読み飛ばす）
+///
+/// 同じ訳語の候補は、よく使う語（`(P)`）を先に、辞書の順に並べる。
+pub fn to_skk(text: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(text);
+    let mut index = HashMap::<String, usize>::new();
+    let mut lines = Vec::<(String, Vec<Candidate>)>::new();
+    for entry in text.lines().filter_map(parse_line) {
+        let word = match entry.usually_kana {
+            true => entry.kana,
+            false => entry.word,
+        };
+        let word = match word.contains([';', '/']) {
+            true => encode_candidate(word),
+            false => word.to_string(),
+        };
+        let mut gloss = entry.glosses[..entry.glosses.len().min(GLOSS_MAX)].join("; ");
+        if entry.glosses.len() > GLOSS_MAX {
+            gloss.push('…');
+        }
+        let cand = join_annotation(&word, &gloss);
+        for key in entry.glosses.iter().flat_map(|g| keys_of(g)) {
+            let i = *index.entry(key.to_string()).or_insert_with(|| {
+                lines.push((key.to_string(), Vec::new()));
+                lines.len() - 1
+            });
+            // 同じ語が別の行にもあれば、最初の行の訳語を残す
+            let candidates = &mut lines[i].1;
+            if !candidates.iter().any(|(_, w, _)| *w == word) {
+                candidates.push((entry.common, word.clone(), cand.clone()));
+            }
+        }
+    }
+    let mut out = String::new();
+    for (key, mut candidates) in lines {
+        candidates.sort_by_key(|(common, _, _)| !common);
+        out.push_str(&key);
+        out.push_str(" /");
+        for (_, _, c) in candidates {
+            out.push_str(&c);
+            out.push('/');
+        }
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+// 見出し [読み] /訳語/.../ （EDICT2は 見出し1;見出し2(P) [読み1;読み2] /(n) (1) 訳語/.../EntL.../）
+fn parse_line(line: &str) -> Option<Entry<'_>> {
+    let (head, rest) = line.split_once(" /")?;
+    // 先頭行は辞書の説明（　？？？ /EDICT, .../）
+    if head.starts_with('\u{3000}') {
+        return None;
+    }
+    let (words, kanas) = match head.split_once(" [") {
+        Some((words, kanas)) => (words, kanas.strip_suffix(']')?),
+        None => (head, head),
+    };
+    let (word, kana) = (first_of(words), first_of(kanas));
+    if word.is_empty() || kana.is_empty() {
+        return None;
+    }
+    let mut entry = Entry {
+        word,
+        kana,
+        glosses: Vec::new(),
+        common: head.contains("(P)"),
+        usually_kana: false,
+    };
+    for field in rest.trim_end_matches('/').split('/') {
+        if field.starts_with("EntL") || field == "(P)" {
+            entry.common |= field == "(P)";
+            continue;
+        }
+        let (tags, gloss) = split_tags(field);
+        entry.usually_kana |= tags.iter().any(|t| t.split(',').any(|t| t == "uk"));
+        if !gloss.is_empty() {
+            entry.glosses.push(gloss);
+        }
+    }
+    (!entry.glosses.is_empty()).then_some(entry)
+}
+
+// 見出し1;見出し2(P) -> 見出し1
+fn first_of(s: &str) -> &str {
+    let s = s.split(';').next().unwrap_or(s);
+    s.split('(').next().unwrap_or(s).trim()
+}
+
+// 訳語の前の (n) (1) {comp} などのタグと、残りの訳語
+fn split_tags(field: &str) -> (Vec<&str>, &str) {
+    let mut tags = Vec::new();
+    let mut rest = field.trim();
+    while let Some(close) = match rest.chars().next() {
+        Some('(') => rest.find(')'),
+        Some('{') => rest.find('}'),
+        _ => None,
+    } {
+        tags.push(&rest[1..close]);
+        rest = rest[close + 1..].trim_start();
+    }
+    (tags, rest.trim_end())
+}
+
+// 訳語のうち、見出し語にできる1語のもの（to return → return、apple (fruit) → apple）
+fn keys_of(gloss: &str) -> impl Iterator<Item = &str> {
+    gloss.split(';').filter_map(|g| {
+        let g = g.trim();
+        let g = g.strip_prefix("to ").unwrap_or(g);
+        let g = g.split(" (").next().unwrap_or(g).trim();
+        let is_word = !g.is_empty()
+            && g.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && g.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '\'' || c == '.');
+        is_word.then_some(g)
+    })
+}
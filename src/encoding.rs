@@ -0,0 +1,132 @@
+//! 辞書の文字コード変換
+//!
+//! 配布されているSKK辞書の多くはEUC-JPなので、読み込み時にUTF-8にする。
+//! skkservのクライアントにもEUC-JPで話すものが多いので、その応答にも使う。
+//! 変換はlibcのiconvに任せる（JIS X 0213の文字を含む辞書もあるので、使えればその上位集合で読む）。
+
+use std::{
+    ffi::CStr,
+    io::{self, Error, ErrorKind},
+    ptr,
+};
+
+const UTF_8: &CStr = c"UTF-8";
+const EUC_JP: &[&CStr] = &[c"EUC-JISX0213", c"EUC-JP"];
+const CHUNK: usize = 64 * 1024;
+
+/// EUC-JPのバイト列をUTF-8の文字列にする
+///
+/// 変換できないバイトがあれば、その位置を含むエラーを返す。
+pub fn euc_jp_to_utf8(input: &[u8]) -> io::Result<String> {
+    let out = convert(input, false)?;
+    String::from_utf8(out).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// UTF-8の文字列をEUC-JPのバイト列にする（skkservの応答用）
+///
+/// EUC-JPにない文字（絵文字など）があればエラーを返す。
+pub fn utf8_to_euc_jp(input: &str) -> io::Result<Vec<u8>> {
+    convert(input.as_bytes(), true)
+}
+
+fn convert(input: &[u8], to_euc: bool) -> io::Result<Vec<u8>> {
+    let cd = EUC_JP
+        .iter()
+        .map(|&euc| if to_euc { (euc, UTF_8) } else { (UTF_8, euc) })
+        // SAFETY: どちらも'\0'で終わる文字列
+        .map(|(to, from)| unsafe { libc::iconv_open(to.as_ptr(), from.as_ptr()) })
+        .find(|&cd| cd as isize != -1)
+        .ok_or_else(|| Error::new(ErrorKind::Unsupported, "iconv does not support EUC-JP"))?;
+
+    let mut out = Vec::with_capacity(input.len() * 3 / 2);
+    let mut chunk = vec![0u8; CHUNK];
+    let mut in_ptr = input.as_ptr() as *mut libc::c_char;
+    let mut in_left = input.len();
+    let result = loop {
+        let mut out_ptr = chunk.as_mut_ptr() as *mut libc::c_char;
+        let mut out_left = chunk.len();
+        // SAFETY: 各ポインタは残りの長さとともに input / chunk の範囲を指す
+        let r = unsafe { libc::iconv(cd, &mut in_ptr, &mut in_left, &mut out_ptr, &mut out_left) };
+        let err = Error::last_os_error();
+        out.extend_from_slice(&chunk[..chunk.len() - out_left]);
+        if r != usize::MAX {
+            break Ok(());
+        }
+        // 出力が溢れただけなら続ける
+        if err.raw_os_error() != Some(libc::E2BIG) {
+            let at = input.len() - in_left;
+            break Err(Error::new(
+                ErrorKind::InvalidData,
+                if to_euc {
+                    format!("not representable in EUC-JP at byte {}", at)
+                } else {
+                    format!("not EUC-JP at byte {}", at)
+                },
+            ));
+        }
+    };
+    // EUC-JISX0213は、後に結合する文字が来るかもしれない文字（か、カ、ㇷ など）を保留するので書き出させる
+    let result = result.and_then(|_| {
+        let mut out_ptr = chunk.as_mut_ptr() as *mut libc::c_char;
+        let mut out_left = chunk.len();
+        // SAFETY: 入力をNULLにすると保留した状態だけを chunk に書き出す
+        let r = unsafe {
+            libc::iconv(
+                cd,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut out_ptr,
+                &mut out_left,
+            )
+        };
+        let err = Error::last_os_error();
+        out.extend_from_slice(&chunk[..chunk.len() - out_left]);
+        if r == usize::MAX { Err(err) } else { Ok(()) }
+    });
+    // SAFETY: iconv_openで得た記述子を1度だけ閉じる
+    unsafe { libc::iconv_close(cd) };
+    result.map(|_| out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ばか（か は結合する文字を待って保留される）
+    const BAKA: &[u8] = b"\xa4\xd0\xa4\xab";
+
+    #[test]
+    fn flushes_a_trailing_kana_that_could_combine() {
+        assert_eq!(utf8_to_euc_jp("ばか").unwrap(), BAKA);
+        assert_eq!(utf8_to_euc_jp("カ").unwrap(), b"\xa5\xab");
+        assert_eq!(euc_jp_to_utf8(BAKA).unwrap(), "ばか");
+    }
+
+    #[test]
+    fn converts_known_vectors() {
+        assert_eq!(euc_jp_to_utf8(b"\xb2\xbe\xcc\xbe").unwrap(), "仮名");
+        assert_eq!(
+            utf8_to_euc_jp("仮名 /ａ/").unwrap(),
+            b"\xb2\xbe\xcc\xbe /\xa3\xe1/"
+        );
+        assert_eq!(euc_jp_to_utf8(b"ascii ;x\n").unwrap(), "ascii ;x\n");
+    }
+
+    #[test]
+    fn reports_the_byte_that_cannot_be_converted() {
+        let e = euc_jp_to_utf8(b"\xa4\xa2\xff\xfe").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "not EUC-JP at byte 2");
+        let e = utf8_to_euc_jp("あ😀").unwrap_err();
+        assert_eq!(e.to_string(), "not representable in EUC-JP at byte 3");
+    }
+
+    #[test]
+    fn converts_more_than_a_chunk() {
+        // 出力がCHUNKを超えるので、iconvを何度か呼ぶ
+        let text = "仮名か".repeat(CHUNK / 4);
+        let euc = utf8_to_euc_jp(&text).unwrap();
+        assert_eq!(euc.len(), text.len() / 3 * 2);
+        assert_eq!(euc_jp_to_utf8(&euc).unwrap(), text);
+    }
+}
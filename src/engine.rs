@@ -1,10 +1,19 @@
 use crate::{
     buffer::Buffer,
-    jisyo::Jisyo,
+    builtin,
+    hook::EngineEvent,
+    jisyo::{
+        Jisyo, PaletteCategory, decode_candidate, encode_candidate, join_annotation,
+        split_annotation,
+    },
+    kana::{
+        convert_to_halfwidth_katakana, convert_to_hankaku_ascii, convert_to_katakana,
+        convert_to_lookup_yomi, convert_to_zenkaku_ascii,
+    },
     key::{KeyEvent, Move},
-    romaji::{KanaMatch, search_lookup_table},
-    state::{InputState, KanaState},
-    tables::HIRAGANA_TO_HALFWIDTH_KATAKANA,
+    numeric,
+    romaji::{FailurePolicy, KanaMatch, TimeoutAction, failure_policy, search_lookup_table},
+    state::{InputState, KanaState, SubModes},
 };
 
 type IsOperationDone = bool;
@@ -12,13 +21,15 @@ type IsOperationDone = bool;
 pub fn handle_key(
     state: InputState,
     buffer: &mut Buffer,
-    jisyo: &Jisyo,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
     key: KeyEvent,
 ) -> InputState {
-    if handle_key_cursor(buffer, key) {
+    // 辞書登録中のカーソル移動は、登録する語の中で行う
+    if !matches!(state, InputState::Register { .. }) && handle_key_cursor(buffer, key) {
         state
     } else {
-        handle_key_state(state, buffer, jisyo, key)
+        handle_key_state(state, buffer, jisyo, sub, key)
     }
 }
 
@@ -34,6 +45,8 @@ fn handle_key_cursor(buffer: &mut Buffer, key: KeyEvent) -> IsOperationDone {
         KeyEvent::Navigation(Move::LineTail) => buffer.to_line_tail(),
         KeyEvent::Navigation(Move::SelectLeft) => buffer.select_left(),
         KeyEvent::Navigation(Move::SelectRight) => buffer.select_right(),
+        KeyEvent::Navigation(Move::WordLeft) => _ = buffer.word_left(),
+        KeyEvent::Navigation(Move::WordRight) => _ = buffer.word_right(),
         KeyEvent::Delete => buffer.delete(),
         _ => {
             return false;
@@ -45,42 +58,83 @@ fn handle_key_cursor(buffer: &mut Buffer, key: KeyEvent) -> IsOperationDone {
 fn handle_key_state(
     state: InputState,
     buffer: &mut Buffer,
-    jisyo: &Jisyo,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
     key: KeyEvent,
 ) -> InputState {
     match state {
-        InputState::Kana { romaji, state } => handle_kana(romaji, state, buffer, jisyo, key),
+        InputState::Kana { romaji, state } => handle_kana(romaji, state, buffer, jisyo, sub, key),
         InputState::Converting {
             yomi: y,
             candidates: c,
+            sources: s,
+            keys: ks,
+            selected_index: i,
+            katakana: k,
+        } => handle_converting(y, c, s, ks, i, k, buffer, jisyo, sub, key),
+        InputState::Latin => handle_latin(buffer, sub, key),
+        InputState::Abbrev(s) => handle_abbrev(s, buffer, jisyo, sub, key),
+        InputState::Quoted(state) => handle_quoted(state, buffer, key),
+        InputState::EntryEdit {
+            yomi: y,
+            candidates: c,
+            selected_index: i,
+            katakana: k,
+            annotation: a,
+        } => handle_entry_edit(y, c, i, k, a, jisyo, key),
+        InputState::Palette {
+            categories: c,
+            category: g,
+            selected_index: i,
+            katakana: k,
+        } => handle_palette(c, g, i, k, buffer, key),
+        InputState::Learned {
+            entries: e,
             selected_index: i,
-        } => handle_converting(y, c, i, buffer, jisyo, key),
-        InputState::Latin(zenkaku) => handle_latin(zenkaku, buffer, key),
-        InputState::Abbrev(s) => handle_abbrev(s, buffer, jisyo, key),
+            katakana: k,
+        } => handle_learned(e, i, k, jisyo, key),
+        InputState::Register {
+            yomi: y,
+            katakana: k,
+            word: w,
+            inner: i,
+        } => handle_register(y, k, w, *i, buffer, jisyo, sub, key),
+        InputState::Completing {
+            yomi: y,
+            completions: c,
+            selected_index: i,
+            katakana: k,
+        } => handle_completing(y, c, i, k, buffer, jisyo, sub, key),
     }
 }
 
 // -------------------- Latin --------------------
 
-fn handle_latin(mut is_zenkaku: bool, buffer: &mut Buffer, key: KeyEvent) -> InputState {
+fn handle_latin(buffer: &mut Buffer, sub: &mut SubModes, key: KeyEvent) -> InputState {
     use KeyEvent::*;
     match key {
-        Char(c) => buffer.insert_char(if is_zenkaku {
+        Char(c) => buffer.insert_char(if sub.zenkaku_latin {
             convert_to_zenkaku_ascii(c)
         } else {
             c
         }),
-        ToggleHankakuZenkaku => is_zenkaku = !is_zenkaku,
+        ToggleHankakuZenkaku => sub.zenkaku_latin = !sub.zenkaku_latin,
         Backspace => buffer.backspace(),
         ToggleLatin => return InputState::new_kana(),
         _ => (),
     }
-    InputState::Latin(is_zenkaku)
+    InputState::Latin
 }
 
 // -------------------- Abbrev --------------------
 
-fn handle_abbrev(mut s: String, buffer: &mut Buffer, jisyo: &Jisyo, key: KeyEvent) -> InputState {
+fn handle_abbrev(
+    mut s: String,
+    buffer: &mut Buffer,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
+    key: KeyEvent,
+) -> InputState {
     use KeyEvent::*;
     match key {
         Char(c) => s.push(c),
@@ -95,8 +149,9 @@ fn handle_abbrev(mut s: String, buffer: &mut Buffer, jisyo: &Jisyo, key: KeyEven
             buffer.insert_str(&s);
             return InputState::new_kana();
         }
-        StartConversion => {
-            if let Some(c) = InputState::new_converting(&s, jisyo) {
+        // 前後の空白は見出し語にならないので除いて引く
+        StartConversion if !s.trim().is_empty() => {
+            if let Some(c) = convert_or_register(s.trim(), jisyo, sub, false) {
                 return c;
             }
         }
@@ -105,96 +160,202 @@ fn handle_abbrev(mut s: String, buffer: &mut Buffer, jisyo: &Jisyo, key: KeyEven
     InputState::Abbrev(s)
 }
 
+// -------------------- Quoted --------------------
+
+fn handle_quoted(state: KanaState, buffer: &mut Buffer, key: KeyEvent) -> InputState {
+    let state = match (key, state) {
+        (KeyEvent::Char(c), KanaState::ToBeConverted(mut yomi, katakana)) => {
+            yomi.push(c);
+            KanaState::ToBeConverted(yomi, katakana)
+        }
+        (KeyEvent::Char(c), other) => {
+            buffer.insert_char(c);
+            other
+        }
+        (_, other) => other, // その他のキーは引用の取り消し
+    };
+    InputState::Kana {
+        romaji: String::new(),
+        state,
+    }
+}
+
+// -------------------- 読みの補完 --------------------
+
+// Tabで次、Shift+Tabで前の見出し語へ（端では反対の端へ）
+// その他のキーは選んでいる見出し語を読みにしてから処理する
+#[allow(clippy::too_many_arguments)]
+fn handle_completing(
+    yomi: String,
+    completions: Vec<String>,
+    mut selected_index: usize,
+    katakana: bool,
+    buffer: &mut Buffer,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
+    key: KeyEvent,
+) -> InputState {
+    use KeyEvent::*;
+    let n = completions.len();
+    let yomi = match key {
+        Complete => {
+            selected_index = (selected_index + 1) % n;
+            return InputState::Completing {
+                yomi,
+                completions,
+                selected_index,
+                katakana,
+            };
+        }
+        CompletePrev => {
+            selected_index = (selected_index + n - 1) % n;
+            return InputState::Completing {
+                yomi,
+                completions,
+                selected_index,
+                katakana,
+            };
+        }
+        CancelConversion => {
+            return InputState::Kana {
+                romaji: String::new(),
+                state: KanaState::ToBeConverted(yomi, katakana),
+            };
+        }
+        _ => completions.into_iter().nth(selected_index).unwrap_or(yomi),
+    };
+    let state = InputState::Kana {
+        romaji: String::new(),
+        state: KanaState::ToBeConverted(yomi, katakana),
+    };
+    handle_key(state, buffer, jisyo, sub, key)
+}
+
 // -------------------- Kana --------------------
 
 fn handle_kana(
     mut romaji: String,
     mut state: KanaState,
     buffer: &mut Buffer,
-    jisyo: &Jisyo,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
     key: KeyEvent,
 ) -> InputState {
     use KanaState::*;
     use KeyEvent::*;
 
     match key {
-        ToggleLatin => return InputState::new_latin(),
+        ToggleLatin => return InputState::Latin,
         StartAbbrev => return InputState::new_abbrev(),
-        ToggleHankakuZenkaku => {
-            state = match state {
-                Katakana(hankaku) => Katakana(!hankaku),
-                Hiragana(zenkaku) => Hiragana(!zenkaku),
-                other => other,
+        QuoteNext => return InputState::Quoted(state),
+        StartPalette => {
+            if !matches!(state, ToBeConverted(..))
+                && let Some(p) = InputState::new_palette(jisyo, state.is_katakana())
+            {
+                return p;
             }
         }
+        StartLearned => {
+            if !matches!(state, ToBeConverted(..))
+                && let Some(l) = InputState::new_learned(jisyo, state.is_katakana())
+            {
+                return l;
+            }
+        }
+        ToggleHankakuZenkaku => match state {
+            Katakana => sub.hankaku_katakana = !sub.hankaku_katakana,
+            Hiragana => sub.zenkaku_symbol = !sub.zenkaku_symbol,
+            ToBeConverted(..) => (),
+        },
         ToggleKatakana => {
-            state = if let ToBeConverted(ref y) = state {
-                buffer.insert_str(&convert_to_katakana(&delete_setsuji(y)));
-                return InputState::new_kana();
+            state = if let ToBeConverted(ref y, katakana) = state {
+                // 読みを反対側の仮名で確定し、元の入力に戻る
+                let y = delete_setsuji(y);
+                buffer.insert_str(&if katakana { y } else { convert_to_katakana(&y) });
+                return InputState::Kana {
+                    romaji: String::new(),
+                    state: KanaState::new(katakana),
+                };
             } else {
                 match state {
-                    Hiragana(_) => KanaState::new_katakana(),
-                    Katakana(_) => KanaState::new_hiragana(),
+                    Hiragana => Katakana,
+                    Katakana => Hiragana,
                     other => other,
                 }
             }
         }
         StartConversion => {
-            if let ToBeConverted(ref y) = state
+            if let ToBeConverted(ref y, katakana) = state
                 && y != ">"
-                && let Some(c) = InputState::new_converting(y, jisyo)
+                && let Some(c) = convert_or_register(y, jisyo, sub, katakana)
             {
                 return c;
             }
         }
+        Complete | CompletePrev if romaji.is_empty() => {
+            if let ToBeConverted(ref y, katakana) = state
+                && !y.is_empty()
+            {
+                match InputState::new_completing(y, jisyo, katakana) {
+                    Some(c) => return c,
+                    None => buffer.ring_bell(),
+                }
+            }
+        }
         Backspace => {
             if !romaji.is_empty() {
                 romaji.pop();
-            } else if let ToBeConverted(yomi) = &mut state {
+            } else if let ToBeConverted(yomi, katakana) = &mut state {
                 if !yomi.is_empty() {
                     yomi.pop();
                 } else {
-                    state = KanaState::new_hiragana();
+                    state = KanaState::new(*katakana);
                 }
             } else {
                 buffer.backspace();
             }
         }
         CommitUnconverted => {
-            if let ToBeConverted(ref mut y) = state {
-                buffer.insert_str(&delete_setsuji(y));
-                return InputState::new_kana();
+            if let ToBeConverted(ref y, katakana) = state {
+                let y = delete_setsuji(y);
+                buffer.insert_str(&if katakana { convert_to_katakana(&y) } else { y });
+                return InputState::Kana {
+                    romaji: String::new(),
+                    state: KanaState::new(katakana),
+                };
             }
         }
         Setsuji if romaji.is_empty() => {
-            if let ToBeConverted(ref mut y) = state // 接頭辞
+            if let ToBeConverted(ref mut y, katakana) = state // 接頭辞
                 && !y.is_empty()
             {
                 y.push('>');
-                if let Some(c) = InputState::new_converting(y, jisyo) {
+                if let Some(c) = convert_or_register(y, jisyo, sub, katakana) {
                     return c;
                 }
             } else {
                 // 接尾辞
-                state = ToBeConverted(String::from(">"))
+                state = ToBeConverted(String::from(">"), state.is_katakana())
             }
         }
         StartYomiOrOkuri(c) if romaji.is_empty() => {
-            if let ToBeConverted(ref mut y) = state
+            if let ToBeConverted(ref mut y, katakana) = state
                 && !y.is_empty()
             {
                 y.push(c);
-                if let Some(conv) = InputState::new_converting(y, jisyo) {
+                if let Some(conv) = convert_or_register(y, jisyo, sub, katakana) {
                     return conv;
                 } else {
                     y.pop();
                 }
             } else {
+                let katakana = state.is_katakana();
                 return handle_kana(
                     String::new(),
-                    ToBeConverted(String::new()),
+                    ToBeConverted(String::new(), katakana),
                     buffer,
                     jisyo,
+                    sub,
                     Char(c),
                 );
             }
@@ -203,17 +364,48 @@ fn handle_kana(
             romaji.push(c);
             match search_lookup_table(&romaji) {
                 KanaMatch::Success(kana) => {
-                    commit_kana(buffer, &mut state, kana.commit);
+                    commit_kana(buffer, &mut state, sub, &kana.commit);
                     romaji.clear();
-                    romaji.push_str(kana.pushback);
+                    romaji.push_str(&kana.pushback);
                 }
                 KanaMatch::Failure => {
                     romaji.pop();
-                    if let ToBeConverted(_) = state {
+                    // 読みの中の数字は数値変換に使う
+                    // 読みの先頭なら、読みを始めるために打ったローマ字（Q1kaiのq）は捨てる
+                    if c.is_ascii_digit()
+                        && let ToBeConverted(y, _) = &state
+                        && (romaji.is_empty() || y.is_empty())
+                    {
+                        romaji.clear();
+                        commit_kana(buffer, &mut state, sub, c.encode_utf8(&mut [0; 4]));
                         break 'char;
                     }
-                    if (c.is_ascii_punctuation() || c.is_ascii_digit()) && romaji.is_empty() {
-                        buffer.insert_char(if let Hiragana(true) = state {
+                    let symbol = c.is_ascii_punctuation() || c.is_ascii_digit();
+                    // 記号・数字だけは、途中のローマ字がなければかなモードでそのまま入力される
+                    let dropped =
+                        matches!(state, ToBeConverted(..)) || !(symbol && romaji.is_empty());
+                    match failure_policy() {
+                        _ if !dropped => (),
+                        FailurePolicy::Insert if !romaji.is_empty() => {
+                            commit_kana(buffer, &mut state, sub, &romaji);
+                            return handle_kana(String::new(), state, buffer, jisyo, sub, key);
+                        }
+                        FailurePolicy::Insert => {
+                            commit_kana(buffer, &mut state, sub, c.encode_utf8(&mut [0; 4]));
+                            break 'char;
+                        }
+                        FailurePolicy::Beep => buffer.ring_bell(),
+                        FailurePolicy::Pending => {
+                            romaji.push(c);
+                            break 'char;
+                        }
+                        FailurePolicy::Drop => (),
+                    }
+                    if let ToBeConverted(..) = state {
+                        break 'char;
+                    }
+                    if symbol && romaji.is_empty() {
+                        buffer.insert_char(if matches!(state, Hiragana) && sub.zenkaku_symbol {
                             convert_to_zenkaku_ascii(c)
                         } else {
                             c
@@ -229,102 +421,517 @@ fn handle_kana(
     InputState::Kana { romaji, state }
 }
 
+// 途中まで打ったローマ字を、一定時間入力がなかったときに片付ける
+pub fn flush_romaji(
+    state: InputState,
+    buffer: &mut Buffer,
+    sub: &SubModes,
+    action: TimeoutAction,
+) -> InputState {
+    let (romaji, mut state) = match state {
+        InputState::Kana { romaji, state } => (romaji, state),
+        InputState::Register {
+            yomi,
+            katakana,
+            mut word,
+            inner,
+        } => {
+            let inner = flush_romaji(*inner, &mut word, sub, action);
+            return InputState::Register {
+                yomi,
+                katakana,
+                word,
+                inner: Box::new(inner),
+            };
+        }
+        other => return other,
+    };
+    if let TimeoutAction::Flush = action
+        && !romaji.is_empty()
+    {
+        let kana = if romaji == "n" { "ん" } else { &romaji };
+        commit_kana(buffer, &mut state, sub, kana);
+    }
+    InputState::Kana {
+        romaji: String::new(),
+        state,
+    }
+}
+
 // -------------------- Converting --------------------
 
+#[allow(clippy::too_many_arguments)]
 fn handle_converting(
-    mut yomi: String,
+    yomi: String,
     candidates: Vec<String>,
+    sources: Vec<String>,
+    keys: Vec<String>,
     mut selected_index: usize,
+    katakana: bool,
     buffer: &mut Buffer,
-    jisyo: &Jisyo,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
     key: KeyEvent,
 ) -> InputState {
     use KeyEvent::*;
+    if key == StartEntryEdit && jisyo.has_user() {
+        return InputState::EntryEdit {
+            yomi,
+            candidates,
+            selected_index,
+            katakana,
+            annotation: None,
+        };
+    }
+    // 辞書になかった読み（候補は読みのカタカナだけ）は、次の候補で辞書登録に入る
+    if key == NextCandidate
+        && jisyo.has_user()
+        && sources == [builtin::TAG]
+        && builtin::katakana(&convert_to_lookup_yomi(&yomi)).as_ref() == candidates.first()
+    {
+        return InputState::new_register(&yomi, katakana);
+    }
+    if key == PurgeCandidate && jisyo.has_user() {
+        jisyo.purge(&keys[selected_index], &candidates[selected_index]);
+        return reconvert(yomi, katakana, jisyo, |c| selected_index.min(c.len() - 1));
+    }
+    // 固定した候補は先頭に移るので、固定をやめたときも同じ候補を選んだままにする
+    if key == PinCandidate && jisyo.has_user() {
+        let cand = candidates[selected_index].clone();
+        jisyo.toggle_pin(&keys[selected_index], &cand);
+        return reconvert(yomi, katakana, jisyo, |c| {
+            c.iter().position(|c| *c == cand).unwrap_or(0)
+        });
+    }
+    // 確定に続けて打った文字（送り仮名を決める）
+    let next = match key {
+        CommitCandidateWithChar(c) => Some(c),
+        _ => None,
+    };
     let mut commit_candidate_with_context = |kana_state: KanaState| {
         commit_candidate(
             &yomi,
             &candidates,
             selected_index,
             kana_state,
+            next,
             buffer,
             jisyo,
+            sub,
         )
     };
     match key {
         NextCandidate => selected_index = (selected_index + 1).min(candidates.len() - 1),
         PrevCandidate => selected_index = selected_index.saturating_sub(1),
-        CancelConversion => {
-            if yomi.is_ascii() {
-                return InputState::Abbrev(yomi);
-            }
-            if matches!(yomi.as_bytes().last(), Some(c) if c.is_ascii_lowercase()) {
-                yomi.pop();
-            }
-            return InputState::Kana {
-                romaji: String::new(),
-                state: KanaState::ToBeConverted(yomi),
-            };
-        }
-        CommitCandidate => return commit_candidate_with_context(KanaState::new_hiragana()),
-        ToggleKatakana => return commit_candidate_with_context(KanaState::new_katakana()),
+        CancelConversion => return back_to_yomi(yomi, katakana),
+        CommitCandidate => return commit_candidate_with_context(KanaState::new(katakana)),
+        ToggleKatakana => return commit_candidate_with_context(KanaState::new(!katakana)),
         StartAbbrev => {
-            let next_state = commit_candidate_with_context(KanaState::new_hiragana());
-            return handle_key(next_state, buffer, jisyo, StartAbbrev);
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, StartAbbrev);
+        }
+        // ユーザー辞書がなければ、これまでどおり確定して x から読みを始める
+        PurgeCandidate => {
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, StartYomiOrOkuri('x'));
         }
         CommitCandidateWithStartYomi(next) => {
-            let next_state = commit_candidate_with_context(KanaState::new_hiragana());
-            return handle_key(next_state, buffer, jisyo, StartYomiOrOkuri(next));
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, StartYomiOrOkuri(next));
         }
         CommitCandidateWithSetsubiji => {
-            let next_state = commit_candidate_with_context(KanaState::new_hiragana());
-            return handle_key(next_state, buffer, jisyo, Setsuji);
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, Setsuji);
         }
         CommitCandidateWithChar(next) => {
-            let next_state = commit_candidate_with_context(KanaState::new_hiragana());
-            return handle_key(next_state, buffer, jisyo, Char(next));
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, Char(next));
         }
         Backspace => {
-            let next_state = commit_candidate_with_context(KanaState::new_hiragana());
-            return handle_key(next_state, buffer, jisyo, Backspace);
+            let next_state = commit_candidate_with_context(KanaState::new(katakana));
+            return handle_key(next_state, buffer, jisyo, sub, Backspace);
         }
         _ => (),
     }
     InputState::Converting {
         yomi,
         candidates,
+        sources,
+        keys,
+        selected_index,
+        katakana,
+    }
+}
+
+// ユーザー辞書を変えた後に引き直す（候補がなくなれば読みに戻る）
+fn reconvert(
+    yomi: String,
+    katakana: bool,
+    jisyo: &Jisyo,
+    select: impl FnOnce(&[String]) -> usize,
+) -> InputState {
+    match InputState::new_converting(&yomi, jisyo, katakana) {
+        Some(InputState::Converting {
+            candidates,
+            sources,
+            keys,
+            ..
+        }) => InputState::Converting {
+            yomi,
+            selected_index: select(&candidates),
+            candidates,
+            sources,
+            keys,
+            katakana,
+        },
+        _ => back_to_yomi(yomi, katakana),
+    }
+}
+
+// -------------------- Entry edit --------------------
+
+fn handle_entry_edit(
+    yomi: String,
+    mut candidates: Vec<String>,
+    mut selected_index: usize,
+    katakana: bool,
+    annotation: Option<String>,
+    jisyo: &mut Jisyo,
+    key: KeyEvent,
+) -> InputState {
+    use KeyEvent::*;
+    if let Some(mut a) = annotation {
+        // 註釈の入力中：確定で候補に反映、取り消しで破棄
+        match key {
+            Char(c) => a.push(c),
+            Backspace => _ = a.pop(),
+            CommitCandidate => {
+                let cand = split_annotation(&candidates[selected_index]).0.to_string();
+                candidates[selected_index] = if a.is_empty() {
+                    cand
+                } else {
+                    join_annotation(&cand, &a)
+                };
+                return InputState::EntryEdit {
+                    yomi,
+                    candidates,
+                    selected_index,
+                    katakana,
+                    annotation: None,
+                };
+            }
+            CancelConversion => {
+                return InputState::EntryEdit {
+                    yomi,
+                    candidates,
+                    selected_index,
+                    katakana,
+                    annotation: None,
+                };
+            }
+            _ => (),
+        }
+        return InputState::EntryEdit {
+            yomi,
+            candidates,
+            selected_index,
+            katakana,
+            annotation: Some(a),
+        };
+    }
+    let last = candidates.len().saturating_sub(1);
+    match key {
+        NextCandidate => selected_index = (selected_index + 1).min(last),
+        PrevCandidate => selected_index = selected_index.saturating_sub(1),
+        MoveCandidateUp if selected_index > 0 => {
+            candidates.swap(selected_index, selected_index - 1);
+            selected_index -= 1;
+        }
+        MoveCandidateDown if selected_index < last => {
+            candidates.swap(selected_index, selected_index + 1);
+            selected_index += 1;
+        }
+        DeleteCandidate if !candidates.is_empty() => {
+            candidates.remove(selected_index);
+            selected_index = selected_index.min(candidates.len().saturating_sub(1));
+        }
+        EditAnnotation if !candidates.is_empty() => {
+            let current = split_annotation(&candidates[selected_index]).1;
+            let annotation = current
+                .map(decode_candidate)
+                .unwrap_or_default()
+                .to_string();
+            return InputState::EntryEdit {
+                yomi,
+                candidates,
+                selected_index,
+                katakana,
+                annotation: Some(annotation),
+            };
+        }
+        CommitCandidate => {
+            // 他の辞書から来た註釈は書き戻さず、編集したものだけを記録する
+            let key = convert_to_lookup_yomi(&yomi);
+            let original = jisyo.lookup(&key).unwrap_or_default();
+            let candidates = candidates
+                .into_iter()
+                .map(|c| match original.contains(&c) {
+                    true => split_annotation(&c).0.to_string(),
+                    false => c,
+                })
+                .collect();
+            jisyo.set_user_entry(&key, candidates);
+            return InputState::new_converting(&yomi, jisyo, katakana)
+                .unwrap_or_else(|| back_to_yomi(yomi, katakana));
+        }
+        CancelConversion => {
+            return InputState::new_converting(&yomi, jisyo, katakana)
+                .unwrap_or_else(|| back_to_yomi(yomi, katakana));
+        }
+        _ => (),
+    }
+    InputState::EntryEdit {
+        yomi,
+        candidates,
+        selected_index,
+        katakana,
+        annotation: None,
+    }
+}
+
+// -------------------- Palette --------------------
+
+fn handle_palette(
+    categories: Vec<PaletteCategory>,
+    mut category: usize,
+    mut selected_index: usize,
+    katakana: bool,
+    buffer: &mut Buffer,
+    key: KeyEvent,
+) -> InputState {
+    use KeyEvent::*;
+    let back = InputState::Kana {
+        romaji: String::new(),
+        state: KanaState::new(katakana),
+    };
+    match key {
+        NextCandidate => {
+            selected_index = (selected_index + 1).min(categories[category].1.len() - 1)
+        }
+        PrevCandidate => selected_index = selected_index.saturating_sub(1),
+        NextCategory => {
+            category = (category + 1) % categories.len();
+            selected_index = 0;
+        }
+        PrevCategory => {
+            category = (category + categories.len() - 1) % categories.len();
+            selected_index = 0;
+        }
+        CommitCandidate => {
+            let (symbol, _) = InputState::candidate(&categories[category].1, selected_index);
+            buffer.insert_str(&symbol);
+            return back;
+        }
+        CancelConversion | Backspace => return back,
+        _ => (),
+    }
+    InputState::Palette {
+        categories,
+        category,
         selected_index,
+        katakana,
+    }
+}
+
+// -------------------- Learned --------------------
+
+// 学習した候補を順に見て、Dで忘れる（Alt+Xで戻せる）
+fn handle_learned(
+    mut entries: Vec<(String, String)>,
+    mut selected_index: usize,
+    katakana: bool,
+    jisyo: &mut Jisyo,
+    key: KeyEvent,
+) -> InputState {
+    use KeyEvent::*;
+    let back = InputState::Kana {
+        romaji: String::new(),
+        state: KanaState::new(katakana),
+    };
+    match key {
+        NextCandidate => selected_index = (selected_index + 1).min(entries.len() - 1),
+        PrevCandidate => selected_index = selected_index.saturating_sub(1),
+        DeleteCandidate => {
+            let (yomi, cand) = entries.remove(selected_index);
+            jisyo.forget(&yomi, &cand);
+            if entries.is_empty() {
+                return back;
+            }
+            selected_index = selected_index.min(entries.len() - 1);
+        }
+        CommitCandidate | CancelConversion | Backspace => return back,
+        _ => (),
+    }
+    InputState::Learned {
+        entries,
+        selected_index,
+        katakana,
+    }
+}
+
+// -------------------- Register --------------------
+
+// 登録する語は入れ子の状態で入力するので、その中でさらに変換（や登録）ができる
+#[allow(clippy::too_many_arguments)]
+fn handle_register(
+    yomi: String,
+    katakana: bool,
+    mut word: Buffer,
+    inner: InputState,
+    buffer: &mut Buffer,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
+    key: KeyEvent,
+) -> InputState {
+    use KeyEvent::*;
+    match key {
+        CommitRegistration if inner.is_idle() => {
+            let w = word.as_string();
+            if w.is_empty() {
+                return back_to_yomi(yomi, katakana);
+            }
+            // ; や / を含む語もそのまま登録できるようにする
+            let candidates = [if w.contains([';', '/']) {
+                encode_candidate(&w)
+            } else {
+                w
+            }];
+            return commit_candidate(
+                &yomi,
+                &candidates,
+                0,
+                KanaState::new(katakana),
+                None,
+                buffer,
+                jisyo,
+                sub,
+            );
+        }
+        CancelConversion if inner.is_idle() => return back_to_yomi(yomi, katakana),
+        _ => (),
+    }
+    let inner = handle_key(inner, &mut word, jisyo, sub, key);
+    InputState::Register {
+        yomi,
+        katakana,
+        word,
+        inner: Box::new(inner),
     }
 }
 
 // -------------------- Helpers --------------------
 
+// 貼り付け：辞書登録中は登録する語に入れる（別の所からコピーした語をそのまま登録できる）
+// 登録する語には改行を含められないので、1行目の前後の空白を除いたものにする
+pub fn paste(state: &mut InputState, buffer: &mut Buffer, text: &str) {
+    match state {
+        InputState::Register { word, inner, .. } => {
+            let line = text.lines().next().unwrap_or_default().trim();
+            paste(inner, word, line);
+        }
+        _ => buffer.insert_text(text),
+    }
+}
+
+// 候補がなければ辞書登録に入る（登録先のユーザー辞書がなければ何もしない）
+fn convert_or_register(
+    yomi: &str,
+    jisyo: &Jisyo,
+    sub: &mut SubModes,
+    katakana: bool,
+) -> Option<InputState> {
+    if let Some(c) = InputState::new_converting(yomi, jisyo, katakana) {
+        sub.events
+            .push(EngineEvent::ConversionStart(yomi.to_string()));
+        return Some(c);
+    }
+    sub.events.push(EngineEvent::NoCandidates(yomi.to_string()));
+    // 読み込み中は辞書にある語でも見つからないので、登録にはしない
+    (jisyo.has_user() && !jisyo.is_loading()).then(|| InputState::new_register(yomi, katakana))
+}
+
+// 変換を取り消して読みの入力に戻る
+fn back_to_yomi(mut yomi: String, katakana: bool) -> InputState {
+    if convert_to_hankaku_ascii(&yomi).is_ascii() {
+        return InputState::Abbrev(yomi);
+    }
+    if matches!(yomi.as_bytes().last(), Some(c) if c.is_ascii_lowercase()) {
+        yomi.pop();
+    }
+    InputState::Kana {
+        romaji: String::new(),
+        state: KanaState::ToBeConverted(yomi, katakana),
+    }
+}
+
+// nextは確定に続けて打った文字（送りありなら、送り仮名ごとの候補としても学習する）
+#[allow(clippy::too_many_arguments)]
 fn commit_candidate(
     yomi: &str,
     candidates: &[String],
     selected_index: usize,
     kana_state: KanaState,
+    next: Option<char>,
     buffer: &mut Buffer,
-    jisyo: &Jisyo,
+    jisyo: &mut Jisyo,
+    sub: &mut SubModes,
 ) -> InputState {
-    let (commit, _) = InputState::candidate(candidates, selected_index);
+    // 変換はウ段の送り仮名とみなして引いたので、打った送り仮名のまとまりで引き直す
+    // 候補を選び直していなければ、引き直した先頭を確定する
+    let okuri = InputState::okuri(yomi).and_then(|c| typed_okuri(c, next));
+    let relooked = okuri
+        .as_deref()
+        .filter(|_| selected_index == 0)
+        .and_then(|kana| jisyo.lookup_okuri(&convert_to_lookup_yomi(yomi), Some(kana)))
+        .filter(|c| !c.is_empty());
+    let candidates = relooked.as_deref().unwrap_or(candidates);
+    let (cand, _) = InputState::candidate(candidates, selected_index);
+    let key = convert_to_lookup_yomi(yomi);
+    // 数値変換の候補は、数字を#にした見出し語に#0〜#3のまま学習する
+    let commit = numeric::expand(&cand, &key);
+    let key = match commit != cand {
+        true => numeric::numeric_key(&key).unwrap_or(key),
+        false => key,
+    };
+    jisyo.record(&key, &candidates[selected_index], okuri.as_deref());
+    sub.events.push(EngineEvent::Commit(commit.to_string()));
     let mut next_state = InputState::Kana {
         romaji: String::new(),
         state: kana_state,
     };
-    buffer.insert_str(commit);
+    buffer.insert_str(&commit);
     if let Some(okuri) = InputState::okuri(yomi) {
-        next_state = handle_key(next_state, buffer, jisyo, KeyEvent::Char(okuri));
+        next_state = handle_key(next_state, buffer, jisyo, sub, KeyEvent::Char(okuri));
     }
     next_state
 }
 
-fn commit_kana(buffer: &mut Buffer, state: &mut KanaState, kana: &str) {
+// 送り仮名の子音（か母音）と、続けて打った文字からなるかな（おおk + i -> き）
+fn typed_okuri(okuri: char, next: Option<char>) -> Option<String> {
+    let kana = |romaji: &str| match search_lookup_table(romaji) {
+        KanaMatch::Success(kana) if kana.pushback.is_empty() => Some(kana.commit),
+        _ => None,
+    };
+    kana(okuri.encode_utf8(&mut [0; 4])).or_else(|| kana(&format!("{}{}", okuri, next?)))
+}
+
+fn commit_kana(buffer: &mut Buffer, state: &mut KanaState, sub: &SubModes, kana: &str) {
     use KanaState::*;
     match state {
-        ToBeConverted(yomi) => yomi.push_str(kana),
-        Hiragana(_) => buffer.insert_str(kana),
-        Katakana(hankaku) => buffer.insert_str(
-            &(if *hankaku {
+        ToBeConverted(yomi, _) => yomi.push_str(kana),
+        Hiragana => buffer.insert_str(kana),
+        Katakana => buffer.insert_str(
+            &(if sub.hankaku_katakana {
                 convert_to_halfwidth_katakana(kana)
             } else {
                 convert_to_katakana(kana)
@@ -337,35 +944,154 @@ fn delete_setsuji(s: &str) -> String {
     s.to_string().replace('>', "")
 }
 
-fn convert_to_katakana(hiragana: &str) -> String {
-    const OFFSET: u32 = 0x60;
-    hiragana
-        .chars()
-        .map(|c| {
-            if (0x3041..=0x3096).contains(&(c as u32)) {
-                char::from_u32(c as u32 + OFFSET).unwrap()
-            } else {
-                c
-            }
-        })
-        .collect()
-}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{jisyo::CandidateOrder, testutil::TempPath};
+    use KeyEvent::*;
+
+    // 辞書とユーザー辞書（始めは空）を読み込む。ファイルは返した値のdropで消える
+    fn load(text: &str) -> (Jisyo, TempPath) {
+        let path = TempPath::new("jisyo.utf8");
+        let user = path.with_extension("user");
+        fs::write(&path, text).unwrap();
+        let mut jisyo = Jisyo::load(path.as_str(), CandidateOrder::Dictionary).unwrap();
+        jisyo.load_user(user.to_str().unwrap(), None).unwrap();
+        (jisyo, path)
+    }
 
-pub fn convert_to_halfwidth_katakana(hiragana: &str) -> String {
-    let mut result = String::with_capacity(hiragana.len());
-    for c in hiragana.chars() {
-        match HIRAGANA_TO_HALFWIDTH_KATAKANA.binary_search_by_key(&c, |&(k, _)| k) {
-            Ok(idx) => result.push_str(HIRAGANA_TO_HALFWIDTH_KATAKANA[idx].1),
-            Err(_) => result.push_str(&convert_to_katakana(&c.to_string())),
+    // かなモードでキーを順に処理して、バッファの内容を返す
+    fn type_keys(jisyo: &mut Jisyo, keys: &[KeyEvent]) -> String {
+        let (mut buffer, mut sub) = (Buffer::default(), SubModes::default());
+        let mut state = InputState::new_kana();
+        for key in keys {
+            state = handle_key(state, &mut buffer, jisyo, &mut sub, *key);
         }
+        buffer.as_string()
+    }
+
+    // 読みを変換し、select番目の候補を選んでキーを押す。その後の状態とバッファの内容を返す
+    fn press_on_candidate(
+        jisyo: &mut Jisyo,
+        yomi: &str,
+        select: usize,
+        key: KeyEvent,
+    ) -> (InputState, String) {
+        let (mut buffer, mut sub) = (Buffer::default(), SubModes::default());
+        let mut state = InputState::new_converting(yomi, jisyo, false).unwrap();
+        for _ in 0..select {
+            state = handle_key(state, &mut buffer, jisyo, &mut sub, NextCandidate);
+        }
+        let state = handle_key(state, &mut buffer, jisyo, &mut sub, key);
+        (state, buffer.as_string())
+    }
+
+    // KaK（と候補の選び直し）に続けて送り仮名の母音を打つ
+    fn kak(select: usize, vowel: char) -> Vec<KeyEvent> {
+        let mut keys = vec![StartYomiOrOkuri('k'), Char('a'), StartYomiOrOkuri('k')];
+        keys.extend((0..select).map(|_| NextCandidate));
+        keys.push(CommitCandidateWithChar(vowel));
+        keys
+    }
+
+    #[test]
+    fn okuri_block_follows_the_typed_kana() {
+        let (mut jisyo, _files) = load("かk /欠/書/[き/書/]/\n");
+        // 学習すると先頭が変わるので、辞書の並びだけを見る
+        jisyo.set_learning(false);
+        assert_eq!(type_keys(&mut jisyo, &kak(0, 'i')), "書き");
+        assert_eq!(type_keys(&mut jisyo, &kak(0, 'u')), "欠く");
+        // 選び直した候補はそのまま確定する
+        assert_eq!(type_keys(&mut jisyo, &kak(1, 'u')), "書く");
+    }
+
+    #[test]
+    fn learned_okuri_block_is_looked_up_with_the_typed_kana() {
+        let (mut jisyo, _files) = load("かk /欠/書/\n");
+        assert_eq!(type_keys(&mut jisyo, &kak(1, 'i')), "書き");
+        // 最後に学習したのは欠（く）だが、書きの送り仮名のまとまりが先に来る
+        assert_eq!(type_keys(&mut jisyo, &kak(1, 'u')), "欠く");
+        assert_eq!(type_keys(&mut jisyo, &kak(0, 'i')), "書き");
+        assert_eq!(type_keys(&mut jisyo, &kak(0, 'u')), "欠く");
+        // 保存して読み直しても同じ
+        jisyo.save_user().unwrap();
+        jisyo.reload_user().unwrap();
+        assert_eq!(type_keys(&mut jisyo, &kak(0, 'i')), "書き");
+    }
+
+    #[test]
+    fn purge_numeric_candidate_under_its_key() {
+        let (mut jisyo, _files) = load("#かい /#1回/#1階/\n");
+        press_on_candidate(&mut jisyo, "1かい", 0, PurgeCandidate);
+        let found = jisyo.lookup_okuri("1かい", None).unwrap();
+        assert_eq!(found[0], "#1階");
+        assert!(!found.iter().any(|c| c == "#1回"));
+        // 数字が違っても同じ見出し語なので出さない
+        let found = jisyo.lookup_okuri("3かい", None).unwrap();
+        assert!(!found.iter().any(|c| c == "#1回"));
+    }
+
+    #[test]
+    fn pin_case_folded_candidate_under_its_key() {
+        let (mut jisyo, _files) = load("HTTP /ハイパー/\nhttp /エイチ/\n");
+        let first = |jisyo: &Jisyo| jisyo.lookup_okuri("http", None).unwrap()[0].clone();
+        press_on_candidate(&mut jisyo, "http", 1, PinCandidate);
+        assert_eq!(first(&jisyo), "ハイパー");
+        // 固定をやめれば、入力したとおりの見出し語の候補が先に戻る
+        press_on_candidate(&mut jisyo, "http", 0, PinCandidate);
+        assert_eq!(first(&jisyo), "エイチ");
+        // 大文字・小文字の違う見出し語の候補も消せる
+        press_on_candidate(&mut jisyo, "http", 1, PurgeCandidate);
+        assert_eq!(jisyo.lookup_okuri("http", None).unwrap(), ["エイチ"]);
+    }
+
+    #[test]
+    fn purge_selects_the_next_candidate() {
+        let (mut jisyo, _files) = load("かんじ /漢字/感じ/幹事/\n");
+        let (state, _) = press_on_candidate(&mut jisyo, "かんじ", 1, PurgeCandidate);
+        let InputState::Converting {
+            candidates,
+            selected_index,
+            ..
+        } = state
+        else {
+            panic!("not converting");
+        };
+        assert_eq!(candidates, ["漢字", "幹事"]);
+        assert_eq!(selected_index, 1);
+        // 最後の候補を消すと、その次はないので前の候補を選ぶ
+        let (state, _) = press_on_candidate(&mut jisyo, "かんじ", 1, PurgeCandidate);
+        assert!(matches!(
+            state,
+            InputState::Converting {
+                selected_index: 0,
+                ..
+            }
+        ));
+        // 候補がなくなれば読みに戻る
+        let (state, _) = press_on_candidate(&mut jisyo, "かんじ", 0, PurgeCandidate);
+        assert!(matches!(
+            state,
+            InputState::Kana {
+                state: KanaState::ToBeConverted(..),
+                ..
+            }
+        ));
     }
-    result
-}
 
-fn convert_to_zenkaku_ascii(c: char) -> char {
-    match c {
-        '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap(),
-        ' ' => '　',
-        _ => c,
+    #[test]
+    fn numeric_candidate_is_expanded_on_commit() {
+        let (mut jisyo, _files) = load("#かい /#1回/#3回/#0回/\n");
+        let commit = |jisyo: &mut Jisyo, select| {
+            press_on_candidate(jisyo, "12かい", select, CommitCandidate).1
+        };
+        assert_eq!(commit(&mut jisyo, 0), "１２回");
+        assert_eq!(commit(&mut jisyo, 1), "十二回");
+        assert_eq!(commit(&mut jisyo, 2), "12回");
+        // 学習は数字を#にした見出し語に、#のままの候補で記録する
+        assert_eq!(jisyo.lookup_okuri("#かい", None).unwrap()[0], "#0回");
+        assert_eq!(commit(&mut jisyo, 0), "12回");
     }
 }
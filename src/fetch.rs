@@ -0,0 +1,191 @@
+//! よく使われるSKK辞書のダウンロード（`--fetch-jisyo`）
+//!
+//! skk-devが配布している`SKK-JISYO.*.gz`を`curl`（なければ`wget`）で
+//! キャッシュの置き場（[`crate::jisyocache::cache_dir`]の`jisyo/`）に取ってくる。
+//! 配布元の`.md5`と照合し、一致しなければ置き換えない。
+//! 同じ内容の辞書がすでにあればダウンロードしない。
+
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::jisyocache;
+
+const BASE_URL: &str = "https://skk-dev.github.io/dict/";
+// 指定する名前（SKK-JISYO.の後ろ）
+const KNOWN: &[&str] = &[
+    "L",
+    "M",
+    "ML",
+    "S",
+    "jinmei",
+    "fullname",
+    "geo",
+    "propernoun",
+    "station",
+    "law",
+    "okinawa",
+    "assoc",
+    "edict",
+    "itaiji",
+    "JIS2",
+    "JIS3_4",
+];
+
+/// 名前の辞書をダウンロードして、JISYO_PATHに並べるパスを返す（進み具合は標準エラー出力に出す）
+pub fn fetch(names: &[String]) -> io::Result<Vec<PathBuf>> {
+    let dir = jisyocache::cache_dir()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no cache directory (HOME is not set)"))?
+        .join("jisyo");
+    fs::create_dir_all(&dir)?;
+    let mut pathes = Vec::new();
+    for name in names {
+        let name = name.strip_prefix("SKK-JISYO.").unwrap_or(name);
+        if !KNOWN.contains(&name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown jisyo: {} ({})", name, KNOWN.join(", ")),
+            ));
+        }
+        pathes.push(fetch_one(&dir, &format!("SKK-JISYO.{}.gz", name))?);
+    }
+    Ok(pathes)
+}
+
+fn fetch_one(dir: &Path, file: &str) -> io::Result<PathBuf> {
+    let path = dir.join(file);
+    let url = format!("{}{}", BASE_URL, file);
+    let sum = download(&format!("{}.md5", url), &dir.join(format!("{}.md5", file)))?;
+    let sum = String::from_utf8_lossy(&sum);
+    let expected = sum
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if expected.len() != 32 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}.md5: not an MD5 checksum", url),
+        ));
+    }
+    if fs::read(&path).is_ok_and(|bytes| hex(&md5(&bytes)) == expected) {
+        eprintln!("{}: up to date", file);
+        return Ok(path);
+    }
+    eprintln!("{}: downloading {}", file, url);
+    let tmp = path.with_extension(format!("gz.{}", std::process::id()));
+    let bytes = download(&url, &tmp)?;
+    let actual = hex(&md5(&bytes));
+    if actual != expected {
+        let _ = fs::remove_file(&tmp);
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: checksum mismatch ({} != {})", file, actual, expected),
+        ));
+    }
+    // 照合してから置き換えるので、途中で失敗しても前の辞書は残る
+    fs::rename(&tmp, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+    Ok(path)
+}
+
+// urlをpathに保存して中身を返す（TLSは持たないので、curlかwgetに任せる）
+fn download(url: &str, path: &Path) -> io::Result<Vec<u8>> {
+    let tools: [(&str, &[&str]); 2] = [("curl", &["-fsSL", "-o"]), ("wget", &["-q", "-O"])];
+    for (tool, args) in tools {
+        let status = Command::new(tool)
+            .args(args)
+            .arg(path)
+            .arg(url)
+            .stdin(Stdio::null())
+            .status();
+        match status {
+            Ok(s) if s.success() => return fs::read(path),
+            Ok(s) => {
+                let _ = fs::remove_file(path);
+                return Err(Error::other(format!("{}: {} failed ({})", url, tool, s)));
+            }
+            // コマンドがなければ次を試す
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "curl or wget is required to download dictionaries",
+    ))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// RFC 1321
+fn md5(data: &[u8]) -> [u8; 16] {
+    const S: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let k: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect();
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+    for chunk in msg.chunks(64) {
+        let m: Vec<u32> = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(k[i])
+                .wrapping_add(m[g])
+                .rotate_left(S[(i / 16) * 4 + i % 4]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    let mut out = [0; 16];
+    for (o, s) in out.chunks_mut(4).zip(state) {
+        o.copy_from_slice(&s.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 1321 の付録の値
+    #[test]
+    fn md5_of_known_vectors() {
+        let md5_hex = |s: &str| hex(&md5(s.as_bytes()));
+        assert_eq!(md5_hex(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex("abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5_hex("abcdefghijklmnopqrstuvwxyz"),
+            "c3fcd3d76192e4007dfb496cca67e13b"
+        );
+        // 64バイトを超えて、パディングが次のブロックにまたがる
+        assert_eq!(
+            md5_hex(&"1234567890".repeat(8)),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+}
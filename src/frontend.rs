@@ -1,27 +1,47 @@
+mod prompt;
+pub mod replay;
+
 use std::{
-    io::{self, Read, Write},
-    process::{Command, Stdio},
+    fs,
+    io::{self, Write},
+    mem,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use termion::{event::Key, input::TermRead};
+use termion::event::Key;
 
 use crate::{
     buffer::Buffer,
-    engine::handle_key,
+    command::{CommandResult, copy_from_command, copy_to_command},
+    config::{Config, QuitAction},
+    control::{self, ControlSocket, Mode, Reply, Request},
+    engine::{flush_romaji, handle_key, paste},
+    hook::{self, EngineEvent},
     jisyo::Jisyo,
     key::{KeyEvent, Move},
-    state::{InputState, KanaState},
+    romaji::load_table,
+    signal::{self, Signal},
+    state::{InputState, KanaState, Shrink, StatusPart, SubModes},
+    status,
+    terminal::{Input, Keys, Terminal, caret_notation},
     util::{
         ClosedInterval, push_char_to_vec_u8, push_itoa_usize_to_string, push_itoa_usize_to_vec_u8,
         push_str_to_vec_u8,
     },
+    wrap::wrap,
 };
+use prompt::{History, Prompt, PromptKind, PromptResult};
 
 struct CharWidth(u8);
-const REPLACE: CharWidth = CharWidth(0);
+const REPLACE: CharWidth = CharWidth(u8::MAX);
+const COMBINE: CharWidth = CharWidth(0); // 前の文字に重ねて描く
 
 // 線形探索向け：よく使う文字を先頭に
 const SIMPLIFIED_WIDTH_TABLE: &[(ClosedInterval<u32>, CharWidth)] = &[
+    // 結合用の濁点・半濁点（Hiraganaの範囲より先に）
+    (ClosedInterval(0x3099, 0x309A), COMBINE),
     // 幅2：日本語／CJK系
     (ClosedInterval(0x3040, 0x309F), CharWidth(2)), // Hiragana
     (ClosedInterval(0x30A0, 0x30FF), CharWidth(2)), // Katakana
@@ -42,12 +62,18 @@ const SIMPLIFIED_WIDTH_TABLE: &[(ClosedInterval<u32>, CharWidth)] = &[
     (ClosedInterval(0x7F, 0x9F), REPLACE),
     // ZWSP / ZWNJ / ZWJ / LRM / RLM
     (ClosedInterval(0x200B, 0x200F), REPLACE),
-    // Combining marks（入力やコピペで出ることがある）
-    (ClosedInterval(0x0300, 0x036F), REPLACE),
-    (ClosedInterval(0x1AB0, 0x1AFF), REPLACE),
-    (ClosedInterval(0x1DC0, 0x1DFF), REPLACE),
-    (ClosedInterval(0x20D0, 0x20FF), REPLACE),
-    (ClosedInterval(0xFE20, 0xFE2F), REPLACE),
+    // Combining marks（顔文字の目や口の飾りに使う）
+    (ClosedInterval(0x0300, 0x036F), COMBINE),
+    (ClosedInterval(0x0483, 0x0489), COMBINE), // Cyrillic
+    (ClosedInterval(0x0610, 0x061A), COMBINE), // Arabic
+    (ClosedInterval(0x064B, 0x065F), COMBINE),
+    (ClosedInterval(0x0E31, 0x0E31), COMBINE), // Thai
+    (ClosedInterval(0x0E34, 0x0E3A), COMBINE),
+    (ClosedInterval(0x0E47, 0x0E4E), COMBINE),
+    (ClosedInterval(0x1AB0, 0x1AFF), COMBINE),
+    (ClosedInterval(0x1DC0, 0x1DFF), COMBINE),
+    (ClosedInterval(0x20D0, 0x20FF), COMBINE),
+    (ClosedInterval(0xFE20, 0xFE2F), COMBINE),
     // Variation Selector（EmojiなどのVS）
     (ClosedInterval(0xFE00, 0xFE0F), REPLACE),
     // Bidi制御文字
@@ -64,9 +90,11 @@ const SIMPLIFIED_WIDTH_TABLE: &[(ClosedInterval<u32>, CharWidth)] = &[
 const DIM: &str = "\x1b[2m";
 const CURSOR: &str = "\x1b[7m";
 const RESET: &str = "\x1b[0m";
+const BELL: &str = "\x07";
 const STATUS: &str = "\x1b[97m\x1b[44m";
 const CLEAR_ALL: &str = "\x1b[2J";
 const CLEAR_CUR_LINE: &str = "\x1b[2K";
+const MSG_LOADING: &str = "辞書読込中…";
 const CURSOR_SHOW: &str = "\x1b[?25h";
 const CURSOR_HIDE: &str = "\x1b[?25l";
 
@@ -75,6 +103,9 @@ const SYMB_MORE_L: char = '<'; // 行省略記号(左)
 const SYMB_MORE_R: char = '>'; // 行省略記号(右)
 const SYMB_NO_LINE: char = '~';
 const SYMB_LF: char = '¶';
+const SYMB_RULER: char = '|'; // RULER_WIDTHの位置（曖昧幅の罫線は避ける）
+const ELLIPSIS: char = '…'; // ステータス行で縮めた所
+const SYMB_MASK: char = '●'; // --maskedで本文の代わりに表示
 
 const REPLACED_CHAR_W: usize = 2;
 const REPLACE_TAB: &str = "\\t";
@@ -83,10 +114,23 @@ const REPLACE_OTHER: &str = "\\?";
 const SCROLL_MARGIN: usize = 8; // 横スクロール開始の余裕幅(半角); ViewStateのサンプリングを考慮する
 const CURSOR_SAMPLING_MASK: usize = 0b11;
 const MIN_TERM_H: usize = 2;
+const MIN_STATUS_W: usize = 24; // 右端に時計などを出すとき、モード表示のために残す幅
+
+const KEY_IGNORED: &str = "無視";
+const KEY_MASKED: &str = "文字の入力"; // --maskedでは何の文字かを出さない
+const MSG_QUIT_CONFIRM: &str = "未送出の内容があります。終了しますか？(y/n)";
+const MSG_SEARCH: &str = "検索: ";
+const MSG_GOTO_LINE: &str = "行番号: ";
+const MSG_JISYO: &str = "辞書: ";
+const READING_MAX: usize = 10; // 読みの表示に並べる数
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+const FRAME_INTERVAL: Duration = Duration::from_millis(16); // キー入力での描画は1秒に60回まで
 
 // -------------------- キーバインド --------------------
+#[derive(Debug)]
 enum FrontCmd {
     SendAndClear,
+    Send,
     Quit,
     Paste,
     Undo,
@@ -95,24 +139,79 @@ enum FrontCmd {
     CopySelected,
     CutSelected,
     PrintCodePoint,
+    PrintReading,
+    ReloadJisyo,
+    Wrap,
+    ToggleKeyInfo,
+    Suspend,
+    SendAndQuit,
+    GotoLine,
+    RestorePurged,
+    SwitchJisyo,
 }
 
 fn to_front_cmd(k: &Key) -> Option<FrontCmd> {
     use termion::event::Key::*;
     match k {
         Ctrl('q') => Some(FrontCmd::Quit),
+        Alt('q') => Some(FrontCmd::SendAndQuit),
         Ctrl('s') => Some(FrontCmd::SendAndClear),
+        Ctrl('w') => Some(FrontCmd::Send),
         Ctrl('d') => Some(FrontCmd::Clear),
         Ctrl('r') => Some(FrontCmd::Refresh),
         Ctrl('x') => Some(FrontCmd::CutSelected),
         Ctrl('v') => Some(FrontCmd::Paste),
         Ctrl('c') => Some(FrontCmd::CopySelected),
         Ctrl('b') => Some(FrontCmd::PrintCodePoint),
+        Ctrl('e') => Some(FrontCmd::ReloadJisyo),
+        Ctrl('f') => Some(FrontCmd::Wrap),
+        Ctrl('y') => Some(FrontCmd::ToggleKeyInfo),
+        Alt('z') => Some(FrontCmd::Suspend), // Ctrl+Zは半角／全角の切替に使っている
+        Alt('g') => Some(FrontCmd::GotoLine),
+        Alt('x') => Some(FrontCmd::RestorePurged),
+        Alt('y') => Some(FrontCmd::PrintReading),
+        Alt('j') => Some(FrontCmd::SwitchJisyo),
         Esc => Some(FrontCmd::Undo),
         _ => None,
     }
 }
 
+// --view：内容を変えない操作だけを受け付ける
+fn is_read_only(cmd: &FrontCmd) -> bool {
+    matches!(
+        cmd,
+        FrontCmd::Quit
+            | FrontCmd::Refresh
+            | FrontCmd::Suspend
+            | FrontCmd::SendAndQuit
+            | FrontCmd::GotoLine
+            | FrontCmd::Send
+            | FrontCmd::CopySelected
+            | FrontCmd::PrintCodePoint
+            | FrontCmd::PrintReading
+            | FrontCmd::ToggleKeyInfo
+    )
+}
+
+#[derive(Debug)]
+enum ViewCmd {
+    Quit,
+    Search,
+    SearchNext,
+    SearchPrev,
+}
+
+fn to_view_cmd(k: &Key) -> Option<ViewCmd> {
+    use termion::event::Key::*;
+    match k {
+        Char('q') => Some(ViewCmd::Quit),
+        Char('/') => Some(ViewCmd::Search),
+        Char('n') => Some(ViewCmd::SearchNext),
+        Char('N') => Some(ViewCmd::SearchPrev),
+        _ => None,
+    }
+}
+
 fn to_key_event_global(k: &Key) -> Option<KeyEvent> {
     use termion::event::Key::*;
     match k {
@@ -129,6 +228,8 @@ fn to_key_event_global(k: &Key) -> Option<KeyEvent> {
         PageDown => Some(KeyEvent::Navigation(Move::RapidDown)),
         ShiftLeft => Some(KeyEvent::Navigation(Move::SelectLeft)),
         ShiftRight => Some(KeyEvent::Navigation(Move::SelectRight)),
+        CtrlLeft => Some(KeyEvent::Navigation(Move::WordLeft)),
+        CtrlRight => Some(KeyEvent::Navigation(Move::WordRight)),
         Delete => Some(KeyEvent::Delete),
         Backspace => Some(KeyEvent::Backspace),
         _ => None,
@@ -156,17 +257,26 @@ fn to_key_event_abbrev(k: &Key) -> Option<KeyEvent> {
 fn to_key_event_kana(kana_state: &KanaState, k: &Key) -> Option<KeyEvent> {
     use termion::event::Key::*;
     match k {
+        Ctrl('o') => Some(KeyEvent::QuoteNext),
+        Ctrl('k') => Some(KeyEvent::StartPalette),
+        Ctrl('u') => Some(KeyEvent::StartLearned),
         Char('q') => Some(KeyEvent::ToggleKatakana),
         Char('>') => Some(KeyEvent::Setsuji),
         Char('/') => Some(KeyEvent::StartAbbrev),
         Char(c @ ' ') => match kana_state {
-            KanaState::ToBeConverted(_) => Some(KeyEvent::StartConversion),
+            KanaState::ToBeConverted(..) => Some(KeyEvent::StartConversion),
             _ => Some(KeyEvent::Char(*c)),
         },
         Char(c @ '\n') => match kana_state {
-            KanaState::ToBeConverted(_) => Some(KeyEvent::CommitUnconverted),
+            KanaState::ToBeConverted(..) => Some(KeyEvent::CommitUnconverted),
             _ => Some(KeyEvent::Char(*c)),
         },
+        Char('\t') if matches!(kana_state, KanaState::ToBeConverted(..)) => {
+            Some(KeyEvent::Complete)
+        }
+        BackTab if matches!(kana_state, KanaState::ToBeConverted(..)) => {
+            Some(KeyEvent::CompletePrev)
+        }
         Char(c) if c.is_ascii_uppercase() => {
             Some(KeyEvent::StartYomiOrOkuri(c.to_ascii_lowercase()))
         }
@@ -175,6 +285,44 @@ fn to_key_event_kana(kana_state: &KanaState, k: &Key) -> Option<KeyEvent> {
     }
 }
 
+fn to_key_event_entry_edit(editing_annotation: bool, k: &Key) -> Option<KeyEvent> {
+    use termion::event::Key::*;
+    match k {
+        Char('\n') => Some(KeyEvent::CommitCandidate),
+        Char(c) if editing_annotation => Some(KeyEvent::Char(*c)),
+        Char(' ') => Some(KeyEvent::NextCandidate),
+        Char('x') => Some(KeyEvent::PrevCandidate),
+        Char('<') => Some(KeyEvent::MoveCandidateUp),
+        Char('>') => Some(KeyEvent::MoveCandidateDown),
+        Char('D') => Some(KeyEvent::DeleteCandidate),
+        Char('a') => Some(KeyEvent::EditAnnotation),
+        _ => None,
+    }
+}
+
+fn to_key_event_palette(k: &Key) -> Option<KeyEvent> {
+    use termion::event::Key::*;
+    match k {
+        Char(' ') => Some(KeyEvent::NextCandidate),
+        Char('x') => Some(KeyEvent::PrevCandidate),
+        Char('n') => Some(KeyEvent::NextCategory),
+        Char('p') => Some(KeyEvent::PrevCategory),
+        Char('\n') => Some(KeyEvent::CommitCandidate),
+        _ => None,
+    }
+}
+
+fn to_key_event_learned(k: &Key) -> Option<KeyEvent> {
+    use termion::event::Key::*;
+    match k {
+        Char(' ') => Some(KeyEvent::NextCandidate),
+        Char('x') => Some(KeyEvent::PrevCandidate),
+        Char('D') => Some(KeyEvent::DeleteCandidate),
+        Char('\n') => Some(KeyEvent::CommitCandidate),
+        _ => None,
+    }
+}
+
 fn to_key_event_conversion(k: &Key) -> Option<KeyEvent> {
     use termion::event::Key::*;
     match k {
@@ -184,6 +332,9 @@ fn to_key_event_conversion(k: &Key) -> Option<KeyEvent> {
         Char('\n') => Some(KeyEvent::CommitCandidate),
         Char('>') => Some(KeyEvent::CommitCandidateWithSetsubiji),
         Char('/') => Some(KeyEvent::StartAbbrev),
+        Ctrl('t') => Some(KeyEvent::StartEntryEdit),
+        Char('X') => Some(KeyEvent::PurgeCandidate),
+        Ctrl('p') => Some(KeyEvent::PinCandidate),
         Char(c) if c.is_ascii_uppercase() => Some(KeyEvent::CommitCandidateWithStartYomi(
             c.to_ascii_lowercase(),
         )),
@@ -197,15 +348,67 @@ fn to_key_event_with_state(state: &InputState, k: &Key) -> Option<KeyEvent> {
         Some(s)
     } else {
         match state {
-            InputState::Latin(_) => to_key_event_latin(k),
+            InputState::Latin => to_key_event_latin(k),
             InputState::Converting { .. } => to_key_event_conversion(k),
             InputState::Kana { state: s, .. } => to_key_event_kana(s, k),
             InputState::Abbrev { .. } => to_key_event_abbrev(k),
+            InputState::Quoted(_) => to_key_event_latin(k),
+            InputState::EntryEdit { annotation, .. } => {
+                to_key_event_entry_edit(annotation.is_some(), k)
+            }
+            InputState::Palette { .. } => to_key_event_palette(k),
+            InputState::Learned { .. } => to_key_event_learned(k),
+            InputState::Register { inner, .. } => match k {
+                Key::Char('\n') if inner.is_idle() => Some(KeyEvent::CommitRegistration),
+                _ => to_key_event_with_state(inner, k),
+            },
+            // 補完を選んでいる間も、Tab以外は読みの入力中と同じ
+            InputState::Completing { katakana, .. } => {
+                let yomi = KanaState::ToBeConverted(String::new(), *katakana);
+                to_key_event_kana(&yomi, k)
+            }
         }
     }
 }
 
+// Ctrl+Y のキー診断用：runと同じ順に、キーがどう扱われるかを調べる（状態は変えない）
+fn classify_key(k: &Key, is: &InputState, config: &Config) -> String {
+    if config.single_line && *k == Key::Char('\n') && is.is_idle() {
+        return String::from("1行入力の終了");
+    }
+    if let Some(cmd) = to_view_cmd(k).filter(|_| config.view_only) {
+        return format!("{:?}", cmd);
+    }
+    let cmd = to_front_cmd(k);
+    if let Some(cmd) = &cmd
+        && config.view_only
+        && !is_read_only(cmd)
+    {
+        return format!("{:?}（--viewでは無効）", cmd);
+    }
+    let ev = to_key_event_with_state(is, k)
+        .filter(|ev| !config.view_only || matches!(ev, KeyEvent::Navigation(_)));
+    match (cmd, ev) {
+        (Some(cmd), Some(ev)) => format!("{:?} + {:?}", cmd, ev),
+        (Some(cmd), None) => format!("{:?}", cmd),
+        (None, Some(ev)) => format!("{:?}", ev),
+        (None, None) => String::from(KEY_IGNORED),
+    }
+}
+
+// Ctrl+Y の診断表示に出すキーと扱い（--maskedでは入力した文字を伏せる）
+fn describe_key(k: &Key, class: String, masked: bool) -> (String, String) {
+    match k {
+        Key::Char(c) if masked && !c.is_control() => (
+            format!("{:?}", Key::Char(SYMB_MASK)),
+            String::from(KEY_MASKED),
+        ),
+        _ => (format!("{:?}", k), class),
+    }
+}
+
 // -------------------- 文字幅 --------------------
+// 置換して表示する文字はNone、前の文字に重ねる結合文字はSome(0)
 #[inline(always)]
 fn char_width(c: char) -> Option<usize> {
     let v = c as u32;
@@ -214,13 +417,17 @@ fn char_width(c: char) -> Option<usize> {
     }
     for (interval, width) in SIMPLIFIED_WIDTH_TABLE {
         if interval.contains(v) {
-            let w = width.0;
-            return if w == 0 { None } else { Some(w as usize) };
+            return (width.0 != REPLACE.0).then_some(width.0 as usize);
         }
     }
     Some(1)
 }
 
+// 画面上のセル数（置換して表示する文字はその幅）
+pub fn cell_width(c: char) -> usize {
+    char_width(c).unwrap_or(REPLACED_CHAR_W)
+}
+
 // -------------------- Viewport (スクロール) --------------------
 #[derive(Default, Clone)]
 struct ViewState {
@@ -230,6 +437,9 @@ struct ViewState {
     cursor_col: usize,
     active_line_offset: usize,
     ignore_inactive_lines: bool,
+    ruler: Option<usize>, // 行頭からのセル数
+    mask: bool,
+    top: Option<usize>, // --view：画面の1行目に描く行（Noneならカーソルの行を最下行に描く）
 }
 
 impl ViewState {
@@ -307,6 +517,29 @@ enum SelectionState {
     Post,
 }
 
+// スクロールの位置をカーソルに合わせ、描く範囲のずれを返す
+// 位置は前の位置から決まるので、描くのを後回しにするときもキーごとに呼ぶ（track_view）
+fn scroll_view(term_size: (usize, usize), vs: &mut ViewState, buffer: &Buffer) -> usize {
+    let (term_w, term_h) = term_size;
+    let (r, _) = buffer.cursor();
+    let view_bottom = term_h - 1;
+    // 上から順に描く場合は、カーソルが画面から出るときだけ縦にスクロールする
+    vs.top = vs
+        .top
+        .map(|top| top.min(r).max((r + 1).saturating_sub(view_bottom)));
+    let shift = vs.top.map_or(r, |top| top + view_bottom - 1);
+    vs.update(buffer, term_w);
+    shift
+}
+
+fn track_view(term_size: (usize, usize), vs: &mut ViewState, buffer: &Buffer) {
+    if vs.mask {
+        scroll_view(term_size, vs, &buffer.masked(SYMB_MASK));
+    } else {
+        scroll_view(term_size, vs, buffer);
+    }
+}
+
 fn prepare_view_to_buffer(
     out: &mut Vec<u8>,
     term_size: (usize, usize),
@@ -314,20 +547,31 @@ fn prepare_view_to_buffer(
     buffer: &Buffer,
 ) {
     let (term_w, term_h) = term_size;
+    // 伏せ字は置き換えた写しを描く（スクロールの計算も写しの幅で行う）
+    let masked;
+    let buffer = if vs.mask {
+        masked = buffer.masked(SYMB_MASK);
+        &masked
+    } else {
+        buffer
+    };
     let (r, _) = buffer.cursor();
     let view_bottom = term_h - 1;
     let vs_old = vs.clone();
-    vs.update(buffer, term_w);
+    let shift = scroll_view(term_size, vs, buffer);
 
     out.clear();
     for y in 1..=view_bottom {
-        let active_line = y == view_bottom;
+        let row = (shift + y)
+            .checked_sub(view_bottom)
+            .filter(|&row| row < buffer.line_count());
+        let active_line = row == Some(r);
         if !vs.should_redraw_all(&vs_old) && !active_line {
             continue;
         }
         push_cursor_goto(out, y, 1);
         push_str_to_vec_u8(out, CLEAR_CUR_LINE);
-        if let Some(row) = (r + y).checked_sub(view_bottom) {
+        if let Some(row) = row {
             let raw_line = buffer.line(row);
             let sel = if active_line {
                 Some(buffer.selection())
@@ -340,9 +584,11 @@ fn prepare_view_to_buffer(
             } else {
                 calc_offset(raw_line, vs.left_cells)
             };
-            prepare_line_to_buffer(out, raw_line, i, term_w, sel, lf);
+            prepare_line_to_buffer(out, raw_line, i, term_w, sel, lf, vs.ruler);
         } else {
             push_fmt_ch(out, DIM, SYMB_NO_LINE);
+            let ruler = vs.ruler.and_then(|r| r.checked_sub(vs.left_cells));
+            push_ruler(out, ruler, SYMB_CHAR_W, term_w);
         }
     }
 }
@@ -354,10 +600,17 @@ fn prepare_line_to_buffer(
     term_w: usize,
     selection: Option<ClosedInterval<usize>>,
     lf: bool,
+    ruler: Option<usize>,
 ) {
     let mut used = 0usize;
+    // 行頭からのセル数（画面上の位置との差がルーラーのずれになる）
+    let mut cells: usize = line[..offset]
+        .iter()
+        .map(|c| char_width(*c).unwrap_or(REPLACED_CHAR_W))
+        .sum();
     let mut ss = SelectionState::Pre;
     let mut end_of_line = true;
+    let mut attach = false; // 直前に描いたのが結合文字を重ねられる文字か
     for (i, c) in line.iter().enumerate().skip(offset) {
         let width_original = char_width(*c);
         let w = width_original.unwrap_or(REPLACED_CHAR_W);
@@ -366,17 +619,34 @@ fn prepare_line_to_buffer(
             break;
         }
 
+        // 結合文字は前の文字と同じセルに描く（SYMB_MORE_Lや置換した文字には重ねない）
+        if w == 0 {
+            if attach {
+                push_char_to_vec_u8(out, *c);
+            }
+            continue;
+        }
+
         // 左にオフセットなら行頭の1文字を潰してSYMB_MORE_Lを描画（見た目とセル数の安定性を優先）
+        cells += w;
         if i != 0 && used == 0 {
             push_fmt_ch(out, DIM, SYMB_MORE_L);
             used += SYMB_CHAR_W;
+            attach = false;
             continue;
         }
 
         let replace = width_original.is_none();
-        let in_selection = matches!(selection, Some(ref interval) if interval.contains(i));
+        // カーソルが結合文字にあるときは、重ねた先の文字を反転する
+        let marks = line[i + 1..]
+            .iter()
+            .take_while(|c| char_width(**c) == Some(0))
+            .count();
+        let in_selection =
+            matches!(selection, Some(ClosedInterval(l, r)) if l <= i + marks && i <= r);
         handle_selection(out, &mut ss, in_selection);
         handle_push_character(out, *c, replace, in_selection);
+        attach = !replace;
         used += w;
     }
 
@@ -393,19 +663,36 @@ fn prepare_line_to_buffer(
             let fmt = if selection_remains { CURSOR } else { DIM };
             let tail = if lf { SYMB_LF } else { ' ' };
             push_fmt_ch(out, fmt, tail);
+            let ruler = ruler.and_then(|r| r.checked_sub(cells - used));
+            push_ruler(out, ruler, used + SYMB_CHAR_W, term_w);
         } else {
             push_fmt_ch(out, DIM, SYMB_MORE_R);
         }
     }
 }
 
+// 行の末尾（used）より右にルーラーがあれば描く（文字には重ねない）
+fn push_ruler(out: &mut Vec<u8>, ruler: Option<usize>, used: usize, term_w: usize) {
+    let Some(r) = ruler.filter(|&r| r >= used && r + SYMB_CHAR_W < term_w) else {
+        return;
+    };
+    for _ in used..r {
+        push_char_to_vec_u8(out, ' ');
+    }
+    push_fmt_ch(out, DIM, SYMB_RULER);
+}
+
+#[allow(clippy::too_many_arguments)]
 fn prepare_status_line(
     out: &mut Vec<u8>,
     term_size: (usize, usize),
-    code_point: Option<&str>,
+    message: Option<&str>,
     state: &InputState,
-    buffer: Option<&Buffer>,
+    sub: &SubModes,
+    buffer: &Buffer,
+    show_buffer_info: bool,
     has_ss: bool,
+    segments: &str,
 ) {
     let (term_w, term_h) = term_size;
     out.clear();
@@ -414,29 +701,107 @@ fn prepare_status_line(
     push_str_to_vec_u8(out, STATUS);
     push_str_to_vec_u8(out, CLEAR_CUR_LINE);
 
-    let mut usable_cells = term_w;
-    if let Some(cp) = code_point {
-        push_str_until(out, cp, &mut usable_cells);
-        if usable_cells > 0 {
-            push_char_to_vec_u8(out, ' ');
-            usable_cells -= 1;
-        }
+    // 時計などは右端に置き、その手前（空白1つを挟む）までを使う
+    let col = segments_col(term_w, segments);
+    let mut usable_cells = col.map_or(term_w, |c| c - 1);
+    let mut parts = Vec::new();
+    if let Some(msg) = message {
+        parts.push((String::from(msg), Shrink::Message));
+        parts.push((String::from(" "), Shrink::Keep));
     }
-    push_str_until(out, &state.status_as_string(), &mut usable_cells);
-    if let Some(b) = buffer {
-        if usable_cells > 0 {
-            push_char_to_vec_u8(out, ' ');
-            usable_cells -= 1;
-        }
-        push_str_until(out, &b.status_as_string(), &mut usable_cells);
+    parts.extend(state.status_parts(sub));
+    if show_buffer_info {
+        parts.push((format!(" {}", buffer.status_as_string()), Shrink::Detail));
+    }
+    if buffer.has_unsent() {
+        parts.push((String::from(" +unsent"), Shrink::Detail));
     }
     if has_ss {
-        push_str_until(out, " +undo", &mut usable_cells);
+        parts.push((String::from(" +undo"), Shrink::Detail));
+    }
+    // push_str_untilは最後の1セルを使わない
+    let fitted = fit_status_parts(parts, usable_cells.saturating_sub(1));
+    push_str_until(out, &fitted, &mut usable_cells);
+    if let Some(col) = col {
+        push_status_segments(out, term_size, col, segments);
     }
 
     push_str_to_vec_u8(out, RESET);
 }
 
+// ステータス行の入力中はその内容と入力状態を、そうでなければmsgと本文の情報を出す
+#[allow(clippy::too_many_arguments)]
+fn prepare_prompt_line(
+    out: &mut Vec<u8>,
+    term_size: (usize, usize),
+    prompt: &Option<Prompt>,
+    msg: Option<&str>,
+    state: &InputState,
+    sub: &SubModes,
+    buffer: &Buffer,
+    has_ss: bool,
+    segments: &str,
+) {
+    match prompt {
+        Some(p) => {
+            let state = p.state().unwrap_or(state);
+            let msg = Some(p.message());
+            prepare_status_line(
+                out,
+                term_size,
+                msg.as_deref(),
+                state,
+                sub,
+                buffer,
+                false,
+                has_ss,
+                segments,
+            );
+        }
+        None => prepare_status_line(
+            out, term_size, msg, state, sub, buffer, true, has_ss, segments,
+        ),
+    }
+}
+
+// queryの次（forwardでなければ前）の出現を選択する（見つからなければベルを鳴らしてメッセージを返す）
+fn find_query(buffer: &mut Buffer, query: &[char], forward: bool) -> Option<String> {
+    if buffer.find(query, forward) {
+        return None;
+    }
+    buffer.ring_bell();
+    (!query.is_empty()).then(|| format!("見つかりません: {}", query.iter().collect::<String>()))
+}
+
+// 右端の表示（起動時の辞書の読み込み中は、その表示を先頭に足す）
+fn status_segments(config: &Config, loading: bool) -> String {
+    let seg = status::segments_as_string(&config.status_segments);
+    match (loading, seg.is_empty()) {
+        (false, _) => seg,
+        (true, true) => String::from(MSG_LOADING),
+        (true, false) => format!("{} {}", MSG_LOADING, seg),
+    }
+}
+
+// 右端の表示を始める列（幅が足りなければNone）
+fn segments_col(term_w: usize, segments: &str) -> Option<usize> {
+    let w: usize = segments
+        .chars()
+        .map(|c| char_width(c).unwrap_or(REPLACED_CHAR_W))
+        .sum();
+    (w > 0 && w + MIN_STATUS_W < term_w).then(|| term_w - w)
+}
+
+// 右端の表示だけを書く（他の表示は消さない）
+fn push_status_segments(out: &mut Vec<u8>, term_size: (usize, usize), col: usize, segments: &str) {
+    let (term_w, term_h) = term_size;
+    let mut usable_cells = term_w - col + 1;
+    push_cursor_goto(out, term_h, col);
+    push_str_to_vec_u8(out, STATUS);
+    push_str_until(out, segments, &mut usable_cells);
+    push_str_to_vec_u8(out, RESET);
+}
+
 #[inline(always)]
 fn handle_selection(out: &mut Vec<u8>, ss: &mut SelectionState, in_selection: bool) {
     if in_selection && matches!(ss, SelectionState::Pre) {
@@ -489,25 +854,103 @@ pub fn push_cursor_goto(out: &mut Vec<u8>, row: usize, col: usize) {
     push_char_to_vec_u8(out, 'H');
 }
 
+// 入りきらなければ、入る所まで書いて…を付ける（最後の1セルは使わない）
 pub fn push_str_until(out: &mut Vec<u8>, s: &str, cell_counter: &mut usize) {
     if *cell_counter == 0 {
         return;
     }
+    let fits = str_width(s) < *cell_counter;
     for c in s.chars() {
         let width_original = char_width(c);
         let w = width_original.unwrap_or(REPLACED_CHAR_W);
-        if (*cell_counter).saturating_sub(w) < 1 {
+        // 切るときは…の1セルを残す
+        let reserved = if fits { 0 } else { 1 };
+        if (*cell_counter).saturating_sub(w + reserved) < 1 {
             break;
         }
         push_replaced_char(out, c, width_original.is_none());
         *cell_counter -= w
     }
+    if !fits && *cell_counter > 1 {
+        push_char_to_vec_u8(out, ELLIPSIS);
+        *cell_counter -= 1;
+    }
+}
+
+fn str_width(s: &str) -> usize {
+    s.chars().map(cell_width).sum()
+}
+
+// 幅に収まるよう、Shrinkの順に部品の中ほどを…にして縮める（DetailとPreviewは丸ごと落とす）
+fn fit_status_parts(mut parts: Vec<StatusPart>, width: usize) -> String {
+    let mut total: usize = parts.iter().map(|(s, _)| str_width(s)).sum();
+    for rank in [
+        Shrink::Detail,
+        Shrink::Preview,
+        Shrink::Message,
+        Shrink::Text,
+    ] {
+        for (text, _) in parts.iter_mut().filter(|(_, s)| *s == rank) {
+            if total <= width {
+                break;
+            }
+            let w = str_width(text);
+            // 補足や先読みは半端に残しても読めないので丸ごと落とす
+            *text = if rank <= Shrink::Preview {
+                String::new()
+            } else {
+                truncate_middle(text, w.saturating_sub(total - width).max(w.min(1)))
+            };
+            total = total - w + str_width(text);
+        }
+    }
+    parts.into_iter().map(|(s, _)| s).collect()
+}
+
+// 先頭と末尾を残して中ほどを…にし、width以下のセル数にする
+fn truncate_middle(s: &str, width: usize) -> String {
+    if str_width(s) <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let chars: Vec<char> = s.chars().collect();
+    let budget = width - 1; // …の分
+    let (mut head, mut head_w) = (0, 0);
+    let (mut tail, mut tail_w) = (chars.len(), 0);
+    // 先頭を多めに、交互に1文字ずつ足す
+    while head < tail {
+        let grow_head = head_w <= tail_w;
+        let w = cell_width(chars[if grow_head { head } else { tail - 1 }]);
+        if head_w + tail_w + w > budget {
+            break;
+        }
+        if grow_head {
+            head += 1;
+            head_w += w;
+        } else {
+            tail -= 1;
+            tail_w += w;
+        }
+    }
+    // 末尾側の先頭に残った結合文字は、重ねる先がないので落とす
+    while tail < chars.len() && char_width(chars[tail]) == Some(0) {
+        tail += 1;
+    }
+    let mut out: String = chars[..head].iter().collect();
+    out.push(ELLIPSIS);
+    out.extend(&chars[tail..]);
+    out
 }
 
 // -------------------- terminal size --------------------
-fn get_terminal_size() -> (usize, usize) {
-    let (w, h) = termion::terminal_size().expect("failed to query terminal size");
-    (w as usize, h as usize)
+// 取得できない端末（一部のマルチプレクサやシリアルコンソール）では設定した大きさとみなす
+fn get_terminal_size<T: Terminal>(ui: &T, config: &Config) -> (usize, usize) {
+    match ui.size() {
+        Ok((w, h)) if w > 0 && h > 0 => (w, h),
+        _ => config.fallback_term_size,
+    }
 }
 
 fn is_terminal_too_small(term_size: (usize, usize)) -> bool {
@@ -527,6 +970,19 @@ fn draw_terminal_too_small<W: Write>(out: &mut W) -> io::Result<()> {
     Ok(())
 }
 
+// Ctrl+Y：最後のキーの診断を1行目に重ねて描く
+fn draw_key_info<W: Write>(out: &mut W, term_w: usize, info: &str) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    push_cursor_goto(&mut buf, 1, 1);
+    push_str_to_vec_u8(&mut buf, STATUS);
+    push_str_to_vec_u8(&mut buf, CLEAR_CUR_LINE);
+    let mut usable_cells = term_w;
+    push_str_until(&mut buf, info, &mut usable_cells);
+    push_str_to_vec_u8(&mut buf, RESET);
+    out.write_all(&buf)?;
+    out.flush()
+}
+
 fn redraw<W: Write>(
     out: &mut W,
     view: Option<&[u8]>,
@@ -542,29 +998,291 @@ fn redraw<W: Write>(
     Ok(())
 }
 
-// -------------------- command --------------------
-fn copy_to_command(text: &str, shell: &str, cmd: &str) {
-    let mut child = Command::new(shell)
-        .arg("-c")
-        .arg(cmd)
-        .stdin(Stdio::piped())
-        .spawn()
-        .expect("command CPY_TO failure");
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(text.as_bytes())
-            .expect("failed to convert text into bytes");
+// 描くのを後回しにした画面（貼り付けやキーリピートで続けて届くキーは、描画を間引いて処理を進める）
+#[derive(Default)]
+struct PendingFrame {
+    deadline: Option<Instant>, // Someなら後回しにした画面がある
+    view: bool,                // 本文も描く
+    buffer_info: bool,         // ステータス行にバッファの情報を出す
+}
+
+// 後回しにした画面を今の状態で描く（本文は間引いた変更を含めて全体を描き直す）
+#[allow(clippy::too_many_arguments)]
+fn draw_pending_frame<T: Terminal>(
+    ui: &mut T,
+    frame: &mut PendingFrame,
+    (v, sl): (&mut Vec<u8>, &mut Vec<u8>),
+    ts: (usize, usize),
+    vs: &mut ViewState,
+    b: &Buffer,
+    is: &InputState,
+    sub: &SubModes,
+    has_ss: bool,
+    seg: &str,
+) -> io::Result<()> {
+    let PendingFrame {
+        deadline,
+        view,
+        buffer_info,
+    } = std::mem::take(frame);
+    if deadline.is_none() {
+        return Ok(());
+    }
+    if view {
+        prepare_view_to_buffer(v, ts, vs, b);
+    }
+    prepare_status_line(sl, ts, None, is, sub, b, buffer_info, has_ss, seg);
+    redraw(ui, view.then_some(v.as_slice()), Some(sl))
+}
+
+// -------------------- event --------------------
+// 送出の目的（完了時の後処理に使う）
+enum Sent {
+    Clear(String), // 成功したらバッファをクリア
+    Keep(String),  // 成功したら送出済みにする
+    Quit(String),  // 成功したら終了する
+    Cut(String),   // 成功したら選択範囲を削除する
+    Selection,
+}
+
+enum Event {
+    Key(Key),
+    UnknownKey(Vec<u8>), // termionが解釈できなかったシーケンス
+    CopyDone(Sent, CommandResult),
+    PasteProgress(usize),
+    PasteDone(Result<String, String>),
+    JisyoReloaded(io::Result<Jisyo>),
+    JisyoAttached(String, io::Result<Jisyo>),
+    ReloadSignal,  // SIGUSR1
+    SuspendSignal, // SIGTSTP
+    RomajiTableChanged,
+    Control(Request, Sender<Reply>), // 制御用ソケットからのコマンドと応答の返し先
+    HookFailed(String),              // EVENT_HOOKやEVENT_LOGの失敗
+}
+
+fn spawn_key_reader(keys: Keys, tx: Sender<Event>) {
+    thread::spawn(move || {
+        for k in keys {
+            let ev = match k {
+                Input::Key(k) => Event::Key(k),
+                Input::Unknown(raw) => Event::UnknownKey(raw),
+                Input::Control(req, reply) => Event::Control(req, reply),
+            };
+            if tx.send(ev).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn spawn_copy_to(text: String, sent: Sent, config: &Config, tx: &Sender<Event>) {
+    let tx = tx.clone();
+    copy_to_command(text, config, move |r| {
+        let _ = tx.send(Event::CopyDone(sent, r));
+    });
+}
+
+// エンジンの出来事に入力モードの切り替わりを足して、EVENT_HOOKとEVENT_LOGに渡す
+fn emit_events(
+    is: &InputState,
+    sub: &mut SubModes,
+    mode: &mut Option<&'static str>,
+    hook: Option<&Sender<Vec<EngineEvent>>>,
+) {
+    let mut events = mem::take(&mut sub.events);
+    if let Some(m) = hook::input_mode(is)
+        && *mode != Some(m)
+    {
+        events.push(EngineEvent::Mode(m));
+        *mode = Some(m);
+    }
+    if let Some(hook) = hook
+        && !events.is_empty()
+    {
+        let _ = hook.send(events);
     }
-    let _ = child.wait();
 }
 
-fn copy_from_command(shell: &str, cmd: &str) -> String {
-    let out = Command::new(shell)
-        .arg("-c")
-        .arg(cmd)
-        .output()
-        .expect("command CPY_FROM failure");
-    String::from_utf8_lossy(&out.stdout).to_string()
+fn spawn_copy_from(config: &Config, tx: &Sender<Event>) {
+    let (tx_progress, tx_done) = (tx.clone(), tx.clone());
+    copy_from_command(
+        config,
+        move |n| {
+            let _ = tx_progress.send(Event::PasteProgress(n));
+        },
+        move |r| {
+            let _ = tx_done.send(Event::PasteDone(r));
+        },
+    );
+}
+
+fn spawn_attach_jisyo(spec: &str, config: &Config, tx: &Sender<Event>) {
+    let (spec, order, tx) = (spec.to_string(), config.candidate_order, tx.clone());
+    thread::spawn(move || {
+        let r = Jisyo::load(&spec, order);
+        let _ = tx.send(Event::JisyoAttached(spec, r));
+    });
+}
+
+fn spawn_reload_jisyo(config: &Config, tx: &Sender<Event>) {
+    let (config, tx) = (config.clone(), tx.clone());
+    thread::spawn(move || {
+        // 読み込みが異常終了しても、読み込み中の表示が残らないよう失敗として知らせる
+        let r = std::panic::catch_unwind(|| Jisyo::from_config(&config))
+            .unwrap_or_else(|_| Err(io::Error::other("dictionary loader panicked")));
+        let _ = tx.send(Event::JisyoReloaded(r));
+    });
+}
+
+// シグナルをイベントにする（登録に失敗したらシグナルは受け付けない）
+fn spawn_watch_signals(tx: &Sender<Event>) {
+    let Ok(signals) = signal::signals() else {
+        return;
+    };
+    let tx = tx.clone();
+    thread::spawn(move || {
+        for sig in signals {
+            let ev = match sig {
+                Signal::Usr1 => Event::ReloadSignal,
+                Signal::Tstp => Event::SuspendSignal,
+            };
+            if tx.send(ev).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// コマンドをメインループに渡し、処理が終わるまで待って応答する
+fn spawn_control(socket: &ControlSocket, tx: &Sender<Event>) -> io::Result<()> {
+    let tx = tx.clone();
+    socket.serve(move |req| {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        tx.send(Event::Control(req, reply_tx))
+            .map_err(|_| String::from("shutting down"))?;
+        reply_rx
+            .recv()
+            .unwrap_or_else(|_| Err(String::from("shutting down")))
+    })
+}
+
+// 端末を元の画面・カーソル表示・通常モードに戻してから止まり、再開したら戻す
+// 再開後の描画は呼び出し側で行う（止まっている間に端末のサイズが変わりうる）
+fn suspend<T: Terminal>(ui: &mut T) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    push_str_to_vec_u8(&mut buf, CURSOR_SHOW);
+    push_str_to_vec_u8(&mut buf, &termion::screen::ToMainScreen.to_string());
+    ui.write_all(&buf)?;
+    ui.flush()?;
+    ui.set_raw_mode(false)?;
+    signal::stop();
+    ui.set_raw_mode(true)?;
+    ui.write_all(termion::screen::ToAlternateScreen.to_string().as_bytes())?;
+    ui.write_all(CURSOR_HIDE.as_bytes())
+}
+
+// ローマ字表の更新時刻を定期的に確認し、変わったら通知する
+fn spawn_watch_romaji_table(path: String, tx: &Sender<Event>) {
+    let tx = tx.clone();
+    let modified = |path: &str| -> Option<SystemTime> { fs::metadata(path).ok()?.modified().ok() };
+    thread::spawn(move || {
+        let mut last = modified(&path);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let now = modified(&path);
+            // 書き込み途中で消えている間（保存時の置き換えなど）は次の確認を待つ
+            if now.is_some() && now != last {
+                last = now;
+                if tx.send(Event::RomajiTableChanged).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn reload_message(r: &io::Result<Jisyo>, old: &Jisyo) -> String {
+    match r {
+        Ok(_) if old.is_loading() => String::from("辞書を読み込みました"),
+        Err(e) if old.is_loading() => format!("辞書の読み込みに失敗: {}", e),
+        Ok(new) => {
+            let diff = old.diff(new);
+            let mut msg = String::from("辞書を再読み込みしました: 追加 ");
+            push_itoa_usize_to_string(&mut msg, diff.added, 10);
+            msg.push_str(" / 削除 ");
+            push_itoa_usize_to_string(&mut msg, diff.removed, 10);
+            msg.push_str(" / 変更 ");
+            push_itoa_usize_to_string(&mut msg, diff.changed, 10);
+            msg
+        }
+        Err(e) => format!("辞書の再読み込みに失敗: {}", e),
+    }
+}
+
+// 名前の辞書があれば無効・有効を切り替え、なければパスとみなして別スレッドで読み込んで足す
+// 空なら辞書の一覧を出す
+fn switch_jisyo(jisyo: &mut Jisyo, input: &str, config: &Config, tx: &Sender<Event>) -> String {
+    if input.is_empty() {
+        let (enabled, disabled) = jisyo.dict_names();
+        let mut names: Vec<String> = enabled
+            .iter()
+            .map(|(tag, attached)| match attached {
+                true => format!("{}（追加）", tag),
+                false => tag.to_string(),
+            })
+            .collect();
+        names.extend(disabled.iter().map(|tag| format!("{}（無効）", tag)));
+        return format!("辞書: {}", names.join(", "));
+    }
+    match jisyo.toggle_dict(input) {
+        Some(true) => format!("辞書を有効にしました: {}", input),
+        Some(false) => format!("辞書を無効にしました: {}", input),
+        None => {
+            spawn_attach_jisyo(input, config, tx);
+            format!("辞書を読み込んでいます: {}", input)
+        }
+    }
+}
+
+// 候補がwordそのものの見出し語があればその読みを、なければwordを含む語と読みを並べる
+fn reading_message(word: &str, found: &[(String, String)]) -> String {
+    let exact: Vec<&str> = found
+        .iter()
+        .filter(|(_, cand)| cand == word)
+        .map(|(yomi, _)| yomi.as_str())
+        .collect();
+    if found.is_empty() {
+        return format!("読みが見つかりません: {}", word);
+    }
+    let (head, items): (_, Vec<String>) = match exact.is_empty() {
+        false => ("の読み", exact.iter().map(|y| y.to_string()).collect()),
+        true => (
+            "を含む語",
+            found.iter().map(|(y, c)| format!("{}({})", c, y)).collect(),
+        ),
+    };
+    let mut msg = format!("{} {}: ", word, head);
+    msg.push_str(&items[..items.len().min(READING_MAX)].join(", "));
+    if items.len() > READING_MAX {
+        msg.push_str(", …");
+    }
+    msg
+}
+
+// UNKNOWN_KEY_LOGに1行ずつ追記する
+fn log_unknown_key(path: &str, seq: &str) -> io::Result<()> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(f, "{}", seq)
+}
+
+fn recv_event(rx: &Receiver<Event>, deadline: Option<Instant>) -> Result<Event, RecvTimeoutError> {
+    match deadline {
+        Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
 }
 
 // -------------------- snapshot --------------------
@@ -588,58 +1306,622 @@ pub fn cleanup<W: Write>(out: &mut W) -> io::Result<()> {
     out.flush()
 }
 
-pub fn run<W, R>(
-    mut ui: W,
-    input: R,
-    jisyo: Jisyo,
-    shell: &str,
-    cpyt: &str,
-    cpyf: &str,
-) -> io::Result<()>
-where
-    W: Write,
-    R: Read,
-{
+// runを終えたときのバッファとユーザー辞書
+pub struct Exit {
+    pub text: String,          // 終了時のバッファの内容
+    pub saved: io::Result<()>, // ユーザー辞書の保存（失敗してもtextは返す）
+}
+
+// initialはあらかじめバッファに入れておくテキスト（送出済みとして扱う）
+pub fn run<T: Terminal>(
+    ui: &mut T,
+    mut jisyo: Jisyo,
+    config: &Config,
+    initial: &str,
+) -> io::Result<Exit> {
     let mut b = Buffer::default();
+    if !initial.is_empty() {
+        b.insert_text(initial);
+        b.mark_sent();
+    }
+    // 閲覧は先頭から
+    if config.view_only {
+        while b.move_up() {}
+        b.to_line_head();
+    }
     let mut ss = Buffer::default();
     let mut is = InputState::new_kana();
-    let mut vs = ViewState::default();
+    let mut sub = SubModes {
+        show_source: config.show_candidate_source,
+        preview: config.candidate_preview,
+        ..Default::default()
+    };
+    let mut input_mode = hook::input_mode(&is); // 起動時のモードは知らせない
+    let mut vs = ViewState {
+        ruler: config.ruler_width,
+        mask: config.masked,
+        top: config.view_only.then_some(0),
+        ..Default::default()
+    };
     let mut has_ss = false;
+    let mut prompt: Option<Prompt> = None; // ステータス行で入力中の検索語・行番号・確認
+    let mut history = History::default();
+    let mut query: Vec<char> = Vec::new(); // 最後に検索した語
+    let mut reloading = jisyo.is_loading();
+    let mut key_info = false; // Ctrl+Yで切り替える
+    let mut last_key: Option<(String, String, Instant)> = None; // キー、扱い、受け取った時刻
+    let mut key_info_line = String::new();
+    let mut ignored_keys = 0usize;
+    let mut idle_deadline: Option<Instant> = None;
+    let mut romaji_deadline: Option<Instant> = None;
+    let mut tick_deadline = (!config.status_segments.is_empty()).then(status::next_tick);
+    let mut seg = status_segments(config, jisyo.is_loading());
+    let mut frame = PendingFrame::default();
+    let mut last_frame = Instant::now(); // キー入力で最後に描いた時刻
+    let mut queued: Option<Event> = None; // 描く前に受け取っておいた次の入力
 
     ui.write_all(CURSOR_HIDE.as_bytes())?;
     ui.flush()?;
 
-    let mut ts = get_terminal_size();
+    let mut ts = get_terminal_size(ui, config);
     let mut too_small = is_terminal_too_small(ts);
     let mut sl: Vec<u8> = Vec::new();
     let mut v: Vec<u8> = Vec::new();
     if !too_small {
         prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-        prepare_status_line(&mut sl, ts, None, &is, None, has_ss);
-        redraw(&mut ui, Some(&v), Some(&sl))?;
+        prepare_status_line(&mut sl, ts, None, &is, &sub, &b, false, has_ss, &seg);
+        redraw(ui, Some(&v), Some(&sl))?;
     } else {
-        draw_terminal_too_small(&mut ui)?;
+        draw_terminal_too_small(ui)?;
     }
 
-    for key in input.keys() {
-        let k = match key {
-            Ok(k) => k,
-            Err(_) => continue,
+    let (tx, rx) = mpsc::channel();
+    spawn_key_reader(ui.keys()?, tx.clone());
+    if let Some(path) = &config.romaji_table_path {
+        spawn_watch_romaji_table(path.clone(), &tx);
+    }
+    spawn_watch_signals(&tx);
+    let hook = {
+        let tx = tx.clone();
+        hook::spawn(config, move |e| _ = tx.send(Event::HookFailed(e)))
+    };
+    // ソケットが作れなくても編集はできるので、ステータス行で知らせるだけにする
+    let _control = match &config.control_socket {
+        Some(path) => match control::bind(path).and_then(|c| spawn_control(&c, &tx).map(|_| c)) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                if !too_small {
+                    let msg = format!("制御用ソケットを開けません: {}", e);
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                None
+            }
+        },
+        None => None,
+    };
+    if reloading {
+        spawn_reload_jisyo(config, &tx);
+    }
+
+    loop {
+        // 前のキーの処理が描画まで終わったので、かかった時間が決まる
+        if let Some((k, class, at)) = last_key.take() {
+            let us = at.elapsed().as_micros();
+            key_info_line = format!("{} => {} {}us 無視:{}", k, class, us, ignored_keys);
+        }
+        if key_info && !too_small {
+            draw_key_info(ui, ts.0, &key_info_line)?;
+        }
+        let deadline = [
+            idle_deadline,
+            romaji_deadline,
+            tick_deadline,
+            frame.deadline,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let ev = match queued.take() {
+            Some(ev) => Ok(ev),
+            None => recv_event(&rx, deadline),
+        };
+        // 後回しにした画面は、ほかの描画より先に描く（キーは、間引ける入力かを見てから）
+        let frame_due = frame.deadline.is_some_and(|d| d <= Instant::now());
+        if !matches!(ev, Ok(Event::Key(_))) && !too_small {
+            let bufs = (&mut v, &mut sl);
+            draw_pending_frame(
+                ui, &mut frame, bufs, ts, &mut vs, &b, &is, &sub, has_ss, &seg,
+            )?;
+        }
+        let k = match ev {
+            Ok(Event::Key(k)) => k,
+            Ok(Event::UnknownKey(raw)) => {
+                // 割り当てられるように、受け取ったシーケンスをそのまま見せる
+                let seq = caret_notation(&raw);
+                ignored_keys += 1;
+                last_key = Some((seq.clone(), String::from(KEY_IGNORED), Instant::now()));
+                let mut msg = format!("未対応のキー: {}", seq);
+                if let Some(path) = &config.unknown_key_log
+                    && let Err(e) = log_unknown_key(path, &seq)
+                {
+                    msg = format!("{}（ログの書き込みに失敗: {}）", msg, e);
+                }
+                if !too_small {
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                continue;
+            }
+            Ok(Event::CopyDone(sent, r)) => {
+                // 送出後に編集された場合はクリアしない
+                let mut view: Option<&[u8]> = None;
+                let mut save_err = None;
+                match (&sent, &r) {
+                    (Sent::Clear(text), Ok(())) if *text == b.as_string() => {
+                        take_snapshot(&mut has_ss, &b, &mut ss);
+                        b.mark_sent();
+                        b.clear();
+                        if !too_small {
+                            prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                            view = Some(&v);
+                        }
+                        // 区切りごとに学習を保存する（異常終了しても失われない）
+                        if let Err(e) = jisyo.save_user() {
+                            save_err = Some(format!("ユーザー辞書の保存に失敗: {}", e));
+                        }
+                    }
+                    (Sent::Keep(text), Ok(())) if *text == b.as_string() => b.mark_sent(),
+                    // 送出の後に編集していたら、その内容を失わないよう終了しない
+                    (Sent::Quit(text), Ok(())) if *text == b.as_string() => {
+                        b.mark_sent();
+                        break;
+                    }
+                    // 失敗したら残し、送出の間に選択範囲が変わっていたら削除しない
+                    (Sent::Cut(text), Ok(())) if b.selected_as_string().as_ref() == Some(text) => {
+                        take_snapshot(&mut has_ss, &b, &mut ss);
+                        b.delete();
+                        if !too_small {
+                            prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                            view = Some(&v);
+                        }
+                    }
+                    _ => (),
+                }
+                if !too_small {
+                    let msg = r.as_ref().err().map(|m| m.as_str());
+                    let msg = msg.or(save_err.as_deref());
+                    prepare_status_line(&mut sl, ts, msg, &is, &sub, &b, false, has_ss, &seg);
+                    redraw(ui, view, Some(&sl))?;
+                }
+                continue;
+            }
+            Ok(Event::PasteProgress(n)) => {
+                if !too_small {
+                    let mut msg = String::from("貼り付け中: ");
+                    push_itoa_usize_to_string(&mut msg, n, 10);
+                    msg.push_str(" bytes");
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                continue;
+            }
+            Ok(Event::HookFailed(msg)) => {
+                if too_small {
+                    continue;
+                }
+                prepare_status_line(&mut sl, ts, Some(&msg), &is, &sub, &b, false, has_ss, &seg);
+                redraw(ui, None, Some(&sl))?;
+                continue;
+            }
+            Ok(Event::PasteDone(r)) => {
+                // 端末が小さくても貼り付けは本文に入れる（描画だけを控える）
+                let (msg, dirty) = match r {
+                    Ok(text) => {
+                        take_snapshot(&mut has_ss, &b, &mut ss);
+                        paste(&mut is, &mut b, &text);
+                        (None, true)
+                    }
+                    Err(msg) => (Some(msg), false),
+                };
+                if !too_small {
+                    if dirty {
+                        prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                    }
+                    let msg = msg.as_deref();
+                    prepare_status_line(&mut sl, ts, msg, &is, &sub, &b, dirty, has_ss, &seg);
+                    redraw(ui, dirty.then_some(&v), Some(&sl))?;
+                }
+                continue;
+            }
+            Ok(Event::Control(req, reply)) => {
+                let mut dirty = false;
+                let r = match req {
+                    // 伏せ字の本文はソケットからも読ませない
+                    Request::GetBuffer if config.masked => Err(String::from("masked")),
+                    Request::GetBuffer => Ok(b.as_string()),
+                    Request::GetMode => Ok(String::from(is.mode_name())),
+                    _ if prompt.is_some() => Err(String::from("busy: prompt is active")),
+                    Request::Insert(_) if config.view_only => Err(String::from("view only")),
+                    Request::Insert(text) => {
+                        take_snapshot(&mut has_ss, &b, &mut ss);
+                        paste(&mut is, &mut b, &text);
+                        dirty = true;
+                        Ok(String::new())
+                    }
+                    // 読みや候補を選んでいる途中で切り替えると、入力が失われる
+                    Request::SetMode(_) if !is.is_idle() => {
+                        Err(format!("busy: {}", is.mode_name()))
+                    }
+                    Request::SetMode(mode) => {
+                        is = match mode {
+                            Mode::Kana => InputState::new_kana(),
+                            Mode::Katakana => InputState::Kana {
+                                romaji: String::new(),
+                                state: KanaState::Katakana,
+                            },
+                            Mode::Latin => InputState::Latin,
+                        };
+                        emit_events(&is, &mut sub, &mut input_mode, hook.as_ref());
+                        Ok(String::new())
+                    }
+                };
+                let _ = reply.send(r);
+                if !too_small {
+                    if dirty {
+                        prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                    }
+                    prepare_status_line(&mut sl, ts, None, &is, &sub, &b, dirty, has_ss, &seg);
+                    redraw(ui, dirty.then_some(&v), Some(&sl))?;
+                }
+                continue;
+            }
+            // 端末を戻さずに止まると、シェルに戻っても画面が壊れたままになる
+            Ok(Event::SuspendSignal) => {
+                suspend(ui)?;
+                ts = get_terminal_size(ui, config);
+                too_small = is_terminal_too_small(ts);
+                if too_small {
+                    draw_terminal_too_small(ui)?;
+                    continue;
+                }
+                vs.ignore_inactive_lines = false;
+                prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                prepare_status_line(&mut sl, ts, None, &is, &sub, &b, true, has_ss, &seg);
+                redraw(ui, Some(&v), Some(&sl))?;
+                ui.write_all(CURSOR_HIDE.as_bytes())?;
+                continue;
+            }
+            Ok(Event::ReloadSignal) => {
+                if !std::mem::replace(&mut reloading, true) {
+                    spawn_reload_jisyo(config, &tx);
+                }
+                continue;
+            }
+            Ok(Event::JisyoReloaded(r)) => {
+                reloading = false;
+                let mut msg = reload_message(&r, &jisyo);
+                if let Ok(mut new) = r {
+                    new.take_user_from(&mut jisyo);
+                    new.take_session_from(&mut jisyo);
+                    jisyo = new;
+                    // 外で編集されたユーザー辞書も取り込む（未保存の学習は残す）
+                    if let Err(e) = jisyo.reload_user() {
+                        msg = format!("{}（ユーザー辞書の読み込みに失敗: {}）", msg, e);
+                    }
+                }
+                seg = status_segments(config, false);
+                if !too_small {
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                continue;
+            }
+            Ok(Event::JisyoAttached(spec, r)) => {
+                let msg = match r {
+                    Ok(new) => {
+                        let n = new.entry_count();
+                        let tags = jisyo.attach(new).join(", ");
+                        format!("辞書を追加しました: {}（{}語）", tags, n)
+                    }
+                    Err(e) => format!("辞書を追加できません: {}: {}", spec, e),
+                };
+                if too_small {
+                    continue;
+                }
+                prepare_status_line(&mut sl, ts, Some(&msg), &is, &sub, &b, false, has_ss, &seg);
+                redraw(ui, None, Some(&sl))?;
+                continue;
+            }
+            Ok(Event::RomajiTableChanged) => {
+                // 問題のある表は読み込まず、それまでの表を使い続ける
+                let path = config.romaji_table_path.as_deref();
+                let msg = match load_table(config.romaji_preset, path) {
+                    Err(e) => format!("ローマ字表の再読み込みに失敗: {}", e),
+                    Ok(()) => String::from("ローマ字表を再読み込みしました"),
+                };
+                if !too_small {
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout)
+                if romaji_deadline.is_some_and(|d| d <= Instant::now()) =>
+            {
+                romaji_deadline = None;
+                if too_small {
+                    continue;
+                }
+                // ステータス行の入力中は、その入力のローマ字を片付ける
+                if let Some(p) = prompt.as_mut() {
+                    p.flush_romaji(&sub, config.romaji_timeout_action);
+                    prepare_prompt_line(&mut sl, ts, &prompt, None, &is, &sub, &b, has_ss, &seg);
+                    redraw(ui, None, Some(&sl))?;
+                    continue;
+                }
+                b.clear_dirty();
+                is = flush_romaji(is, &mut b, &sub, config.romaji_timeout_action);
+                if b.is_dirty() {
+                    prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                }
+                prepare_status_line(&mut sl, ts, None, &is, &sub, &b, false, has_ss, &seg);
+                redraw(ui, b.is_dirty().then_some(&v), Some(&sl))?;
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) if frame_due => {
+                last_frame = Instant::now();
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout)
+                if tick_deadline.is_some_and(|d| d <= Instant::now()) =>
+            {
+                // 表示中のメッセージを消さないよう、幅が変わらなければ右端だけを書き直す
+                tick_deadline = Some(status::next_tick());
+                let new = status_segments(config, jisyo.is_loading() && reloading);
+                if too_small || new == seg {
+                    seg = new;
+                    continue;
+                }
+                let old_col = segments_col(ts.0, &seg);
+                seg = new;
+                match segments_col(ts.0, &seg) {
+                    Some(col) if Some(col) == old_col => {
+                        let mut out = Vec::new();
+                        push_status_segments(&mut out, ts, col, &seg);
+                        redraw(ui, None, Some(&out))?;
+                    }
+                    _ => {
+                        prepare_status_line(&mut sl, ts, None, &is, &sub, &b, false, has_ss, &seg);
+                        redraw(ui, None, Some(&sl))?;
+                    }
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // 一定時間入力がなければ、クリアせずに送出
+                idle_deadline = None;
+                if b.has_unsent() {
+                    let text = b.as_string();
+                    spawn_copy_to(text.clone(), Sent::Keep(text), config, &tx);
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let class = match &prompt {
+            Some(p) => String::from(p.kind.description()),
+            None => classify_key(&k, &is, config),
         };
-        if let Some(cmd) = to_front_cmd(&k) {
+        let throttled = prompt.is_none()
+            && class != KEY_IGNORED
+            && !config.view_only
+            && to_front_cmd(&k).is_none();
+        if !throttled && !too_small {
+            let bufs = (&mut v, &mut sl);
+            draw_pending_frame(
+                ui, &mut frame, bufs, ts, &mut vs, &b, &is, &sub, has_ss, &seg,
+            )?;
+        }
+        if class == KEY_IGNORED {
+            ignored_keys += 1;
+            // 割り当てのない特殊キー（Ctrl+矢印など）は、何も起きなかったことを知らせる
+            if !matches!(k, Key::Char(_)) && !too_small {
+                let msg = format!("割り当てのないキー: {:?}", k);
+                prepare_status_line(&mut sl, ts, Some(&msg), &is, &sub, &b, false, has_ss, &seg);
+                redraw(ui, None, Some(&sl))?;
+            }
+        }
+        let (key, class) = describe_key(&k, class, config.masked);
+        last_key = Some((key, class, Instant::now()));
+        idle_deadline = config.auto_send.map(|d| Instant::now() + d);
+        // ステータス行の入力中：キーは入力のバッファに渡す（かな漢字変換もできる）
+        // Ctrl+Qは入力を取り消して、いつもの終了の操作として扱う
+        if let Some(mut p) = prompt.take() {
+            let quit = k == Key::Ctrl('q') && p.kind != PromptKind::Confirm;
+            let result = match quit {
+                true => PromptResult::Cancel,
+                false => p.handle(&k, &mut jisyo, &mut sub, &history),
+            };
+            emit_events(&is, &mut sub, &mut input_mode, hook.as_ref());
+            if p.take_bell() {
+                ui.write_all(BELL.as_bytes())?;
+            }
+            b.clear_dirty();
+            let mut msg = None;
+            match result {
+                PromptResult::Pending => prompt = Some(p),
+                PromptResult::Submit(_) if p.kind == PromptKind::Confirm => break,
+                PromptResult::Submit(text) => {
+                    history.push(p.kind, &text);
+                    match p.kind {
+                        PromptKind::Search => {
+                            query = text.chars().collect();
+                            msg = find_query(&mut b, &query, true);
+                        }
+                        PromptKind::GotoLine => match text.trim().parse::<usize>() {
+                            Ok(n) if n > 0 => b.goto_line(n - 1),
+                            _ => msg = Some(format!("行番号ではありません: {}", text)),
+                        },
+                        PromptKind::Jisyo => {
+                            msg = Some(switch_jisyo(&mut jisyo, text.trim(), config, &tx));
+                        }
+                        PromptKind::Confirm => (),
+                    }
+                }
+                PromptResult::Cancel => (),
+            }
+            if b.take_bell() {
+                ui.write_all(BELL.as_bytes())?;
+            }
+            let pending = prompt.as_ref().is_some_and(Prompt::has_pending_romaji);
+            romaji_deadline = config
+                .romaji_timeout
+                .filter(|_| pending)
+                .map(|d| Instant::now() + d);
+            if !quit {
+                if !too_small {
+                    if b.is_dirty() {
+                        prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                    }
+                    prepare_prompt_line(
+                        &mut sl,
+                        ts,
+                        &prompt,
+                        msg.as_deref(),
+                        &is,
+                        &sub,
+                        &b,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, b.is_dirty().then_some(&v), Some(&sl))?;
+                }
+                continue;
+            }
+        }
+        // 1行入力：読みや変換の途中でなければEnterで終了する
+        if config.single_line && k == Key::Char('\n') && is.is_idle() {
+            break;
+        }
+        // 閲覧のみ：1文字のキーで検索などを行う
+        if let Some(cmd) = to_view_cmd(&k).filter(|_| config.view_only && !too_small) {
+            b.clear_dirty();
+            let mut msg = None;
+            match cmd {
+                ViewCmd::Quit => break,
+                ViewCmd::Search => prompt = Some(Prompt::new(PromptKind::Search, MSG_SEARCH)),
+                ViewCmd::SearchNext => msg = find_query(&mut b, &query, true),
+                ViewCmd::SearchPrev => msg = find_query(&mut b, &query, false),
+            }
+            if b.take_bell() {
+                ui.write_all(BELL.as_bytes())?;
+            }
+            if b.is_dirty() {
+                prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+            }
+            prepare_prompt_line(
+                &mut sl,
+                ts,
+                &prompt,
+                msg.as_deref(),
+                &is,
+                &sub,
+                &b,
+                has_ss,
+                &seg,
+            );
+            redraw(ui, b.is_dirty().then_some(&v), Some(&sl))?;
+            continue;
+        }
+        if let Some(cmd) = to_front_cmd(&k).filter(|c| !config.view_only || is_read_only(c)) {
             match cmd {
-                FrontCmd::Quit => break,
-                FrontCmd::Refresh => {
-                    ts = get_terminal_size();
+                FrontCmd::Quit => {
+                    // 終了時に出力するなら、未送出でも失われない
+                    if too_small || !b.has_unsent() || config.print_on_exit {
+                        break;
+                    }
+                    if config.quit_action == QuitAction::Send {
+                        let text = b.as_string();
+                        spawn_copy_to(text.clone(), Sent::Quit(text), config, &tx);
+                        continue;
+                    }
+                    prompt = Some(Prompt::new(PromptKind::Confirm, MSG_QUIT_CONFIRM));
+                    prepare_prompt_line(&mut sl, ts, &prompt, None, &is, &sub, &b, has_ss, &seg);
+                    redraw(ui, None, Some(&sl))?;
+                }
+                // 空なら送るものがないのでそのまま終了する
+                FrontCmd::SendAndQuit => {
+                    if b.is_empty() {
+                        break;
+                    }
+                    let text = b.as_string();
+                    spawn_copy_to(text.clone(), Sent::Quit(text), config, &tx);
+                }
+                FrontCmd::Refresh | FrontCmd::Suspend => {
+                    if matches!(cmd, FrontCmd::Suspend) {
+                        suspend(ui)?;
+                    }
+                    ts = get_terminal_size(ui, config);
                     too_small = is_terminal_too_small(ts);
                     if too_small {
-                        draw_terminal_too_small(&mut ui)?;
+                        draw_terminal_too_small(ui)?;
                         continue;
                     }
                     vs.ignore_inactive_lines = false;
                     prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                    prepare_status_line(&mut sl, ts, None, &is, Some(&b), has_ss);
-                    redraw(&mut ui, Some(&v), Some(&sl))?;
+                    prepare_status_line(&mut sl, ts, None, &is, &sub, &b, true, has_ss, &seg);
+                    redraw(ui, Some(&v), Some(&sl))?;
                     ui.write_all(CURSOR_HIDE.as_bytes())?;
                 }
 
@@ -648,46 +1930,116 @@ where
                     take_snapshot(&mut has_ss, &b, &mut ss);
                     b.clear();
                     prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                    prepare_status_line(&mut sl, ts, None, &is, None, has_ss);
-                    redraw(&mut ui, Some(&v), Some(&sl))?;
+                    prepare_status_line(&mut sl, ts, None, &is, &sub, &b, false, has_ss, &seg);
+                    redraw(ui, Some(&v), Some(&sl))?;
                 }
                 FrontCmd::SendAndClear => {
-                    take_snapshot(&mut has_ss, &b, &mut ss);
-                    copy_to_command(&b.as_string(), shell, cpyt);
-                    b.clear();
-                    prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                    prepare_status_line(&mut sl, ts, None, &is, None, has_ss);
-                    redraw(&mut ui, Some(&v), Some(&sl))?;
+                    // クリアは送出の成功後（CopyDone）
+                    let text = b.as_string();
+                    spawn_copy_to(text.clone(), Sent::Clear(text), config, &tx);
                 }
-                FrontCmd::Paste => {
-                    take_snapshot(&mut has_ss, &b, &mut ss);
-                    b.insert_str(&copy_from_command(shell, cpyf));
-                    prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                    prepare_status_line(&mut sl, ts, None, &is, Some(&b), has_ss);
-                    redraw(&mut ui, Some(&v), Some(&sl))?;
+                FrontCmd::Send => {
+                    let text = b.as_string();
+                    spawn_copy_to(text.clone(), Sent::Keep(text), config, &tx);
+                }
+                FrontCmd::Paste => spawn_copy_from(config, &tx),
+                FrontCmd::ReloadJisyo => {
+                    if !std::mem::replace(&mut reloading, true) {
+                        spawn_reload_jisyo(config, &tx);
+                    }
+                    let msg = "辞書を再読み込み中…";
+                    prepare_status_line(&mut sl, ts, Some(msg), &is, &sub, &b, false, has_ss, &seg);
+                    redraw(ui, None, Some(&sl))?;
                 }
                 FrontCmd::CopySelected => {
                     if let Some(s) = b.selected_as_string() {
-                        copy_to_command(&s, shell, cpyt);
+                        spawn_copy_to(s, Sent::Selection, config, &tx);
                     }
                 }
                 FrontCmd::CutSelected => {
+                    // 削除は送出が成功してから（CopyDone）
                     if let Some(s) = b.selected_as_string() {
-                        take_snapshot(&mut has_ss, &b, &mut ss);
-                        copy_to_command(&s, shell, cpyt);
-                        b.delete();
-                        prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                        prepare_status_line(&mut sl, ts, None, &is, Some(&b), has_ss);
-                        redraw(&mut ui, Some(&v), Some(&sl))?;
+                        spawn_copy_to(s.clone(), Sent::Cut(s), config, &tx);
                     }
                 }
                 FrontCmd::PrintCodePoint => {
-                    if let Some(c) = b.cursor_as_char() {
+                    if let Some(c) = b.cursor_as_char().filter(|_| !config.masked) {
                         let mut cp = String::from("[U+");
                         push_itoa_usize_to_string(&mut cp, *c as usize, 16);
                         cp.push(']');
-                        prepare_status_line(&mut sl, ts, Some(&cp), &is, Some(&b), has_ss);
-                        redraw(&mut ui, None, Some(&sl))?;
+                        prepare_status_line(
+                            &mut sl,
+                            ts,
+                            Some(&cp),
+                            &is,
+                            &sub,
+                            &b,
+                            true,
+                            has_ss,
+                            &seg,
+                        );
+                        redraw(ui, None, Some(&sl))?;
+                    }
+                }
+                FrontCmd::PrintReading => {
+                    if let Some(word) = b.selected_as_string().filter(|_| !config.masked) {
+                        let msg = reading_message(&word, &jisyo.reverse_lookup(&word));
+                        prepare_status_line(
+                            &mut sl,
+                            ts,
+                            Some(&msg),
+                            &is,
+                            &sub,
+                            &b,
+                            true,
+                            has_ss,
+                            &seg,
+                        );
+                        redraw(ui, None, Some(&sl))?;
+                    }
+                }
+                FrontCmd::Wrap => {
+                    take_snapshot(&mut has_ss, &b, &mut ss);
+                    b.reflow(|text| wrap(text, config.wrap_width));
+                    prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                    prepare_status_line(&mut sl, ts, None, &is, &sub, &b, true, has_ss, &seg);
+                    redraw(ui, Some(&v), Some(&sl))?;
+                }
+                FrontCmd::GotoLine => {
+                    prompt = Some(Prompt::new(PromptKind::GotoLine, MSG_GOTO_LINE));
+                    prepare_prompt_line(&mut sl, ts, &prompt, None, &is, &sub, &b, has_ss, &seg);
+                    redraw(ui, None, Some(&sl))?;
+                }
+                FrontCmd::SwitchJisyo => {
+                    prompt = Some(Prompt::new(PromptKind::Jisyo, MSG_JISYO));
+                    prepare_prompt_line(&mut sl, ts, &prompt, None, &is, &sub, &b, has_ss, &seg);
+                    redraw(ui, None, Some(&sl))?;
+                }
+                FrontCmd::RestorePurged => {
+                    let msg = match jisyo.restore_purged() {
+                        Some((yomi, cand)) => format!("削除を取り消しました: {} /{}/", yomi, cand),
+                        None => String::from("取り消せる削除はありません"),
+                    };
+                    prepare_status_line(
+                        &mut sl,
+                        ts,
+                        Some(&msg),
+                        &is,
+                        &sub,
+                        &b,
+                        false,
+                        has_ss,
+                        &seg,
+                    );
+                    redraw(ui, None, Some(&sl))?;
+                }
+                FrontCmd::ToggleKeyInfo => {
+                    key_info = !key_info;
+                    // 消すときは重ねていた1行目を描き直す
+                    if !key_info {
+                        vs.ignore_inactive_lines = false;
+                        prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                        redraw(ui, Some(&v), None)?;
                     }
                 }
                 FrontCmd::Undo => {
@@ -696,31 +2048,76 @@ where
                     }
                     (b, ss) = (ss, b);
                     prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                    prepare_status_line(&mut sl, ts, None, &is, Some(&b), has_ss);
-                    redraw(&mut ui, Some(&v), Some(&sl))?;
+                    prepare_status_line(&mut sl, ts, None, &is, &sub, &b, true, has_ss, &seg);
+                    redraw(ui, Some(&v), Some(&sl))?;
                 }
             }
         }
         if let Some(ev) = to_key_event_with_state(&is, &k)
             && !too_small
+            && (!config.view_only || matches!(ev, KeyEvent::Navigation(_)))
         {
             b.clear_dirty();
-            is = handle_key(is, &mut b, &jisyo, ev);
-            let view: Option<&[u8]> = if b.is_dirty() {
-                prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
-                Some(&v)
-            } else {
-                None
-            };
-            if let KeyEvent::Navigation(_) = ev {
-                prepare_status_line(&mut sl, ts, None, &is, Some(&b), has_ss);
-            } else {
+            is = handle_key(is, &mut b, &mut jisyo, &mut sub, ev);
+            emit_events(&is, &mut sub, &mut input_mode, hook.as_ref());
+            if b.take_bell() {
+                ui.write_all(BELL.as_bytes())?;
+            }
+            let buffer_info = matches!(ev, KeyEvent::Navigation(_));
+            if !buffer_info {
                 drop_snapshot(&mut has_ss, &mut ss);
-                prepare_status_line(&mut sl, ts, None, &is, None, has_ss);
-            };
-            redraw(&mut ui, view, Some(&sl))?;
+            }
+            // 次の入力が届いていれば、まとめて処理してから描く（キーリピートや貼り付け）
+            // 前に描いてから間もないときも、描くのを後回しにして次のキーを処理する
+            if queued.is_none() {
+                queued = rx.try_recv().ok();
+            }
+            let recent = last_frame.elapsed() < FRAME_INTERVAL;
+            if frame.deadline.is_none() && queued.is_none() && !recent {
+                let view: Option<&[u8]> = if b.is_dirty() {
+                    prepare_view_to_buffer(&mut v, ts, &mut vs, &b);
+                    Some(&v)
+                } else {
+                    None
+                };
+                prepare_status_line(&mut sl, ts, None, &is, &sub, &b, buffer_info, has_ss, &seg);
+                redraw(ui, view, Some(&sl))?;
+                last_frame = Instant::now();
+            } else {
+                if b.is_dirty() {
+                    // 間引いた変更はカーソルの行とは限らないので、全体を描き直させる
+                    track_view(ts, &mut vs, &b);
+                    vs.ignore_inactive_lines = false;
+                    frame.view = true;
+                }
+                frame.buffer_info = buffer_info;
+                // 入力が途切れなくても、描くのはこの時刻まで
+                let base = if recent { last_frame } else { Instant::now() };
+                let due = *frame.deadline.get_or_insert(base + FRAME_INTERVAL);
+                if (queued.is_none() && !recent) || due <= Instant::now() {
+                    let bufs = (&mut v, &mut sl);
+                    draw_pending_frame(
+                        ui, &mut frame, bufs, ts, &mut vs, &b, &is, &sub, has_ss, &seg,
+                    )?;
+                    last_frame = Instant::now();
+                }
+            }
+            // 途中のローマ字が残っていれば、時間切れで片付ける
+            let pending = is.has_pending_romaji();
+            romaji_deadline = config
+                .romaji_timeout
+                .filter(|_| pending)
+                .map(|d| Instant::now() + d);
         }
     }
 
-    cleanup(&mut ui)
+    cleanup(ui)?;
+    // 保存に失敗しても、--print-on-exitで書き出す内容は失わない
+    Ok(Exit {
+        text: b.as_string(),
+        saved: jisyo.save_user(),
+    })
 }
+
+#[cfg(test)]
+mod tests;
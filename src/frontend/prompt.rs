@@ -0,0 +1,184 @@
+//! ステータス行の1行入力（検索語、行番号、辞書、y/nの確認）
+//!
+//! 本文とは別のバッファと入力状態を持つので、入力中もかな漢字変換ができ、本文の読みや変換の途中には影響しない。
+//! `↑`/`↓`で同じ種類の入力の履歴をたどる。
+
+use termion::event::Key;
+
+use super::to_key_event_with_state;
+use crate::{
+    buffer::Buffer,
+    engine::{flush_romaji, handle_key},
+    jisyo::Jisyo,
+    romaji::TimeoutAction,
+    state::{InputState, SubModes},
+};
+
+const HISTORY_MAX: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    Search,
+    GotoLine,
+    Jisyo,   // 足す辞書のパスか、切り替える辞書の名前
+    Confirm, // y/nを1キーで答える
+}
+
+impl PromptKind {
+    // Ctrl+Yのキー診断に出す扱い
+    pub fn description(self) -> &'static str {
+        match self {
+            PromptKind::Search => "検索語の入力",
+            PromptKind::GotoLine => "行番号の入力",
+            PromptKind::Jisyo => "辞書の追加・切り替え",
+            PromptKind::Confirm => "終了の確認",
+        }
+    }
+}
+
+pub enum PromptResult {
+    Pending,
+    Submit(String),
+    Cancel,
+}
+
+pub struct Prompt {
+    pub kind: PromptKind,
+    label: &'static str,
+    buffer: Buffer,
+    state: Option<InputState>, // 確認は文字を入力しないのでNone
+    history_index: Option<usize>,
+    draft: String, // 履歴をたどる前に入力していた内容
+}
+
+// 種類ごとの確定した入力（古い順）
+#[derive(Default)]
+pub struct History(Vec<(PromptKind, String)>);
+
+impl History {
+    pub fn push(&mut self, kind: PromptKind, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        // 同じ入力は新しい位置に1つだけ残す
+        self.0.retain(|(k, t)| *k != kind || t != text);
+        self.0.push((kind, text.to_string()));
+        if self.0.len() > HISTORY_MAX {
+            self.0.remove(0);
+        }
+    }
+
+    fn entries(&self, kind: PromptKind) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, t)| t.as_str())
+            .collect()
+    }
+}
+
+impl Prompt {
+    // 検索語はかなで、行番号と辞書は英数字で入力を始める
+    pub fn new(kind: PromptKind, label: &'static str) -> Self {
+        let state = match kind {
+            PromptKind::Search => Some(InputState::new_kana()),
+            PromptKind::GotoLine | PromptKind::Jisyo => Some(InputState::Latin),
+            PromptKind::Confirm => None,
+        };
+        Self {
+            kind,
+            label,
+            buffer: Buffer::default(),
+            state,
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    // ステータス行に出す内容（ラベルと入力中の内容）
+    pub fn message(&self) -> String {
+        format!("{}{}", self.label, self.buffer.as_string())
+    }
+
+    // ステータス行に出す入力状態（確認ではNone）
+    pub fn state(&self) -> Option<&InputState> {
+        self.state.as_ref()
+    }
+
+    // 途中まで打ったローマ字がある（時間切れで片付ける）
+    pub fn has_pending_romaji(&self) -> bool {
+        self.state
+            .as_ref()
+            .is_some_and(InputState::has_pending_romaji)
+    }
+
+    pub fn flush_romaji(&mut self, sub: &SubModes, action: TimeoutAction) {
+        if let Some(state) = self.state.take() {
+            self.state = Some(flush_romaji(state, &mut self.buffer, sub, action));
+        }
+    }
+
+    pub fn take_bell(&mut self) -> bool {
+        self.buffer.take_bell()
+    }
+
+    // 確認：y か Ctrl+Q（2度押し）で確定、その他のキーは取り消し
+    // 入力：読みや変換の途中でなければ、Enterで確定、Esc / Ctrl+Gで取り消し、↑/↓で履歴
+    pub fn handle(
+        &mut self,
+        k: &Key,
+        jisyo: &mut Jisyo,
+        sub: &mut SubModes,
+        history: &History,
+    ) -> PromptResult {
+        let Some(state) = self.state.take() else {
+            return match k {
+                Key::Char('y') | Key::Char('Y') | Key::Ctrl('q') => {
+                    PromptResult::Submit(String::from("y"))
+                }
+                _ => PromptResult::Cancel,
+            };
+        };
+        let idle = state.is_idle();
+        let (state, result) = match k {
+            Key::Char('\n') if idle => (state, PromptResult::Submit(self.buffer.as_string())),
+            Key::Esc | Key::Ctrl('g') if idle => (state, PromptResult::Cancel),
+            Key::Up | Key::Down if idle => {
+                self.browse(history, *k == Key::Up);
+                (state, PromptResult::Pending)
+            }
+            _ => match to_key_event_with_state(&state, k) {
+                Some(ev) => {
+                    self.history_index = None;
+                    let state = handle_key(state, &mut self.buffer, jisyo, sub, ev);
+                    (state, PromptResult::Pending)
+                }
+                None => (state, PromptResult::Pending),
+            },
+        };
+        self.state = Some(state);
+        result
+    }
+
+    // olderなら1つ古い入力へ、そうでなければ新しい入力へ（最後は入力途中の内容に戻る）
+    fn browse(&mut self, history: &History, older: bool) {
+        let entries = history.entries(self.kind);
+        let next = match (self.history_index, older) {
+            (None, true) if !entries.is_empty() => Some(entries.len() - 1),
+            (Some(i), true) if i > 0 => Some(i - 1),
+            (Some(i), false) if i + 1 < entries.len() => Some(i + 1),
+            (Some(_), false) => None,
+            _ => {
+                self.buffer.ring_bell();
+                return;
+            }
+        };
+        if self.history_index.is_none() {
+            self.draft = self.buffer.as_string();
+        }
+        let text = next.map_or(self.draft.as_str(), |i| entries[i]);
+        self.buffer.clear();
+        self.buffer.insert_str(text);
+        self.history_index = next;
+    }
+}
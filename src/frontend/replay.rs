@@ -0,0 +1,361 @@
+//! 台本の再生（`--replay <script>`）と計測（`--bench <script>`）
+//!
+//! 台本のキー入力と制御コマンド（[`crate::control`]と同じもの）を端末なしでrunに流し込み、
+//! 最後のバッファと画面を書き出す。TUI全体を通した回帰テストに使う。
+//! 同じ台本を繰り返して、キー入力からエンジン・描画までの速さを測ることもできる。
+//!
+//! 台本は1行に1つ：
+//! - `keys <text>`：キー入力（`\e`はEsc、`\xHH`は任意のバイト、ほかに`\n` `\t` `\r` `\\`）
+//! - `sleep <ms>`：入力を止めて待つ（ローマ字のタイムアウトなど）
+//! - それ以外は制御コマンド（`insert`、`get-buffer`、`get-mode`、`set-mode`）で、応答を書き出す
+//! - 空行と`#`で始まる行は読み飛ばす
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Cursor, Error, ErrorKind, Write},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use termion::event::Key;
+
+use super::{CURSOR_SHOW, char_width, run};
+use crate::{
+    config::Config,
+    control::{self, Reply, Request},
+    jisyo::Jisyo,
+    terminal::{Input, Keys, Terminal, read_keys},
+};
+
+#[derive(Clone)]
+enum Step {
+    Keys(Vec<u8>),
+    Sleep(Duration),
+    Command(String, Request), // 書き出す用の元の行と、コマンド
+}
+
+// 台本を順に入力にする（コマンドは処理されるまで次に進まない）
+struct Script {
+    steps: VecDeque<Step>,
+    keys: Option<Keys>,
+    pending: Option<(String, Receiver<Reply>)>,
+    log: Arc<Mutex<String>>, // コマンドと応答
+    done: bool,
+}
+
+impl Script {
+    fn new(steps: Vec<Step>, log: &Arc<Mutex<String>>) -> Self {
+        Self {
+            steps: steps.into(),
+            keys: None,
+            pending: None,
+            log: Arc::clone(log),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Script {
+    type Item = Input;
+
+    fn next(&mut self) -> Option<Input> {
+        loop {
+            if let Some((line, rx)) = self.pending.take() {
+                let reply = rx
+                    .recv()
+                    .unwrap_or_else(|_| Err(String::from("shutting down")));
+                let mut log = self.log.lock().unwrap();
+                log.push_str(&format!("> {}\n{}\n", line, control::reply_line(&reply)));
+            }
+            if let Some(input) = self.keys.as_mut().and_then(Iterator::next) {
+                return Some(input);
+            }
+            self.keys = None;
+            match self.steps.pop_front() {
+                Some(Step::Keys(bytes)) => self.keys = Some(read_keys(Cursor::new(bytes))),
+                Some(Step::Sleep(d)) => thread::sleep(d),
+                Some(Step::Command(line, req)) => {
+                    let (tx, rx) = mpsc::channel();
+                    self.pending = Some((line, rx));
+                    return Some(Input::Control(req, tx));
+                }
+                // headless_configで終了時に出力する設定なので、確認なしに終わる
+                None if !self.done => {
+                    self.done = true;
+                    return Some(Input::Key(Key::Ctrl('q')));
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+/// メモリ上で動く端末（入力は渡されたキー、出力は溜めるだけ）
+///
+/// runをそのまま動かせるので、台本の再生やキー入力から描画までの速さの計測に使う。
+/// サイズは分からないと答えて、`DEFAULT_TERM_SIZE`を使わせる。
+pub struct HeadlessTerminal {
+    keys: Option<Keys>,
+    written: Vec<Vec<u8>>,
+}
+
+impl HeadlessTerminal {
+    pub fn new(keys: Keys) -> Self {
+        Self {
+            keys: Some(keys),
+            written: Vec::new(),
+        }
+    }
+
+    /// 書き込まれたバイト数
+    pub fn written_len(&self) -> usize {
+        self.written.iter().map(Vec::len).sum()
+    }
+}
+
+impl Write for HeadlessTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Terminal for HeadlessTerminal {
+    fn size(&self) -> io::Result<(usize, usize)> {
+        Err(Error::new(ErrorKind::Unsupported, "no terminal"))
+    }
+
+    fn set_raw_mode(&mut self, _raw: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn keys(&mut self) -> io::Result<Keys> {
+        match self.keys.take() {
+            Some(keys) => Ok(keys),
+            None => Ok(Box::new(std::iter::empty())),
+        }
+    }
+}
+
+/// 台本を再生して、コマンドの応答・最後のバッファ・最後の画面を返す
+pub fn replay(path: &str, jisyo: Jisyo, config: &Config, initial: &str) -> io::Result<String> {
+    let steps = read_script(path)?;
+    let log = Arc::new(Mutex::new(String::new()));
+    let mut term = HeadlessTerminal::new(Box::new(Script::new(steps, &log)));
+    let config = headless_config(config);
+    let exit = run(&mut term, jisyo, &config, initial)?;
+    exit.saved?;
+
+    // 終了時の後片付けより前に描いたものが最後の画面
+    let mut text = String::new();
+    for chunk in &term.written {
+        let chunk = String::from_utf8_lossy(chunk);
+        if chunk.contains(CURSOR_SHOW) {
+            break;
+        }
+        text.push_str(&chunk);
+    }
+    let mut out = std::mem::take(&mut *log.lock().unwrap());
+    out.push_str("--- buffer\n");
+    out.push_str(&exit.text);
+    out.push_str("\n--- screen\n");
+    out.push_str(&screen(config.fallback_term_size, &text));
+    Ok(out)
+}
+
+/// 台本をruns回再生して、キー入力から描画までにかかった時間を返す（sleepは飛ばす）
+///
+/// 辞書はload_jisyoで毎回読み込み直す（読み込みの時間は含めない）。
+pub fn bench(
+    path: &str,
+    runs: usize,
+    config: &Config,
+    load_jisyo: impl Fn() -> io::Result<Jisyo>,
+) -> io::Result<String> {
+    let mut steps = read_script(path)?;
+    steps.retain(|s| !matches!(s, Step::Sleep(_)));
+    let keys: usize = steps
+        .iter()
+        .map(|s| match s {
+            Step::Keys(bytes) => read_keys(Cursor::new(bytes.clone())).count(),
+            Step::Command(..) => 1,
+            Step::Sleep(_) => 0,
+        })
+        .sum();
+    let config = headless_config(config);
+    let mut times = Vec::with_capacity(runs);
+    let mut written = 0;
+    for _ in 0..runs {
+        let jisyo = load_jisyo()?;
+        let log = Arc::new(Mutex::new(String::new()));
+        let script = Script::new(steps.clone(), &log);
+        let mut term = HeadlessTerminal::new(Box::new(script));
+        let start = Instant::now();
+        run(&mut term, jisyo, &config, "")?.saved?;
+        times.push(start.elapsed());
+        written = term.written_len();
+    }
+    let total: Duration = times.iter().sum();
+    let per_run = total / runs as u32;
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let per_key = per_run / keys.max(1) as u32;
+    Ok(format!(
+        "runs: {}, inputs: {} per run\n\
+         time: {:.1?} per run (min {:.1?}, max {:.1?}), {:.1?} per input\n\
+         output: {} bytes per run\n",
+        runs, keys, per_run, min, max, per_key, written
+    ))
+}
+
+fn read_script(path: &str) -> io::Result<Vec<Step>> {
+    let with_path = |e: Error| Error::new(e.kind(), format!("{}: {}", path, e));
+    parse_script(&fs::read_to_string(path).map_err(with_path)?).map_err(with_path)
+}
+
+// 終了時に出力する設定にして、Ctrl+Qで確認なしに終わらせる
+fn headless_config(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.print_on_exit = true;
+    config.control_socket = None;
+    config
+}
+
+fn parse_script(text: &str) -> io::Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let step = match line.split_once(' ').unwrap_or((line, "")) {
+            ("keys", keys) => decode_keys(keys).map(Step::Keys),
+            ("sleep", ms) => ms
+                .parse::<u64>()
+                .map(|ms| Step::Sleep(Duration::from_millis(ms)))
+                .map_err(|_| format!("invalid sleep: {}", ms)),
+            _ => control::parse(line).map(|req| Step::Command(line.to_string(), req)),
+        };
+        let step =
+            step.map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?;
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn decode_keys(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push(b'\\'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('r') => out.push(b'\r'),
+            Some('e') => out.push(0x1b),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let b = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape: \\x{}", hex))?;
+                out.push(b);
+            }
+            e => return Err(format!("invalid escape: \\{}", e.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Clone, PartialEq)]
+enum Cell {
+    Char(String, bool), // 文字（と重ねた結合文字）と反転表示
+    Wide,               // 全角文字の右半分
+}
+
+// 書き込まれたエスケープシーケンスを画面として解釈する（ゴールデンテストと共通）
+// 反転表示（カーソルと選択範囲）は[]で囲んで表す
+pub(super) fn screen(size: (usize, usize), text: &str) -> String {
+    let (w, h) = size;
+    let blank = Cell::Char(String::from(" "), false);
+    let mut grid = vec![vec![blank.clone(); w]; h];
+    let (mut row, mut col, mut reverse) = (0usize, 0usize, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            let mut param = String::new();
+            let mut cmd = ' ';
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    cmd = c;
+                    break;
+                }
+                param.push(c);
+            }
+            match (cmd, param.as_str()) {
+                ('H', p) => {
+                    let (r, c) = p.split_once(';').unwrap();
+                    row = r.parse::<usize>().unwrap() - 1;
+                    col = c.parse::<usize>().unwrap() - 1;
+                }
+                ('J', "2") => grid = vec![vec![blank.clone(); w]; h],
+                ('K', "2") => grid[row] = vec![blank.clone(); w],
+                ('m', "7") => reverse = true,
+                ('m', "0") => reverse = false,
+                _ => (), // 色やカーソルの表示切替は画面の内容に影響しない
+            }
+            continue;
+        }
+        let cw = char_width(c).unwrap_or(1);
+        // 結合文字は左のセルの文字に重なる
+        if cw == 0 {
+            if let Some(Cell::Char(s, _)) = grid[row][..col]
+                .iter_mut()
+                .rfind(|cell| matches!(cell, Cell::Char(..)))
+            {
+                s.push(c);
+            }
+            continue;
+        }
+        assert!(col + cw <= w, "overflow at row {}: {:?}", row + 1, c);
+        grid[row][col] = Cell::Char(c.to_string(), reverse);
+        if cw == 2 {
+            grid[row][col + 1] = Cell::Wide;
+        }
+        col += cw;
+    }
+
+    let mut out = String::new();
+    for line in grid {
+        let mut s = String::new();
+        let mut in_reverse = false;
+        for cell in line {
+            if let Cell::Char(c, r) = cell {
+                if r != in_reverse {
+                    s.push(if r { '[' } else { ']' });
+                    in_reverse = r;
+                }
+                s.push_str(&c);
+            }
+        }
+        if in_reverse {
+            s.push(']');
+        }
+        out.push_str(s.trim_end());
+        out.push('\n');
+    }
+    out
+}
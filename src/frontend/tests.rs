@@ -0,0 +1,713 @@
+// 描画のゴールデンテスト
+// キー入力を流し込んでrunを動かし、偽の端末に描かれた画面をsrc/frontend/golden/*.txtと比べる
+// 期待値の更新：UPDATE_GOLDEN=1 cargo test
+
+use std::{
+    env, fs,
+    io::{self, Cursor, Write},
+    thread,
+    time::Duration,
+};
+
+use termion::event::Key;
+
+use super::{
+    CURSOR_SHOW, KEY_MASKED, MSG_QUIT_CONFIRM, describe_key,
+    replay::{self, screen},
+    run, str_width, truncate_middle,
+};
+use crate::{
+    config::{Config, Exec, QuitAction},
+    jisyo::{CandidateOrder, Jisyo, JisyoLookup, KatakanaCandidate},
+    romaji::{FailurePolicy, RomajiPreset, TimeoutAction},
+    terminal::{Keys, Terminal, read_keys},
+    testutil::TempPath,
+};
+
+const JISYO: &str = "\
+かんじ /漢字/感じ/幹事/
+にほんご /日本語/
+#かい /#1回/#3回/
+すらっしゅ /(concat \"a\\057b\")/(skk-unknown-gadget)/(株)/
+";
+const QUIT: &str = "\x11y"; // Ctrl+Q（未送出なら確認にyで答える）
+const SHIFT_LEFT: &str = "\x1b[1;2D";
+
+// 固定のサイズと、あらかじめ決めたキー入力を持つ端末
+// 書き込みは1回ずつ溜め、最後にまとめて画面として解釈する
+struct FakeTerminal {
+    size: (usize, usize),
+    input: Vec<u8>,
+    written: Vec<Vec<u8>>,
+}
+
+impl Write for FakeTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Terminal for FakeTerminal {
+    fn size(&self) -> io::Result<(usize, usize)> {
+        Ok(self.size)
+    }
+
+    fn set_raw_mode(&mut self, _raw: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn keys(&mut self) -> io::Result<Keys> {
+        let input = Cursor::new(std::mem::take(&mut self.input));
+        Ok(read_keys(input))
+    }
+}
+
+impl FakeTerminal {
+    // 終了操作（確認の表示と終了時の消去）より前の書き込みを画面として返す
+    fn screen(&self) -> String {
+        // 確認の表示は幅に合わせて中ほどを縮めるので、先頭の数文字だけで判定する
+        let confirm: String = MSG_QUIT_CONFIRM.chars().take(3).collect();
+        let mut text = String::new();
+        for chunk in &self.written {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            if chunk.contains(&confirm) || chunk.contains(CURSOR_SHOW) {
+                break;
+            }
+            text.push_str(chunk);
+        }
+        screen(self.size, &text)
+    }
+}
+
+fn test_config(jisyo_path: &str) -> Config {
+    Config {
+        exec: Exec::Direct,
+        cpy_to: String::from("true"),
+        cpy_from: String::from("true"),
+        cpy_to_transform: Vec::new(),
+        jisyo_path: jisyo_path.to_string(),
+        symbol_jisyo_path: None,
+        palette_path: None,
+        user_jisyo_path: None,
+        romaji_table_path: None,
+        romaji_preset: RomajiPreset::Qwerty,
+        romaji_timeout: None,
+        romaji_timeout_action: TimeoutAction::Flush,
+        romaji_failure: FailurePolicy::Drop,
+        learn_half_life: None,
+        candidate_order: CandidateOrder::Dictionary,
+        jisyo_lookup: JisyoLookup::Merge,
+        yomi_variants: false,
+        katakana_candidate: KatakanaCandidate::Off,
+        auto_send: None,
+        copy_timeout: Duration::from_secs(5),
+        paste_max_bytes: 1024 * 1024,
+        fallback_term_size: (80, 24),
+        status_segments: Vec::new(),
+        ruler_width: None,
+        wrap_width: 72,
+        unknown_key_log: None,
+        control_socket: None,
+        quit_action: QuitAction::Confirm,
+        show_candidate_source: false,
+        candidate_preview: 0,
+        event_hook: None,
+        event_log: None,
+        print_on_exit: false,
+        single_line: false,
+        masked: false,
+        view_only: false,
+    }
+}
+
+fn render(size: (usize, usize), keys: &str) -> String {
+    render_with(size, keys, |_| ())
+}
+
+// 設定を変えて描く
+fn render_with(size: (usize, usize), keys: &str, edit: impl FnOnce(&mut Config)) -> String {
+    render_text(size, "", keys, edit)
+}
+
+// 毎回新しいユーザー辞書で（学習や削除が他の描画に残らないように）
+fn render_user(keys: &str) -> String {
+    let user = TempPath::new("golden.user");
+    let path = user.to_str().unwrap().to_string();
+    render_with((40, 5), keys, |c| c.user_jisyo_path = Some(path))
+}
+
+// バッファにinitialを読み込んでから描く
+fn render_text(
+    size: (usize, usize),
+    initial: &str,
+    keys: &str,
+    edit: impl FnOnce(&mut Config),
+) -> String {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(&path, JISYO).unwrap();
+    let mut config = test_config(path.to_str().unwrap());
+    edit(&mut config);
+    let mut jisyo = Jisyo::from_config(&config).unwrap();
+    if let Some(user) = &config.user_jisyo_path {
+        jisyo.load_user(user, None).unwrap();
+    }
+
+    let mut term = FakeTerminal {
+        size,
+        input: format!("{}{}", keys, QUIT).into_bytes(),
+        written: Vec::new(),
+    };
+    run(&mut term, jisyo, &config, initial)
+        .unwrap()
+        .saved
+        .unwrap();
+    term.screen()
+}
+
+fn assert_golden(name: &str, actual: &str) {
+    let path = format!(
+        "{}/src/frontend/golden/{}.txt",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        expected == actual,
+        "{} differs from golden (UPDATE_GOLDEN=1 to update)\n--- expected\n{}--- actual\n{}",
+        path,
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn empty_buffer() {
+    assert_golden("empty", &render((40, 5), ""));
+}
+
+#[test]
+fn kana_input_over_lines() {
+    assert_golden("kana", &render((40, 5), "nihongo\nkakuyo"));
+}
+
+#[test]
+fn yomi_and_conversion() {
+    assert_golden("yomi", &render((40, 5), "Kanji"));
+    assert_golden("converting", &render((40, 5), "Kanji  "));
+    assert_golden("committed", &render((40, 5), "Kanji  \n"));
+}
+
+#[test]
+fn selection_is_reversed() {
+    let keys = format!("\x0cabcdef{}{}{}", SHIFT_LEFT, SHIFT_LEFT, SHIFT_LEFT);
+    assert_golden("selection", &render((40, 5), &keys));
+}
+
+// 長い行は左右に省略記号を出して横スクロールする
+#[test]
+fn long_line_scrolls() {
+    let keys = "\x0c".to_string() + &"0123456789".repeat(6);
+    assert_golden("scroll_tail", &render((40, 5), &keys));
+    let keys = keys + "\x1b[H";
+    assert_golden("scroll_head", &render((40, 5), &keys));
+}
+
+#[test]
+fn wide_and_replaced_chars() {
+    assert_golden("wide", &render((40, 5), "kanakana\x0c\tend"));
+}
+
+#[test]
+fn terminal_too_small() {
+    assert_golden("too_small", &render((20, 5), "abc"));
+}
+
+// ルーラーは行の末尾より右にだけ描き、横スクロールに合わせて動く
+#[test]
+fn ruler_column() {
+    let ruler = |c: &mut Config| c.ruler_width = Some(10);
+    let keys = "\x0cabc\nabcdefghijkl\n";
+    assert_golden("ruler", &render_with((40, 5), keys, ruler));
+    let keys = "\x0cabc\n".to_string() + &"0123456789".repeat(6);
+    let far = |c: &mut Config| c.ruler_width = Some(50);
+    assert_golden("ruler_scrolled", &render_with((40, 5), &keys, far));
+}
+
+// Ctrl+F：段落を折り返す（句読点は行頭に置かず、英単語の途中では折らない）
+#[test]
+fn wrap_paragraph() {
+    let width = |c: &mut Config| c.wrap_width = 10;
+    let keys = "aiueokakikukeko.\x06\n\n\x0cthe quick brown fox\x06";
+    assert_golden("wrap", &render_with((40, 8), keys, width));
+}
+
+// 候補のない読みは辞書登録になり、登録する語の中でも変換できる
+#[test]
+fn register_word() {
+    let user = TempPath::new("golden.user");
+    let with_user = |c: &mut Config| c.user_jisyo_path = Some(user.to_str().unwrap().to_string());
+    let keys = "Hoge Kanji \n";
+    assert_golden("register", &render_with((40, 5), keys, with_user));
+    let keys = keys.to_string() + "\n";
+    assert_golden("registered", &render_with((40, 5), &keys, with_user));
+}
+
+// Lispの式の候補は評価して出し、評価できない式は候補から外す
+#[test]
+fn gadget() {
+    let keys = "Surassyu \nSurassyu  ";
+    assert_golden("gadget", &render((40, 5), keys));
+}
+
+// JSONの辞書：候補の / や ; はそのまま書け、註釈はオブジェクトで付ける
+#[test]
+fn json_jisyo() {
+    let json = TempPath::new("golden.txt");
+    let text = r#"{"すらっしゅ": ["a/b;c", {"candidate": "x", "annotation": "y/z"}]}"#;
+    fs::write(&json, text).unwrap();
+    let spec = format!("{}?format=json", json.display());
+    let keys = "Surassyu \nSurassyu  ";
+    let screen = render_with((40, 5), keys, |c| c.jisyo_path = spec);
+    assert_golden("json_jisyo", &screen);
+}
+
+// JISYO_PATHのワイルドカード：一致したファイルを名前の順に読み込む
+#[test]
+fn jisyo_glob() {
+    let dir = TempPath::new("golden");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("b.utf8"), "ぐろぶ /後/\n").unwrap();
+    fs::write(dir.join("a.utf8"), "ぐろぶ /先/\n").unwrap();
+    fs::write(dir.join("c.txt"), "ぐろぶ /外/\n").unwrap();
+    let spec = format!("{}/*.utf8", dir.display());
+    let keys = "Gurobu \nGurobu  ";
+    let screen = render_with((40, 5), keys, |c| c.jisyo_path = spec);
+    assert_golden("jisyo_glob", &screen);
+}
+
+// Alt+J：名前の辞書を無効にすると候補から外れ、空のまま確定すると一覧を出す
+#[test]
+fn switch_jisyo() {
+    let dir = TempPath::new("golden");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.utf8"), "ぐろぶ /先/\n").unwrap();
+    fs::write(dir.join("b.utf8"), "ぐろぶ /後/\n").unwrap();
+    let spec = format!("{}/*.utf8", dir.display());
+    let screen = render_with((40, 5), "\x1bja\nGurobu ", |c| c.jisyo_path = spec.clone());
+    assert_golden("switch_jisyo", &screen);
+    let screen = render_with((40, 5), "\x1bja\n\x1bj\n", |c| c.jisyo_path = spec);
+    assert_golden("switch_jisyo_list", &screen);
+}
+
+// priority=N：優先度の大きい辞書の候補を先に（同じなら指定順）
+#[test]
+fn jisyo_priority() {
+    let dir = TempPath::new("golden");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("slang.utf8"), "ぐろぶ /俗/\n").unwrap();
+    fs::write(dir.join("main.utf8"), "ぐろぶ /先/\n").unwrap();
+    fs::write(dir.join("terms.utf8"), "ぐろぶ /後/\n").unwrap();
+    let spec = format!(
+        "{0}/slang.utf8?priority=-1:{0}/main.utf8:{0}/terms.utf8?priority=10",
+        dir.display()
+    );
+    let priority = |c: &mut Config| {
+        c.jisyo_path = spec;
+        c.show_candidate_source = true;
+        c.candidate_preview = 2;
+    };
+    assert_golden("jisyo_priority", &render_with((50, 5), "Gurobu ", priority));
+}
+
+// PALETTE_PATH：定義ファイルのパレットをCtrl+Kで選ぶ（; や ( で始まる記号もそのまま入る）
+#[test]
+fn user_palette() {
+    let path = TempPath::new("golden.palette");
+    fs::write(
+        &path,
+        "# 記号\n[数学]\n∀ ∃ ∞\n[顔文字]\n(;_;) m(_\\s_)m\n[数学]\n≠ ∞\n",
+    )
+    .unwrap();
+    let palette = |c: &mut Config| c.palette_path = Some(path.to_str().unwrap().to_string());
+    assert_golden("user_palette", &render_with((40, 5), "\x0b   ", palette));
+    let keys = "\x0bn\n\x0bn \n";
+    assert_golden("user_palette_commit", &render_with((40, 5), keys, palette));
+}
+
+// 顔文字：全角・半角・結合文字が混じっても、候補の反転とステータス行の幅がずれない
+#[test]
+fn kaomoji_jisyo() {
+    let path = TempPath::new("golden.utf8");
+    fs::write(&path, "かお /٩(๑•̀ㅂ•́)و/ヽ(°̥̥Д°̥̥)ﾉ/ʕ•̫͡•ʔ/\n").unwrap();
+    let kaomoji = |c: &mut Config| {
+        c.jisyo_path = path.to_str().unwrap().to_string();
+        c.candidate_preview = 2;
+    };
+    assert_golden("kaomoji", &render_with((40, 5), "Kao  ", kaomoji));
+    // カーソルが結合文字にあるときは、重ねた先の文字を反転する
+    let keys = "Kao \n\x1b[D\x1b[D\x1b[D";
+    assert_golden("kaomoji_cursor", &render_with((40, 5), keys, kaomoji));
+}
+
+// 顔文字の幅：結合文字は0、全角は2、半角カナは1として数える
+#[test]
+fn kaomoji_widths() {
+    assert_eq!(str_width("٩(๑•̀ㅂ•́)و"), 9);
+    assert_eq!(str_width("ヽ(°̥̥Д°̥̥)ﾉ"), 8);
+    assert_eq!(str_width("ʕ•̫͡•ʔ"), 4);
+    assert_eq!(str_width("(＾ω＾)ﾉ"), 8);
+    // 縮めても幅に収まり、重ねる先のない結合文字は残さない
+    for w in 1..=8 {
+        let s = truncate_middle("ヽ(°̥̥Д°̥̥)ﾉ", w);
+        assert!(str_width(&s) <= w, "{w}: {s}");
+        assert!(!s.contains("…\u{325}"), "{w}: {s}");
+    }
+    assert_eq!(truncate_middle("ヽ(°̥̥Д°̥̥)ﾉ", 5), "ヽ…)ﾉ");
+}
+
+// JISYO_LOOKUP=first：ユーザー辞書にある読みは、他の辞書の候補を出さない
+#[test]
+fn jisyo_lookup_first() {
+    let user = TempPath::new("golden.user");
+    let first = |c: &mut Config| {
+        c.user_jisyo_path = Some(user.to_str().unwrap().to_string());
+        c.jisyo_lookup = JisyoLookup::First;
+    };
+    let keys = "Kanji  \nKanji ";
+    assert_golden("jisyo_lookup_first", &render_with((40, 5), keys, first));
+}
+
+// YOMI_VARIANTS=on：見つからない読みは ず/づ や ゔ/う゛ を入れ替えて引き、引いた読みを註釈に出す
+#[test]
+fn yomi_variants() {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(&path, "づつう /頭痛;痛み/\nう゛ぁいおりん /ヴァイオリン/\n").unwrap();
+    let variants = |c: &mut Config| {
+        c.jisyo_path = path.to_str().unwrap().to_string();
+        c.yomi_variants = true;
+    };
+    assert_golden("yomi_variant", &render_with((40, 5), "Zutuu ", variants));
+    assert_golden(
+        "yomi_variant_vu",
+        &render_with((50, 5), "Vaiorinn ", variants),
+    );
+}
+
+// KATAKANA_CANDIDATE：辞書にない読みは読みのカタカナを候補にし、alwaysなら辞書の候補の最後にも出す
+#[test]
+fn katakana_candidate() {
+    let fallback = |c: &mut Config| c.katakana_candidate = KatakanaCandidate::Fallback;
+    assert_golden(
+        "katakana_fallback",
+        &render_with((40, 5), "Tesuto ", fallback),
+    );
+    // ユーザー辞書があれば、カタカナの候補の次で辞書登録に入る
+    let keys = "Tesuto  ";
+    let user = TempPath::new("golden.user");
+    let register = |c: &mut Config| {
+        c.katakana_candidate = KatakanaCandidate::Fallback;
+        c.user_jisyo_path = Some(user.to_str().unwrap().to_string());
+    };
+    assert_golden("katakana_register", &render_with((40, 5), keys, register));
+    let always = |c: &mut Config| c.katakana_candidate = KatakanaCandidate::Always;
+    assert_golden(
+        "katakana_always",
+        &render_with((40, 5), "Kanji    ", always),
+    );
+}
+
+// Abbrev：英字の読みは大文字・小文字を区別せず、前後の空白を除いて引く（入力したとおりの見出し語が先）
+#[test]
+fn abbrev_ignore_case() {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(
+        &path,
+        "HTTP /エイチティーティーピー/\nhttp /ハイパーテキスト/\n",
+    )
+    .unwrap();
+    let jisyo = |c: &mut Config| c.jisyo_path = path.to_str().unwrap().to_string();
+    assert_golden("abbrev_lower", &render_with((50, 5), "/http ", jisyo));
+    assert_golden("abbrev_folded", &render_with((50, 5), "/\tHttp  ", jisyo));
+}
+
+// format=edict：和英辞書の訳語で引き、訳語は註釈に出して確定では除く（よく使う語が先）
+#[test]
+fn edict_abbrev() {
+    let path = TempPath::new("golden.edict");
+    fs::write(
+        &path,
+        "返る [かえる] /(v5r,vi) to return; to come back/EntL2/\n\
+         帰る [かえる] /(v5r,vi) (1) to return; to come home/(2) to go home/(P)/EntL1/\n",
+    )
+    .unwrap();
+    let spec = format!("{}?format=edict", path.display());
+    let edict = |c: &mut Config| c.jisyo_path = spec.clone();
+    assert_golden("edict_gloss", &render_with((80, 5), "/return ", edict));
+    assert_golden("edict_commit", &render_with((80, 5), "/return  \n", edict));
+}
+
+// 状態の行に収まらないときは、註釈を落として候補の中ほどを…にし、[n/m]は残す
+#[test]
+fn status_truncated() {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(
+        &path,
+        "ながい /寿限無寿限無五劫の擦り切れ海砂利水魚の水行末雲来末風来末;長い名前/長い/\n",
+    )
+    .unwrap();
+    let jisyo = |c: &mut Config| c.jisyo_path = path.to_str().unwrap().to_string();
+    assert_golden("status_truncated", &render_with((40, 5), "Nagai ", jisyo));
+}
+
+// 式の文字列にエスケープせずに書かれた / で候補を分けず、(concat ...) の註釈も戻して出す
+#[test]
+fn escaped_candidate() {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(
+        &path,
+        "りんく /(concat \"https://example.com/\");(concat \"例\\057サイト\")/\n",
+    )
+    .unwrap();
+    let jisyo = |c: &mut Config| c.jisyo_path = path.to_str().unwrap().to_string();
+    assert_golden("escaped_candidate", &render_with((50, 5), "Rinku ", jisyo));
+}
+
+// CANDIDATE_PREVIEW：選択中の候補の後ろに次の候補を並べる（幅が足りなければ先に落とす）
+#[test]
+fn candidate_preview() {
+    let preview = |c: &mut Config| c.candidate_preview = 1;
+    assert_golden("preview", &render_with((40, 5), "Kanji ", preview));
+    assert_golden("preview_last", &render_with((40, 5), "Kanji   ", preview));
+}
+
+// EVENT_LOG：変換の開始・確定・候補なし・入力モードの切り替えを1行ずつ書く
+#[test]
+fn event_log() {
+    let log = TempPath::new("golden.log");
+    let with_log = |c: &mut Config| c.event_log = Some(log.to_str().unwrap().to_string());
+    render_with((40, 5), "Kanji \nqqHoge \n\x0c", with_log);
+    // 書き込みは別スレッドなので、最後の出来事まで待つ
+    let mut text = String::new();
+    for _ in 0..100 {
+        text = fs::read_to_string(&log).unwrap_or_default();
+        if text.ends_with("mode\tlatin\n") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_golden("event_log", &text);
+}
+
+// CANDIDATE_SOURCE=show：候補の出所の辞書名を付ける（学習した候補はユーザー辞書）
+#[test]
+fn candidate_source() {
+    let user = TempPath::new("golden.user");
+    let show = |c: &mut Config| {
+        c.user_jisyo_path = Some(user.to_str().unwrap().to_string());
+        c.show_candidate_source = true;
+    };
+    let keys = "Kanji \nKanji ";
+    assert_golden("candidate_source", &render_with((40, 5), keys, show));
+}
+
+// CANDIDATE_ORDER=frequency：学習した候補は最近確定した順ではなく、確定した回数の多い順
+#[test]
+fn frequency_order() {
+    let user = TempPath::new("golden.user");
+    let frequency = |c: &mut Config| {
+        c.user_jisyo_path = Some(user.to_str().unwrap().to_string());
+        c.candidate_order = CandidateOrder::Frequency;
+    };
+    // 感じを2回、幹事を1回確定してから変換する
+    let keys = "Kanji  \nKanji \nKanji   \nKanji ";
+    assert_golden("frequency_order", &render_with((40, 5), keys, frequency));
+}
+
+// 変換中のXで削除した候補は、Alt+Xで元に戻せる（削除はユーザー辞書に残るので、回ごとに分ける）
+#[test]
+fn restore_purged() {
+    assert_golden("purged", &render_user("Kanji X"));
+    assert_golden("purge_restored", &render_user("Kanji X\x07\x1bx"));
+    assert_golden("purge_converting", &render_user("Kanji X\x07\x1bx "));
+}
+
+// Ctrl+P：候補を先頭に固定する（後で別の候補を確定しても先頭のまま、もう一度でやめる）
+#[test]
+fn pinned_candidate() {
+    assert_golden("pinned", &render_user("Kanji  \x10\nKanji  \nKanji "));
+    assert_golden("unpinned", &render_user("Kanji  \x10\nKanji  \nKanji \x10"));
+}
+
+// Ctrl+U：学習した候補の一覧を見て、Dで忘れる（辞書の順に戻る）
+#[test]
+fn learned_list() {
+    assert_golden("learned", &render_user("Kanji  \n\x15"));
+    assert_golden("learned_forgotten", &render_user("Kanji  \n\x15DKanji "));
+}
+
+// 読みのカタカナ（Ctrl+Oで入れたものなど）はひらがなにして引き、候補はそのまま確定する
+#[test]
+fn katakana_yomi() {
+    let keys = "qKa\x0fン\x0fジ \nKa\x0fン\x0fジ ";
+    assert_golden("katakana_yomi", &render((40, 5), keys));
+}
+
+// 数値変換：読みの数字を#にして引き、候補の#1や#3に数字を入れる
+#[test]
+fn numeric_conversion() {
+    assert_golden("numeric", &render((40, 5), "Q2024kai  "));
+}
+
+// Tab：読みを辞書の見出し語で補完し、そのまま変換できる
+#[test]
+fn complete_yomi() {
+    assert_golden("complete", &render((40, 5), "Ka\t"));
+    assert_golden("complete_converting", &render((40, 5), "Ka\t "));
+}
+
+// --masked：本文は伏せ字にして、変換中の読みと候補だけをステータス行に出す
+#[test]
+fn masked_buffer() {
+    let masked = |c: &mut Config| c.masked = true;
+    assert_golden("masked", &render_with((40, 5), "Kanji \nKanji ", masked));
+}
+
+// --view：編集のキーは効かず、かな漢字変換した語で検索して選択する
+#[test]
+fn view_and_search() {
+    let view = |c: &mut Config| c.view_only = true;
+    let text = "漢字の感じ\nabc\n\n\n\n漢字と感じ\ndef";
+    assert_golden("view", &render_text((40, 6), text, "xyz\x04", view));
+    let keys = "/Kanji  \n\nn";
+    assert_golden("view_search", &render_text((40, 6), text, keys, view));
+    // ↑で前に検索した語を呼び出す
+    let keys = "/Kanji  \n\n/\x1b[A\n";
+    assert_golden(
+        "view_search_history",
+        &render_text((40, 6), text, keys, view),
+    );
+}
+
+// Alt+G：ステータス行に入力した行番号の行頭へ移動する
+#[test]
+fn goto_line() {
+    let text = "abc\ndef\nghi\njkl";
+    assert_golden("goto_line", &render_text((40, 6), text, "\x1bg2\n", |_| ()));
+    let keys = "\x1bg2\x1bg";
+    assert_golden(
+        "goto_line_prompt",
+        &render_text((40, 6), text, keys, |_| ()),
+    );
+}
+
+// Alt+Y：選択範囲（なければカーソル位置の文字）を候補に持つ見出し語の読み、なければそれを含む語を出す
+#[test]
+fn print_reading() {
+    let keys = format!("\x1b[F{}{}\x1by", SHIFT_LEFT, SHIFT_LEFT);
+    assert_golden("reading", &render_text((40, 5), "漢字", &keys, |_| ()));
+    let keys = "\x1b[H\x1by";
+    assert_golden(
+        "reading_partial",
+        &render_text((40, 5), "漢字", keys, |_| ()),
+    );
+}
+
+// ステータス行の入力は本文と別の入力状態を持つ：入力中にかな漢字変換でき、本文の読みはそのまま残る
+#[test]
+fn prompt_kana() {
+    let keys = "Kan\x1bg\x0cKanji \n";
+    assert_golden("prompt_kana", &render((40, 5), keys));
+    let keys = "Kan\x1bg\x0cKanji \n\x07ji ";
+    assert_golden("prompt_kana_cancelled", &render((40, 5), keys));
+}
+
+// 解釈できないシーケンスと割り当てのない特殊キーは、ステータス行で知らせる
+#[test]
+fn unknown_and_unbound_keys() {
+    assert_golden("unknown_key", &render((40, 5), "abc\x1b[1;5P"));
+    assert_golden("unbound_key", &render((40, 5), "abc\x1b[1;2A"));
+}
+
+// termionが解釈しない修飾付きの矢印（tmuxのESC O 5D、rxvtのESC O d）も単語単位の移動になる
+#[test]
+fn modified_arrows() {
+    let keys = "\x0cfoo bar baz\x1bO5D\x1bOd_\x1b[1;5C\x1b[5C!";
+    assert_golden("word_move", &render((40, 5), keys));
+}
+
+// --replay：台本のキー入力とコマンドを順に処理し、応答・バッファ・画面を書き出す
+#[test]
+fn replay_script() {
+    let path = TempPath::new("golden");
+    let (jisyo_path, script_path) = (path.with_extension("jisyo"), path.with_extension("txt"));
+    fs::write(&jisyo_path, JISYO).unwrap();
+    let script = "# 変換中はモードを変えられない\nkeys Kanji \nget-mode\nset-mode latin\n\
+                  keys \\n\ninsert \\nabc\nget-buffer\nkeys \\x01\n";
+    fs::write(&script_path, script).unwrap();
+    let mut config = test_config(jisyo_path.to_str().unwrap());
+    config.fallback_term_size = (40, 6);
+    let jisyo = Jisyo::from_config(&config).unwrap();
+    let report = replay::replay(script_path.to_str().unwrap(), jisyo, &config, "").unwrap();
+    assert_golden("replay", &report);
+}
+
+// --masked：制御用ソケットのget-bufferでも本文を返さない
+#[test]
+fn control_get_buffer_masked() {
+    let path = TempPath::new("golden");
+    let (jisyo_path, script_path) = (path.with_extension("jisyo"), path.with_extension("txt"));
+    fs::write(&jisyo_path, JISYO).unwrap();
+    fs::write(&script_path, "keys \\x0cpass\\n\nget-buffer\nget-mode\n").unwrap();
+    let mut config = test_config(jisyo_path.to_str().unwrap());
+    config.fallback_term_size = (40, 4);
+    config.masked = true;
+    let jisyo = Jisyo::from_config(&config).unwrap();
+    let report = replay::replay(script_path.to_str().unwrap(), jisyo, &config, "").unwrap();
+    assert_golden("control_masked", &report);
+}
+
+// 終了時にユーザー辞書を保存できなくても、--print-on-exitで書き出すバッファの内容は返す
+#[test]
+fn exit_text_survives_failed_save() {
+    let path = TempPath::new("golden.jisyo");
+    fs::write(&path, JISYO).unwrap();
+    // 置き場所のディレクトリがないので、ロックも書き込みも失敗する
+    let user = path.with_extension("missing").join("user");
+    let config = test_config(path.to_str().unwrap());
+    let mut jisyo = Jisyo::from_config(&config).unwrap();
+    jisyo.load_user(user.to_str().unwrap(), None).unwrap();
+
+    let mut term = FakeTerminal {
+        size: (40, 5),
+        input: format!("Kanji \n{}", QUIT).into_bytes(),
+        written: Vec::new(),
+    };
+    let exit = run(&mut term, jisyo, &config, "").unwrap();
+    assert_eq!(exit.text, "漢字");
+    assert!(exit.saved.is_err());
+}
+
+// --masked：Ctrl+Y の診断表示でも、入力した文字は伏せる（特殊キーはそのまま）
+#[test]
+fn key_info_is_masked() {
+    let class = String::from("Printable('p')");
+    assert_eq!(
+        describe_key(&Key::Char('p'), class.clone(), true),
+        (String::from("Char('●')"), String::from(KEY_MASKED))
+    );
+    assert_eq!(
+        describe_key(&Key::Char('p'), class.clone(), false),
+        (String::from("Char('p')"), class)
+    );
+    let enter = String::from("Enter");
+    assert_eq!(describe_key(&Key::Char('\n'), enter.clone(), true).1, enter);
+}
@@ -0,0 +1,327 @@
+//! 辞書の候補に書かれたLispの式（ddskkのプログラム実行変換）
+//!
+//! `(concat "a\057b")`や`(skk-current-date)`のような候補を読んで評価する。
+//! 対応しない関数を使った式は、Lispのまま出さないように候補から外す。
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["日", "月", "火", "水", "木", "金", "土"];
+const WEEKDAYS_EN: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS_EN: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+// 関数名に - を含まなくても式とみなす関数
+const KNOWN: &[&str] = &["concat", "substring", "format"];
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Str(String),
+    Int(i64),
+    Sym(String),
+    List(Vec<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Gadget {
+    Value(String),
+    Unsupported, // 式だが評価できない
+}
+
+/// 候補が式なら評価した結果（式でなければNone）
+pub fn evaluate(cand: &str) -> Option<Gadget> {
+    let (expr, end) = read_gadget(cand)?;
+    if end != cand.len() {
+        return None;
+    }
+    Some(eval(&expr).map_or(Gadget::Unsupported, Gadget::Value))
+}
+
+/// 先頭の式の閉じ括弧の直後の位置（式で始まらなければNone）
+pub fn end(s: &str) -> Option<usize> {
+    read_gadget(s).map(|(_, end)| end)
+}
+
+/// 先頭の記号がheadである式の、文字列の引数
+pub fn string_args(s: &str, head: &str) -> Option<Vec<String>> {
+    let (Expr::List(list), end) = read_gadget(s)? else {
+        return None;
+    };
+    if end != s.len() || list[0] != Expr::Sym(head.to_string()) {
+        return None;
+    }
+    list[1..]
+        .iter()
+        .map(|e| match e {
+            Expr::Str(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// (株) や (c) は式とみなさない：先頭が英字で始まる記号で、- を含むか既知の関数名のときだけ
+fn read_gadget(s: &str) -> Option<(Expr, usize)> {
+    if !s.starts_with('(') {
+        return None;
+    }
+    let mut chars = s.char_indices().peekable();
+    let expr = read(s, &mut chars)?;
+    let Expr::List(list) = &expr else {
+        return None;
+    };
+    match list.first() {
+        Some(Expr::Sym(head))
+            if head.starts_with(|c: char| c.is_ascii_alphabetic())
+                && (head.contains('-') || KNOWN.contains(&head.as_str())) => {}
+        _ => return None,
+    }
+    let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+    Some((expr, end))
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+fn read(s: &str, chars: &mut Chars) -> Option<Expr> {
+    while chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+    let &(start, c) = chars.peek()?;
+    match c {
+        '(' => {
+            chars.next();
+            let mut list = Vec::new();
+            loop {
+                while chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+                if chars.next_if(|&(_, c)| c == ')').is_some() {
+                    return Some(Expr::List(list));
+                }
+                list.push(read(s, chars)?);
+            }
+        }
+        ')' => None,
+        '"' => {
+            chars.next();
+            read_string(chars).map(Expr::Str)
+        }
+        // 'x は (quote x)
+        '\'' => {
+            chars.next();
+            let quoted = read(s, chars)?;
+            Some(Expr::List(vec![Expr::Sym(String::from("quote")), quoted]))
+        }
+        _ => {
+            while chars
+                .next_if(|&(_, c)| !c.is_ascii_whitespace() && !"()\"'".contains(c))
+                .is_some()
+            {}
+            let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+            let atom = &s[start..end];
+            Some(match atom.parse() {
+                Ok(n) => Expr::Int(n),
+                Err(_) => Expr::Sym(atom.to_string()),
+            })
+        }
+    }
+}
+
+// 開きの " の後から閉じの " まで。\073 のような8進数の表記を展開する
+fn read_string(chars: &mut Chars) -> Option<String> {
+    let mut out = String::new();
+    loop {
+        match chars.next()?.1 {
+            '"' => return Some(out),
+            '\\' => match chars.next()?.1 {
+                d @ '0'..='7' => {
+                    // 8進数は最大3桁
+                    let mut n = d.to_digit(8).unwrap();
+                    for _ in 0..2 {
+                        let Some((_, x)) = chars.next_if(|(_, c)| c.is_digit(8)) else {
+                            break;
+                        };
+                        n = n * 8 + x.to_digit(8).unwrap();
+                    }
+                    out.extend(char::from_u32(n));
+                }
+                'n' => out.push('\n'),
+                e => out.push(e),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn eval(expr: &Expr) -> Option<String> {
+    let Expr::List(list) = expr else {
+        return match expr {
+            Expr::Str(s) => Some(s.clone()),
+            _ => None,
+        };
+    };
+    let Some(Expr::Sym(head)) = list.first() else {
+        return None;
+    };
+    let args = &list[1..];
+    match head.as_str() {
+        "concat" => args.iter().map(eval).collect(),
+        "substring" => {
+            let s: Vec<char> = eval(args.first()?)?.chars().collect();
+            let index = |e: Option<&Expr>, default| match e {
+                None => Some(default),
+                Some(Expr::Int(n)) if *n < 0 => s.len().checked_sub(n.unsigned_abs() as usize),
+                Some(Expr::Int(n)) => Some(*n as usize),
+                Some(_) => None,
+            };
+            let from = index(args.get(1), 0)?;
+            let to = index(args.get(2), s.len())?;
+            Some(s.get(from..to)?.iter().collect())
+        }
+        // 引数（書式の指定）は読まず、いつも同じ書式にする
+        "skk-current-date" => {
+            let tm = localtime()?;
+            Some(format!(
+                "{}年{}月{}日({})",
+                tm.tm_year + 1900,
+                tm.tm_mon + 1,
+                tm.tm_mday,
+                WEEKDAYS[tm.tm_wday as usize % 7]
+            ))
+        }
+        "format-time-string" if args.len() == 1 => format_time(&eval(&args[0])?, &localtime()?),
+        // 和暦の今日の日付（組み込みの候補で使う）
+        "unskk-wareki-date" if args.is_empty() => {
+            let tm = localtime()?;
+            let (year, month, day) = (tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday);
+            let (era, start) = match (year, month, day) {
+                (y, m, _) if (y, m) >= (2019, 5) => ("令和", 2019),
+                (y, m, d) if (y, m, d) >= (1989, 1, 8) => ("平成", 1989),
+                _ => return None,
+            };
+            let n = match year - start + 1 {
+                1 => String::from("元"),
+                n => n.to_string(),
+            };
+            Some(format!("{}{}年{}月{}日", era, n, month, day))
+        }
+        "current-time-string" if args.is_empty() => {
+            let tm = localtime()?;
+            Some(format!(
+                "{} {} {:2} {:02}:{:02}:{:02} {}",
+                WEEKDAYS_EN[tm.tm_wday as usize % 7],
+                MONTHS_EN[tm.tm_mon as usize % 12],
+                tm.tm_mday,
+                tm.tm_hour,
+                tm.tm_min,
+                tm.tm_sec,
+                tm.tm_year + 1900
+            ))
+        }
+        _ => None,
+    }
+}
+
+// %Y %m %d などを置き換える（%-d のように - を付けると桁を揃えない）
+fn format_time(fmt: &str, tm: &libc::tm) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut d = chars.next()?;
+        let pad = d != '-';
+        if !pad {
+            d = chars.next()?;
+        }
+        let num = |n: i32| match pad {
+            true => format!("{:02}", n),
+            false => n.to_string(),
+        };
+        let s = match d {
+            'Y' => (tm.tm_year + 1900).to_string(),
+            'y' => num((tm.tm_year + 1900) % 100),
+            'm' => num(tm.tm_mon + 1),
+            'd' => num(tm.tm_mday),
+            'e' if pad => format!("{:2}", tm.tm_mday),
+            'e' => tm.tm_mday.to_string(),
+            'H' => num(tm.tm_hour),
+            'M' => num(tm.tm_min),
+            'S' => num(tm.tm_sec),
+            'a' => WEEKDAYS_EN[tm.tm_wday as usize % 7].to_string(),
+            'b' => MONTHS_EN[tm.tm_mon as usize % 12].to_string(),
+            '%' => String::from("%"),
+            _ => return None,
+        };
+        out.push_str(&s);
+    }
+    Some(out)
+}
+
+/// 現在の地方時
+pub fn localtime() -> Option<libc::tm> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+    // SAFETY: tmはlocaltime_rが書き込むだけの出力先で、どちらのポインタも呼び出しの間有効
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(s: &str) -> Gadget {
+        Gadget::Value(s.to_string())
+    }
+
+    #[test]
+    fn evaluates_string_expressions() {
+        assert_eq!(evaluate("(concat \"a\\057b\")"), Some(value("a/b")));
+        assert_eq!(
+            evaluate("(concat \"x\\073\" \"y\\n\")"),
+            Some(value("x;y\n"))
+        );
+        assert_eq!(
+            evaluate("(substring \"あいうえ\" 1 -1)"),
+            Some(value("いう"))
+        );
+        assert_eq!(evaluate("(substring \"abc\" 5)"), Some(Gadget::Unsupported));
+        assert_eq!(
+            evaluate("(skk-unknown-function)"),
+            Some(Gadget::Unsupported)
+        );
+    }
+
+    #[test]
+    fn parenthesized_words_are_not_expressions() {
+        assert_eq!(evaluate("(株)"), None);
+        assert_eq!(evaluate("(c)"), None);
+        assert_eq!(evaluate("(concat \"a\")b"), None);
+        assert_eq!(evaluate("(concat \"a\""), None);
+        assert_eq!(end("(concat \"a\")b"), Some(12));
+    }
+
+    #[test]
+    fn string_args_of_a_head() {
+        let args = string_args("(unskk-pinned-word \"語\")", "unskk-pinned-word");
+        assert_eq!(args, Some(vec![String::from("語")]));
+        assert_eq!(string_args("(concat \"a\" 1)", "concat"), None);
+        assert_eq!(string_args("(concat \"a\")", "format"), None);
+    }
+
+    #[test]
+    fn formats_a_fixed_time() {
+        // 2024-03-05 (火) 07:08:09
+        // SAFETY: libc::tmは整数（とポインタ）だけの構造体なので、0で埋めてよい
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        (tm.tm_year, tm.tm_mon, tm.tm_mday, tm.tm_wday) = (124, 2, 5, 2);
+        (tm.tm_hour, tm.tm_min, tm.tm_sec) = (7, 8, 9);
+        let format = |fmt| format_time(fmt, &tm);
+        assert_eq!(
+            format("%Y/%m/%d %H:%M:%S").as_deref(),
+            Some("2024/03/05 07:08:09")
+        );
+        assert_eq!(format("%y年%-m月%-d日").as_deref(), Some("24年3月5日"));
+        assert_eq!(format("%a %b %e 100%%").as_deref(), Some("Tue Mar  5 100%"));
+        assert_eq!(format("%Q"), None);
+    }
+}
@@ -0,0 +1,413 @@
+//! gzipで圧縮された辞書の展開（RFC 1951 / RFC 1952）
+//!
+//! 辞書を読み込むときに1度だけ使うので、速さよりも短さを優先している。
+
+use std::io::{self, Error, ErrorKind};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const CM_DEFLATE: u8 = 8;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+
+const MAX_BITS: usize = 15;
+const END_OF_BLOCK: u16 = 256;
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// 動的ハフマン符号で、符号長の符号長が並ぶ順
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// gzipのデータを展開する（複数のメンバーが連結されていれば続けて展開する）
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 4);
+    let mut pos = 0;
+    loop {
+        pos = member(data, pos, &mut out)?;
+        // 末尾のゼロ埋めは無視する
+        if data[pos..].iter().all(|&b| b == 0) {
+            return Ok(out);
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("broken gzip: {}", msg))
+}
+
+// 1つのメンバーを展開し、次のメンバーの位置を返す
+fn member(data: &[u8], pos: usize, out: &mut Vec<u8>) -> io::Result<usize> {
+    let header = data
+        .get(pos..pos + HEADER_LEN)
+        .ok_or_else(|| invalid("truncated header"))?;
+    if header[..2] != MAGIC || header[2] != CM_DEFLATE {
+        return Err(invalid("not a gzip file"));
+    }
+    let flags = header[3];
+    let mut p = pos + HEADER_LEN;
+    if flags & FEXTRA != 0 {
+        let len = data
+            .get(p..p + 2)
+            .ok_or_else(|| invalid("truncated header"))?;
+        p += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let nul = data
+                .get(p..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(|| invalid("truncated header"))?;
+            p += nul + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        p += 2;
+    }
+
+    let start = out.len();
+    let mut bits = Bits::new(data.get(p..).ok_or_else(|| invalid("truncated header"))?);
+    inflate(&mut bits, out, start)?;
+    p += bits.pos;
+
+    let trailer = data
+        .get(p..p + TRAILER_LEN)
+        .ok_or_else(|| invalid("truncated trailer"))?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+        return Err(invalid("checksum mismatch"));
+    }
+    Ok(p + TRAILER_LEN)
+}
+
+// 下位ビットから読む（必要な分だけバイトを進める）
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn read(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let b = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("unexpected end of data"))?;
+            self.pos += 1;
+            self.buf |= (b as u32) << self.count;
+            self.count += 8;
+        }
+        let v = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+
+    // 無圧縮ブロックはバイト境界から始まる
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| invalid("unexpected end of data"))?;
+        self.pos += n;
+        Ok(b)
+    }
+}
+
+// 正規ハフマン符号（符号長ごとの数と、符号順に並べた記号）
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        // 符号が足りない（不完全な）のは許し、多すぎるのは壊れているとみなす
+        let mut left = 1i32;
+        for &c in &count[1..] {
+            left = (left << 1) - c as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed code"));
+            }
+        }
+        let mut offset = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offset[len + 1] = offset[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offset[len as usize] as usize] = sym as u16;
+                offset[len as usize] += 1;
+            }
+        }
+        Ok(Self { count, symbol })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.count[1..] {
+            code |= bits.read(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid code"))
+    }
+}
+
+// startより前（前のメンバー）は参照させない
+fn inflate(bits: &mut Bits, out: &mut Vec<u8>, start: usize) -> io::Result<()> {
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => stored(bits, out)?,
+            1 => {
+                let (lit, dist) = fixed_codes()?;
+                codes(bits, out, start, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(bits)?;
+                codes(bits, out, start, &lit, &dist)?;
+            }
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> io::Result<()> {
+    bits.align();
+    let header = bits.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(invalid("stored block length mismatch"));
+    }
+    out.extend_from_slice(bits.bytes(len as usize)?);
+    Ok(())
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    let nlen = bits.read(5)? as usize + 257;
+    let ndist = bits.read(5)? as usize + 1;
+    let ncode = bits.read(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(invalid("too many codes"));
+    }
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..ncode] {
+        code_lengths[i] = bits.read(3)? as u8;
+    }
+    let lencode = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (len, repeat) = match lencode.decode(bits)? {
+            sym @ 0..16 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeat with no length"))?;
+                (prev, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        if lengths.len() + repeat as usize > nlen + ndist {
+            return Err(invalid("too many lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(invalid("no end-of-block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    start: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let sym = lit.decode(bits)?;
+        if sym < END_OF_BLOCK {
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == END_OF_BLOCK {
+            return Ok(());
+        }
+        let i = (sym - END_OF_BLOCK - 1) as usize;
+        if i >= LEN_BASE.len() {
+            return Err(invalid("invalid length code"));
+        }
+        let len = LEN_BASE[i] as usize + bits.read(LEN_EXTRA[i] as u32)? as usize;
+        let d = dist.decode(bits)? as usize;
+        if d >= DIST_BASE.len() {
+            return Err(invalid("invalid distance code"));
+        }
+        let distance = DIST_BASE[d] as usize + bits.read(DIST_EXTRA[d] as u32)? as usize;
+        if distance > out.len() - start {
+            return Err(invalid("distance too far back"));
+        }
+        // 重なっていることがあるので1バイトずつ写す
+        let from = out.len() - distance;
+        for k in 0..len {
+            out.push(out[from + k]);
+        }
+    }
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pythonのgzip.compress(..., mtime=0)で作ったもの
+    // 固定ハフマン符号のブロック（かな /仮名/\n）
+    const FIXED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x7b, 0xdc, 0xd8, 0xfd, 0xb8,
+        0x71, 0x95, 0x82, 0xfe, 0x93, 0xdd, 0xeb, 0x9e, 0x4e, 0xe8, 0xd5, 0xe7, 0x02, 0x00, 0x1a,
+        0xcc, 0xa0, 0x32, 0x10, 0x00, 0x00, 0x00,
+    ];
+    // 無圧縮のブロック（compresslevel=0）
+    const STORED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x10, 0x00, 0xef, 0xff,
+        0x61, 0x62, 0x63, 0x20, 0x61, 0x62, 0x63, 0x20, 0x61, 0x62, 0x63, 0x20, 0x61, 0x62, 0x63,
+        0x0a, 0x33, 0x81, 0x83, 0xf8, 0x10, 0x00, 0x00, 0x00,
+    ];
+    // 動的ハフマン符号のブロック
+    const DYNAMIC: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x0d, 0xc8, 0xb1, 0x11, 0x00,
+        0x30, 0x08, 0x03, 0xb1, 0xde, 0x53, 0x78, 0x83, 0x07, 0x02, 0xdc, 0x65, 0xff, 0xc5, 0x12,
+        0x95, 0x0a, 0x13, 0x28, 0x4d, 0xa2, 0x32, 0x8d, 0x8e, 0xb9, 0xa8, 0xff, 0x2c, 0x1a, 0x53,
+        0x83, 0x1e, 0xb9, 0x51, 0xb2, 0x32, 0x26, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decompresses_each_block_type() {
+        assert_eq!(decompress(FIXED).unwrap(), "かな /仮名/\n".as_bytes());
+        assert_eq!(decompress(STORED).unwrap(), b"abc abc abc abc\n");
+        let squares = "0 /0/\n1 /1/\n2 /4/\n3 /9/\n4 /16/\n5 /25/\n";
+        assert_eq!(decompress(DYNAMIC).unwrap(), squares.as_bytes());
+    }
+
+    #[test]
+    fn concatenated_members_are_joined() {
+        let data = [FIXED, STORED, &[0; 4]].concat();
+        assert_eq!(
+            decompress(&data).unwrap(),
+            "かな /仮名/\nabc abc abc abc\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        for data in [&FIXED[..5], &FIXED[..20], &DYNAMIC[..30]] {
+            let e = decompress(data).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+        // 2つ目のメンバーが途中で切れている
+        let data = [FIXED, &STORED[..STORED.len() - 1]].concat();
+        assert!(decompress(&data).is_err());
+    }
+
+    #[test]
+    fn checksum_mismatch_is_an_error() {
+        let mut data = STORED.to_vec();
+        data[15] = b'x';
+        let e = decompress(&data).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn crc32_of_a_known_vector() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
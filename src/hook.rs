@@ -0,0 +1,150 @@
+//! エンジンの出来事を外に知らせる（`EVENT_HOOK`と`EVENT_LOG`）
+//!
+//! 入力モードの切り替え・変換の開始・確定・候補なしを、出来事ごとに
+//! `EVENT_LOG`のファイルへ1行ずつ追記し、`EVENT_HOOK`のコマンドを実行する。
+//! 効果音やデスクトップ通知などの連携に使う。
+
+use std::{
+    fs,
+    io::Write,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use crate::{
+    command::{self, CommandResult},
+    config::Config,
+    state::{InputState, KanaState},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineEvent {
+    Mode(&'static str),      // 入力モードが変わった（kana、katakana、latin）
+    ConversionStart(String), // 読みの変換を始めた
+    Commit(String),          // 候補を確定した
+    NoCandidates(String),    // 読みに候補がなかった
+}
+
+impl EngineEvent {
+    /// 出来事の名前（`UNSKK_EVENT`とログの1列目）
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mode(_) => "mode",
+            Self::ConversionStart(_) => "convert",
+            Self::Commit(_) => "commit",
+            Self::NoCandidates(_) => "no-candidates",
+        }
+    }
+
+    /// モードの名前、読み、または確定した語（`UNSKK_EVENT_TEXT`とログの2列目）
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Mode(mode) => mode,
+            Self::ConversionStart(s) | Self::Commit(s) | Self::NoCandidates(s) => s,
+        }
+    }
+
+    /// 読みや確定した語を空にした出来事（`--masked`用、モードの名前は残す）
+    pub fn masked(self) -> Self {
+        match self {
+            Self::Mode(mode) => Self::Mode(mode),
+            Self::ConversionStart(_) => Self::ConversionStart(String::new()),
+            Self::Commit(_) => Self::Commit(String::new()),
+            Self::NoCandidates(_) => Self::NoCandidates(String::new()),
+        }
+    }
+}
+
+/// 入力モード（読みや候補を選んでいる途中などはNone）
+pub fn input_mode(is: &InputState) -> Option<&'static str> {
+    match is {
+        InputState::Latin => Some("latin"),
+        InputState::Kana {
+            state: KanaState::Hiragana,
+            ..
+        } => Some("kana"),
+        InputState::Kana {
+            state: KanaState::Katakana,
+            ..
+        } => Some("katakana"),
+        _ => None,
+    }
+}
+
+/// 出来事を届いた順に知らせるスレッドを起動して、送り先を返す（知らせる先がなければNone）
+///
+/// ログに書き、コマンドを順に実行する（UIを止めない）。失敗はon_errorに渡す。
+/// `--masked`のときは、パスフレーズを外に出さないよう出来事の内容を空にする。
+pub fn spawn<F>(config: &Config, on_error: F) -> Option<Sender<Vec<EngineEvent>>>
+where
+    F: Fn(String) + Send + 'static,
+{
+    if config.event_hook.is_none() && config.event_log.is_none() {
+        return None;
+    }
+    let (exec, timeout) = (config.exec.clone(), config.copy_timeout);
+    let (hook, log) = (config.event_hook.clone(), config.event_log.clone());
+    let masked = config.masked;
+    let (tx, rx) = mpsc::channel::<Vec<EngineEvent>>();
+    thread::spawn(move || {
+        for mut events in rx {
+            if masked {
+                events = events.into_iter().map(EngineEvent::masked).collect();
+            }
+            let mut r = match &log {
+                Some(path) => append_log(path, &events),
+                None => Ok(()),
+            };
+            if let Some(hook) = &hook {
+                for e in &events {
+                    r = r.and(command::run_event_hook(&exec, hook, timeout, e));
+                }
+            }
+            if let Err(e) = r {
+                on_error(e);
+            }
+        }
+    });
+    Some(tx)
+}
+
+// 1行に「名前<TAB>内容」（内容の改行とタブは空白にする）
+fn append_log(path: &str, events: &[EngineEvent]) -> CommandResult {
+    let mut text = String::new();
+    for e in events {
+        text.push_str(e.name());
+        text.push('\t');
+        text.extend(
+            e.text()
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c }),
+        );
+        text.push('\n');
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .map_err(|e| format!("EVENT_LOG failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masked_events_keep_only_the_mode() {
+        let events = [
+            EngineEvent::Mode("kana"),
+            EngineEvent::ConversionStart(String::from("ひみつ")),
+            EngineEvent::Commit(String::from("秘密")),
+            EngineEvent::NoCandidates(String::from("ぱすわーど")),
+        ];
+        let texts: Vec<_> = events
+            .into_iter()
+            .map(|e| e.masked().text().to_string())
+            .collect();
+        assert_eq!(texts, ["kana", "", "", ""]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+};
+
+use crate::{
+    jisyo::{encode_candidate, join_annotation, split_candidates},
+    userjisyo::UserJisyo,
+};
+
+// 他のIMEの学習データ・個人辞書をユーザー辞書に取り込む
+// 戻り値は追加した候補の数
+pub fn import(format: &str, src: &str, user_path: &str) -> io::Result<usize> {
+    let bytes = fs::read(src)?;
+    let text = String::from_utf8(bytes).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: not UTF-8 (convert it with iconv first)", src),
+        )
+    })?;
+    let text = text.trim_start_matches('\u{FEFF}');
+    let entries = match format {
+        "mozc" => parse_mozc(text),
+        "skk" => parse_skk(text),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown import format: {} (mozc or skk)", format),
+            ));
+        }
+    };
+    let mut user = UserJisyo::load(user_path, None, false)?;
+    let mut added = 0;
+    for (yomi, cand) in entries {
+        if user.add(&yomi, &cand) {
+            added += 1;
+        }
+    }
+    user.save()?;
+    Ok(added)
+}
+
+// Mozcのユーザー辞書のエクスポート（読み<TAB>単語<TAB>品詞<TAB>コメント）
+fn parse_mozc(text: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for line in text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let mut it = line.split('\t');
+        let (Some(yomi), Some(word)) = (it.next(), it.next()) else {
+            continue;
+        };
+        if yomi.is_empty() || word.is_empty() || yomi.contains(' ') {
+            continue;
+        }
+        let comment = it.nth(1).unwrap_or_default();
+        entries.push((yomi.to_string(), to_candidate(word, comment)));
+    }
+    entries
+}
+
+// ddskk / ibus-skk の個人辞書（SKK形式、送りありの[る/帰/]ブロックは読み飛ばす）
+fn parse_skk(text: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for line in text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with(';'))
+    {
+        let Some((yomi, rest)) = line.split_once(' ') else {
+            continue;
+        };
+        let mut in_block = false;
+        for c in split_candidates(rest).into_iter().filter(|c| !c.is_empty()) {
+            if in_block {
+                in_block = c != "]";
+            } else if c.starts_with('[') {
+                in_block = true;
+            } else {
+                entries.push((yomi.to_string(), c.to_string()));
+            }
+        }
+    }
+    entries
+}
+
+fn to_candidate(word: &str, comment: &str) -> String {
+    let cand = if word.contains([';', '/']) {
+        encode_candidate(word)
+    } else {
+        word.to_string()
+    };
+    match comment {
+        "" => cand,
+        _ => join_annotation(&cand, comment),
+    }
+}
@@ -1,68 +1,1445 @@
-use std::io;
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{self, Error, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    builtin,
+    config::Config,
+    edictjisyo,
+    encoding::euc_jp_to_utf8,
+    gadget::{self, Gadget},
+    gzip, jisyocache, jsonjisyo,
+    kana::yomi_variants,
+    numeric::numeric_key,
+    palette,
+    userjisyo::UserJisyo,
+};
+
+const USER_TAG: &str = "user";
+const OKURI_ARI_HEADER: &[u8] = b";; okuri-ari entries.";
+const OKURI_NASI_HEADER: &[u8] = b";; okuri-nasi entries.";
+const LEARNED_MAX: usize = 200; // 学習した候補の一覧に出す数
+const CACHE_MIN_BYTES: usize = 1024 * 1024; // これより大きい辞書は索引を自動でキャッシュする
+const NO_SPACE: &str = "no space after the yomi"; // この誤りがあると索引を作れない
+const MERGED_HEADER: &str = ";; -*- mode: fundamental; coding: utf-8 -*-\n";
+
+// 送りありのエントリの [送り仮名/候補/...] の部分
+type OkuriBlock = (String, Vec<String>);
 
 struct SingleJisyo {
+    tag: String,
+    attached: bool,   // 実行中に足した（JISYO_PATHにない）
+    priority: i32,    // 大きい辞書から引く（同じなら指定順）
+    annotation: bool, // falseなら ; を註釈の区切りとみなさない
     text: Vec<u8>,
-    line_starts: Vec<u32>,
+    okuri_ari: Vec<u32>, // 行頭の位置（見出し語の順）
+    okuri_nasi: Vec<u32>,
+}
+
+pub type Problem = (usize, String); // 行番号と誤り
+
+// 辞書の指定（path?key=value&...）
+struct Options<'a> {
+    path: &'a str,
+    annotation: bool,
+    encoding: Encoding,
+    format: Format,
+    priority: i32,
+}
+
+// 辞書ファイルの文字コード（encoding=...）
+#[derive(Clone, Copy)]
+enum Encoding {
+    Auto, // UTF-8として読めなければEUC-JP
+    Utf8,
+    EucJp,
+}
+
+// 辞書の書式（format=...）
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Skk,
+    Json,
+    Edict, // 和英辞書。訳語を見出し語にして読み込む
+}
+
+// 記号パレットのカテゴリ（名前と記号の一覧）
+pub type PaletteCategory = (String, Vec<String>);
+
+// 候補の並べ方
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CandidateOrder {
+    Dictionary, // 辞書の記述順
+    Length,     // 辞書ごとに短い候補から
+    Frequency,  // ユーザー辞書の候補を確定した回数の多い順に（他の辞書は記述順）
+}
+
+// 複数の辞書にある読みの引き方
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JisyoLookup {
+    Merge, // すべての辞書の候補をまとめる
+    First, // 読みのあった最初の辞書（ユーザー辞書を含む）の候補だけ
+}
+
+// 読みのカタカナを候補に出すか
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KatakanaCandidate {
+    Off,
+    Fallback, // 候補が見つからないときだけ
+    Always,   // 見つかっても最後に
+}
+
+pub struct Jisyo {
+    dicts: Vec<SingleJisyo>,
+    disabled: Vec<(usize, SingleJisyo)>, // 実行中に無効にした辞書と、戻す位置
+    palette: Vec<PaletteCategory>,
+    order: CandidateOrder,
+    lookup: JisyoLookup,
+    yomi_variants: bool, // 見つからなければ ず/づ などを入れ替えた読みでも引く
+    katakana: KatakanaCandidate,
+    user: Option<UserJisyo>,
+    learning: bool, // falseならユーザー辞書は読むだけ（--masked）
 }
 
-pub struct Jisyo(Vec<SingleJisyo>);
+// 再読み込み前後で変わった見出し語の数
+pub struct JisyoDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+// --jisyo-stats：辞書ごとの見出し語の数、書式の誤り、読み込みにかかった時間
+pub struct JisyoStats {
+    pub okuri_ari: usize,
+    pub okuri_nasi: usize,
+    pub duplicates: Vec<String>, // 2行以上ある見出し語（どの行が引かれるかは決まらない）
+    pub malformed: Vec<(usize, String)>, // 行番号と誤りの内容
+    pub read_time: Duration,     // 展開・文字コードの変換を含む
+    pub index_time: Option<Duration>, // 索引を作れなければNone（起動にも失敗する）
+    pub cached: bool,            // 使える索引のキャッシュがある
+}
+
+// --merge-jisyo：まとめた辞書（UTF-8のSKK辞書）と、見出し語・候補の数
+pub struct MergedJisyo {
+    pub text: String,
+    pub entries: usize,
+    pub candidates: usize,               // 送り仮名ごとの候補も含む
+    pub skipped: Vec<(String, Problem)>, // 書式の誤りで読み飛ばした行（辞書のパスと、行番号と誤り）
+}
+
+// 見出し語ごとの、まとめた候補と送り仮名ごとの候補
+type MergedEntry = (Vec<String>, Vec<OkuriBlock>);
 
 impl Jisyo {
-    pub fn load(pathes: &str) -> io::Result<Self> {
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let mut jisyo = Self::load(&config.jisyo_path, config.candidate_order)?;
+        jisyo.set_lookup(config.jisyo_lookup);
+        jisyo.set_yomi_variants(config.yomi_variants);
+        jisyo.set_katakana_candidate(config.katakana_candidate);
+        if let Some(path) = &config.symbol_jisyo_path {
+            jisyo.load_palette(path)?;
+        }
+        // 定義ファイルのパレットは補助辞書のカテゴリの後ろ
+        if let Some(path) = &config.palette_path {
+            jisyo.palette.extend(palette::load(path)?);
+        }
+        Ok(jisyo)
+    }
+
+    pub fn load(pathes: &str, order: CandidateOrder) -> io::Result<Self> {
         let mut ret = Vec::<SingleJisyo>::new();
-        let it = pathes.split(':');
-        for path in it {
-            ret.push(SingleJisyo::load(path)?);
+        for spec in expand_pathes(pathes)? {
+            ret.push(SingleJisyo::load(&spec, false)?);
         }
-        Ok(Jisyo(ret))
+        ret.sort_by_key(|j| Reverse(j.priority));
+        Ok(Jisyo {
+            dicts: ret,
+            disabled: Vec::new(),
+            palette: Vec::new(),
+            order,
+            lookup: JisyoLookup::Merge,
+            yomi_variants: false,
+            katakana: KatakanaCandidate::Off,
+            user: None,
+            learning: true,
+        })
+    }
+
+    pub fn set_lookup(&mut self, lookup: JisyoLookup) {
+        self.lookup = lookup;
+    }
+
+    pub fn set_yomi_variants(&mut self, on: bool) {
+        self.yomi_variants = on;
+    }
+
+    pub fn set_katakana_candidate(&mut self, katakana: KatakanaCandidate) {
+        self.katakana = katakana;
+    }
+
+    /// falseにすると、確定・削除・固定・登録をユーザー辞書に記録せず、保存もしない
+    pub fn set_learning(&mut self, on: bool) {
+        self.learning = on;
+    }
+
+    // 起動直後の、まだ何も読み込んでいない辞書（読み込みはfrontendが別スレッドで行う）
+    pub fn loading(order: CandidateOrder) -> Self {
+        Jisyo {
+            dicts: Vec::new(),
+            disabled: Vec::new(),
+            palette: Vec::new(),
+            order,
+            lookup: JisyoLookup::Merge,
+            yomi_variants: false,
+            katakana: KatakanaCandidate::Off,
+            user: None,
+            learning: true,
+        }
+    }
+
+    // JISYO_PATHには必ず1つはあるので、なければ読み込み中（実行中に足した辞書は数えない）
+    pub fn is_loading(&self) -> bool {
+        let mut all = self
+            .dicts
+            .iter()
+            .chain(self.disabled.iter().map(|(_, j)| j));
+        all.all(|j| j.attached)
+    }
+
+    // --compile-jisyo：索引を作り直してキャッシュに書く（書いたファイルを返す）
+    pub fn compile(pathes: &str) -> io::Result<Vec<PathBuf>> {
+        expand_pathes(pathes)?
+            .iter()
+            .map(|spec| {
+                let j = SingleJisyo::load(spec, true)?;
+                jisyocache::write(spec, &j.text, &j.okuri_ari, &j.okuri_nasi)
+            })
+            .collect()
+    }
+
+    // 辞書ごとに調べる（読み込めなかった辞書はそのエラー）
+    pub fn stats(pathes: &str) -> io::Result<Vec<(String, io::Result<JisyoStats>)>> {
+        Ok(expand_pathes(pathes)?
+            .into_iter()
+            .map(|spec| {
+                let stats = SingleJisyo::stats(&spec);
+                (spec, stats)
+            })
+            .collect())
+    }
+
+    /// 辞書ごとの、行番号と誤り（--check-jisyo）
+    pub fn check(pathes: &str) -> io::Result<Vec<(String, io::Result<Vec<Problem>>)>> {
+        Ok(expand_pathes(pathes)?
+            .into_iter()
+            .map(|spec| {
+                let problems = SingleJisyo::check(&spec);
+                (spec, problems)
+            })
+            .collect())
+    }
+
+    /// 辞書をまとめる（--merge-jisyo）。priorityの大きい辞書、同じなら先に指定した辞書の候補を先に並べる
+    ///
+    /// 註釈を除いて同じ候補は1つにし、註釈は最初に付いていたものを残す。
+    /// 送りありは降順、送りなしは昇順の、SKK辞書と同じ並びで書く。
+    pub fn merge(pathes: &[String]) -> io::Result<MergedJisyo> {
+        let (mut okuri_ari, mut okuri_nasi) = (BTreeMap::new(), BTreeMap::new());
+        let mut skipped = Vec::new();
+        let specs = pathes.iter().map(|p| expand_pathes(p));
+        let mut specs = specs.collect::<io::Result<Vec<_>>>()?.concat();
+        let priority = |spec: &str| SingleJisyo::options(spec).map_or(0, |o| o.priority);
+        specs.sort_by_key(|spec| Reverse(priority(spec)));
+        for spec in specs {
+            let path = spec.split_once('?').map_or(spec.as_str(), |(p, _)| p);
+            let (text, annotation) = SingleJisyo::read(&spec).map_err(|e| match e {
+                // 展開や変換のエラーにはパスが入っている
+                e if e.to_string().starts_with(path) => e,
+                e => Error::new(e.kind(), format!("{}: {}", path, e)),
+            })?;
+            let mut section = None;
+            for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+                if line.starts_with(OKURI_ARI_HEADER) {
+                    section = Some(true);
+                } else if line.starts_with(OKURI_NASI_HEADER) {
+                    section = Some(false);
+                }
+                if !SingleJisyo::is_valid_line(line) {
+                    continue;
+                }
+                if let Some(problem) = SingleJisyo::problem_of(line, annotation) {
+                    skipped.push((path.to_string(), (i + 1, problem.to_string())));
+                    continue;
+                }
+                let yomi = String::from_utf8_lossy(SingleJisyo::yomi_at(line)).into_owned();
+                let Some((candidates, blocks)) = SingleJisyo::candidates_at(line) else {
+                    continue;
+                };
+                let entries = match section.unwrap_or_else(|| is_okuri_ari(yomi.as_bytes())) {
+                    true => &mut okuri_ari,
+                    false => &mut okuri_nasi,
+                };
+                let entry: &mut MergedEntry = entries.entry(yomi).or_default();
+                for c in &candidates {
+                    merge_candidate(&mut entry.0, c, annotation);
+                }
+                for (kana, words) in blocks {
+                    let i = match entry.1.iter().position(|(k, _)| *k == kana) {
+                        Some(i) => i,
+                        None => {
+                            entry.1.push((kana, Vec::new()));
+                            entry.1.len() - 1
+                        }
+                    };
+                    for w in &words {
+                        merge_candidate(&mut entry.1[i].1, w, annotation);
+                    }
+                }
+            }
+        }
+
+        let mut merged = MergedJisyo {
+            text: String::from(MERGED_HEADER),
+            entries: okuri_ari.len() + okuri_nasi.len(),
+            candidates: 0,
+            skipped,
+        };
+        let sections = [
+            (OKURI_ARI_HEADER, okuri_ari.iter().rev().collect::<Vec<_>>()),
+            (OKURI_NASI_HEADER, okuri_nasi.iter().collect()),
+        ];
+        for (header, entries) in sections {
+            merged.text.push_str(&String::from_utf8_lossy(header));
+            merged.text.push('\n');
+            for (yomi, (candidates, blocks)) in entries {
+                merged.text.push_str(yomi);
+                merged.text.push_str(" /");
+                for c in candidates {
+                    merged.text.push_str(c);
+                    merged.text.push('/');
+                }
+                for (kana, words) in blocks {
+                    merged.text.push_str(&format!("[{}/", kana));
+                    for w in words {
+                        merged.text.push_str(w);
+                        merged.text.push('/');
+                    }
+                    merged.text.push_str("]/");
+                    merged.candidates += words.len();
+                }
+                merged.text.push('\n');
+                merged.candidates += candidates.len();
+            }
+        }
+        Ok(merged)
+    }
+
+    pub fn load_user(&mut self, path: &str, half_life: Option<Duration>) -> io::Result<()> {
+        let by_frequency = self.order == CandidateOrder::Frequency;
+        self.user = Some(UserJisyo::load(path, half_life, by_frequency)?);
+        Ok(())
+    }
+
+    // 再読み込みでは、ユーザー辞書は保存前の学習ごと引き継ぐ
+    pub fn take_user_from(&mut self, old: &mut Jisyo) {
+        self.user = old.user.take();
+        self.learning = old.learning;
+    }
+
+    // 再読み込みでは、実行中に足した辞書と無効にした辞書も引き継ぐ
+    pub fn take_session_from(&mut self, old: &mut Jisyo) {
+        for (_, j) in old.disabled.iter().filter(|(_, j)| !j.attached) {
+            if let Some(i) = self.dicts.iter().position(|n| n.tag == j.tag) {
+                let n = self.dicts.remove(i);
+                self.disabled.push((i, n));
+            }
+        }
+        self.dicts
+            .extend(mem::take(&mut old.dicts).into_iter().filter(|j| j.attached));
+        self.disabled.extend(
+            mem::take(&mut old.disabled)
+                .into_iter()
+                .filter(|(_, j)| j.attached),
+        );
+        self.dicts.sort_by_key(|j| Reverse(j.priority));
+    }
+
+    /// 実行中に辞書を足す（Jisyo::loadで読み込んだもの）。足した辞書の名前を返す
+    pub fn attach(&mut self, other: Jisyo) -> Vec<String> {
+        let tags = other.dicts.iter().map(|j| j.tag.clone()).collect();
+        for mut j in other.dicts {
+            j.attached = true;
+            self.dicts.push(j);
+        }
+        self.dicts.sort_by_key(|j| Reverse(j.priority));
+        tags
+    }
+
+    /// 名前（CANDIDATE_SOURCE=showの辞書名）の辞書を無効に、無効なら有効にする
+    ///
+    /// 有効にしたらtrue、無効にしたらfalse、その名前の辞書がなければNone。
+    pub fn toggle_dict(&mut self, tag: &str) -> Option<bool> {
+        if let Some(i) = self.dicts.iter().position(|j| j.tag == tag) {
+            let j = self.dicts.remove(i);
+            self.disabled.push((i, j));
+            return Some(false);
+        }
+        let i = self.disabled.iter().position(|(_, j)| j.tag == tag)?;
+        let (pos, j) = self.disabled.remove(i);
+        self.dicts.insert(pos.min(self.dicts.len()), j);
+        self.dicts.sort_by_key(|j| Reverse(j.priority));
+        Some(true)
+    }
+
+    /// 辞書の名前と、実行中に足したか（引く順）、その後に無効にした辞書の名前
+    pub fn dict_names(&self) -> (Vec<(&str, bool)>, Vec<&str>) {
+        let enabled = self.dicts.iter().map(|j| (j.tag.as_str(), j.attached));
+        let disabled = self.disabled.iter().map(|(_, j)| j.tag.as_str());
+        (enabled.collect(), disabled.collect())
+    }
+
+    /// 見出し語の数（送りあり・送りなしの合計）
+    pub fn entry_count(&self) -> usize {
+        self.dicts
+            .iter()
+            .map(|j| j.okuri_ari.len() + j.okuri_nasi.len())
+            .sum()
+    }
+
+    // 確定した候補をユーザー辞書に記録する（註釈は記録しない）
+    pub fn record(&mut self, yomi: &str, cand: &str, okuri: Option<&str>) {
+        if let Some(user) = self.writable_user() {
+            user.record(yomi, split_annotation(cand).0, okuri);
+        }
+    }
+
+    // 候補を外し、他の辞書にあっても出さないようにユーザー辞書に記録する
+    pub fn purge(&mut self, yomi: &str, cand: &str) {
+        if let Some(user) = self.writable_user() {
+            user.purge(yomi, split_annotation(cand).0);
+        }
+    }
+
+    // 候補を読みの先頭に固定する（固定した候補ならやめる）。固定したらtrue
+    pub fn toggle_pin(&mut self, yomi: &str, cand: &str) -> bool {
+        let Some(user) = self.writable_user() else {
+            return false;
+        };
+        let cand = split_annotation(cand).0;
+        let pinned = user.pinned(yomi).as_deref() != Some(cand);
+        user.pin(yomi, pinned.then_some(cand));
+        pinned
+    }
+
+    // 最近学習した見出し語と候補の組（ユーザー辞書がなければ空）
+    pub fn learned(&self) -> Vec<(String, String)> {
+        self.user
+            .as_ref()
+            .map(|u| u.learned(LEARNED_MAX))
+            .unwrap_or_default()
+    }
+
+    // 学習した候補をユーザー辞書から除く（Alt+Xで戻せる）
+    pub fn forget(&mut self, yomi: &str, cand: &str) {
+        if let Some(user) = self.writable_user() {
+            user.forget(yomi, cand);
+        }
+    }
+
+    // 最後に削除した候補を戻す（戻した見出し語と候補、なければNone）
+    pub fn restore_purged(&mut self) -> Option<(String, String)> {
+        self.writable_user()?.restore()
+    }
+
+    // ユーザー辞書に書けるか（学習しないときは、削除や登録の操作も出さない）
+    pub fn has_user(&self) -> bool {
+        self.user.is_some() && self.learning
+    }
+
+    fn writable_user(&mut self) -> Option<&mut UserJisyo> {
+        self.user.as_mut().filter(|_| self.learning)
+    }
+
+    // 辞書エントリ編集の結果をユーザー辞書に書き戻す
+    pub fn set_user_entry(&mut self, yomi: &str, candidates: Vec<String>) {
+        if let Some(user) = self.writable_user() {
+            user.set(yomi, candidates);
+        }
+    }
+
+    pub fn reload_user(&mut self) -> io::Result<()> {
+        match &mut self.user {
+            Some(user) => user.reload(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save_user(&mut self) -> io::Result<()> {
+        match self.writable_user() {
+            Some(user) => user.save(),
+            None => Ok(()),
+        }
+    }
+
+    // 記号パレット用の補助辞書（「カテゴリ /記号1/記号2/.../」、記述順に並ぶ）
+    // 辞書と同じくEUC-JPや.gzも読める。書式の誤った行があれば行番号を示して失敗する
+    pub fn load_palette(&mut self, path: &str) -> io::Result<()> {
+        let bytes = SingleJisyo::read_bytes(path)?;
+        let text = SingleJisyo::decode(path, bytes, Encoding::Auto)?;
+        let mut palette = Vec::new();
+        for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if !SingleJisyo::is_valid_line(line) {
+                continue;
+            }
+            let entry = str::from_utf8(line).ok().and_then(|l| {
+                let (name, _) = l.split_once(' ')?;
+                Some((name, SingleJisyo::candidates_at(line)?.0))
+            });
+            let Some((name, symbols)) = entry else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: invalid entry line", path, i + 1),
+                ));
+            };
+            if !symbols.is_empty() {
+                palette.push((name.to_string(), symbols));
+            }
+        }
+        self.palette.extend(palette);
+        Ok(())
+    }
+
+    pub fn palette(&self) -> &[PaletteCategory] {
+        &self.palette
+    }
+
+    pub fn diff(&self, new: &Jisyo) -> JisyoDiff {
+        let (old, new) = (self.entries(), new.entries());
+        let mut diff = JisyoDiff {
+            added: 0,
+            removed: 0,
+            changed: 0,
+        };
+        for (yomi, lines) in &new {
+            match old.get(yomi) {
+                None => diff.added += 1,
+                Some(old_lines) if old_lines != lines => diff.changed += 1,
+                _ => (),
+            }
+        }
+        diff.removed = old.keys().filter(|yomi| !new.contains_key(*yomi)).count();
+        diff
+    }
+
+    // 見出し語ごとの、JISYO_PATHの全辞書（無効にしたものを含む）のエントリ行
+    fn entries(&self) -> HashMap<&[u8], Vec<&[u8]>> {
+        let mut map = HashMap::<&[u8], Vec<&[u8]>>::new();
+        let disabled = self.disabled.iter().map(|(_, j)| j);
+        for j in self.dicts.iter().chain(disabled).filter(|j| !j.attached) {
+            for &start in j.okuri_ari.iter().chain(&j.okuri_nasi) {
+                let line = SingleJisyo::line_slice(&j.text, start);
+                map.entry(SingleJisyo::yomi_at(line))
+                    .or_default()
+                    .push(line);
+            }
+        }
+        map
     }
 
     pub fn lookup(&self, yomi: &str) -> Option<Vec<String>> {
-        let mut ret = Vec::<String>::new();
-        let Jisyo(vec) = self;
-        for j in vec {
-            if let Some(mut c) = j.lookup(yomi) {
-                ret.append(&mut c)
+        self.lookup_okuri(yomi, None)
+    }
+
+    // prefixで始まる送りなしの見出し語（読みの補完用、prefix自身は除く）
+    // ユーザー辞書の最近使ったものが先、次に辞書の指定順・見出し語の順
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let user = self.user.iter().flat_map(|u| u.completions(prefix));
+        let dicts = self.dicts.iter().flat_map(|j| j.completions(prefix));
+        let mut seen = HashSet::new();
+        user.chain(dicts)
+            .filter(|y| *y != prefix && !is_okuri_ari(y.as_bytes()))
+            .filter(|y| seen.insert(*y))
+            .map(str::to_string)
+            .collect()
+    }
+
+    // 候補にwordを含む見出し語と、その候補（註釈は除く）の組（漢字の読みや確定した語の再変換用）
+    // 候補がwordそのものの組が先で、それぞれユーザー辞書、辞書の指定順・見出し語の順
+    pub fn reverse_lookup(&self, word: &str) -> Vec<(String, String)> {
+        if word.is_empty() {
+            return Vec::new();
+        }
+        let user = self.user.iter().flat_map(|u| u.words());
+        let dicts = self.dicts.iter().flat_map(|j| j.containing(word));
+        let (mut exact, mut partial) = (Vec::new(), Vec::new());
+        let mut seen = HashSet::new();
+        for (yomi, cand) in user.chain(dicts) {
+            // 数値変換の見出し語（#）は読みにならない
+            if yomi.contains('#') {
+                continue;
+            }
+            let cand = match gadget::evaluate(split_annotation(cand).0) {
+                Some(Gadget::Value(s)) => s,
+                Some(Gadget::Unsupported) => continue,
+                None => split_annotation(cand).0.to_string(),
+            };
+            if !cand.contains(word) || !seen.insert((yomi, cand.clone())) {
+                continue;
+            }
+            let ignored = self.user.as_ref().map(|u| u.ignored(yomi));
+            if ignored.is_some_and(|i| i.contains(&cand)) {
+                continue;
+            }
+            match cand == word {
+                true => exact.push((yomi.to_string(), cand)),
+                false => partial.push((yomi.to_string(), cand)),
             }
         }
-        if ret.is_empty() { None } else { Some(ret) }
+        exact.append(&mut partial);
+        exact
+    }
+
+    pub fn lookup_okuri(&self, yomi: &str, okuri: Option<&str>) -> Option<Vec<String>> {
+        let candidates = self.lookup_with_sources(yomi, okuri)?;
+        Some(candidates.into_iter().map(|(cand, ..)| cand).collect())
     }
+
+    // 候補と、それを最初に載せていた辞書の名前（ユーザー辞書はuser）、引いた見出し語
+    // 見出し語は数字を#にしたものや大文字・小文字の違うものもあり、削除や固定はこれに記録する
+    pub fn lookup_with_sources(
+        &self,
+        yomi: &str,
+        okuri: Option<&str>,
+    ) -> Option<Vec<(String, String, String)>> {
+        let found = match self.lookup_folded(yomi, okuri) {
+            None if self.yomi_variants => self.lookup_variants(yomi, okuri),
+            found => found,
+        };
+        self.with_katakana(yomi, found)
+    }
+
+    // 読みのカタカナを最後の候補にする（辞書の候補にあれば足さない）
+    // 読み込み中は辞書にある語も見つからないので出さない
+    fn with_katakana(
+        &self,
+        yomi: &str,
+        found: Option<Vec<(String, String, String)>>,
+    ) -> Option<Vec<(String, String, String)>> {
+        let Some(katakana) = builtin::katakana(yomi) else {
+            return found;
+        };
+        let builtin = |katakana| (katakana, String::from(builtin::TAG), yomi.to_string());
+        match found {
+            None if self.katakana != KatakanaCandidate::Off && !self.is_loading() => {
+                Some(vec![builtin(katakana)])
+            }
+            Some(mut found) if self.katakana == KatakanaCandidate::Always => {
+                if !found
+                    .iter()
+                    .any(|(c, ..)| split_annotation(c).0 == katakana)
+                {
+                    found.push(builtin(katakana));
+                }
+                Some(found)
+            }
+            found => found,
+        }
+    }
+
+    // 英字の読みは大文字・小文字を区別せずに引く（httpでHTTPの候補も出す）
+    // 入力したとおりの見出し語の候補が先、同じ候補は最初の位置にまとめる
+    // 別の見出し語で固定した候補も、入力したとおりの見出し語の候補より先にする
+    fn lookup_folded(
+        &self,
+        yomi: &str,
+        okuri: Option<&str>,
+    ) -> Option<Vec<(String, String, String)>> {
+        let mut found = self.lookup_numeric(yomi, okuri);
+        for other in self.case_variants(yomi) {
+            for c in self.lookup_numeric(&other, okuri).unwrap_or_default() {
+                let found = found.get_or_insert_with(Vec::new);
+                let word = split_annotation(&c.0).0;
+                if !found.iter().any(|(f, ..)| split_annotation(f).0 == word) {
+                    found.push(c);
+                }
+            }
+        }
+        if let Some(found) = found.as_mut() {
+            found.sort_by_key(|(cand, _, key)| key == yomi || !self.is_pinned(key, cand));
+        }
+        found
+    }
+
+    fn is_pinned(&self, yomi: &str, cand: &str) -> bool {
+        let pin = self.user.as_ref().and_then(|u| u.pinned(yomi));
+        pin.is_some_and(|pin| decode_candidate(&pin) == decode_candidate(split_annotation(cand).0))
+    }
+
+    // 大文字・小文字だけが違う見出し語（yomi自身は除く、小文字の読みは組み込みの候補のため必ず含む）
+    fn case_variants(&self, yomi: &str) -> Vec<String> {
+        if !yomi.is_ascii() || !yomi.bytes().any(|b| b.is_ascii_alphabetic()) {
+            return Vec::new();
+        }
+        let mut variants = Vec::<String>::new();
+        let lower = yomi.to_ascii_lowercase();
+        if lower != yomi {
+            variants.push(lower);
+        }
+        // 見出し語は辞書の中で並んでいるので、先頭の1文字（大文字と小文字）で絞って探す
+        let first = &yomi[..1];
+        let mut prefixes = vec![first.to_ascii_lowercase(), first.to_ascii_uppercase()];
+        prefixes.dedup();
+        for prefix in &prefixes {
+            let user = self.user.iter().flat_map(|u| u.completions(prefix));
+            let dicts = self.dicts.iter().flat_map(|j| j.completions(prefix));
+            for y in user.chain(dicts) {
+                if y != yomi && y.eq_ignore_ascii_case(yomi) && !variants.iter().any(|v| v == y) {
+                    variants.push(y.to_string());
+                }
+            }
+        }
+        variants
+    }
+
+    // 読みの変種（ず/づ など）で引いた候補に、引いた読みを註釈として付ける
+    // ユーザー辞書で元の読みに隠した候補は出さない
+    // 隠すのは元の読みなので、引いた見出し語も元の読みとする
+    fn lookup_variants(
+        &self,
+        yomi: &str,
+        okuri: Option<&str>,
+    ) -> Option<Vec<(String, String, String)>> {
+        let ignored = self
+            .user
+            .as_ref()
+            .map(|u| u.ignored(yomi))
+            .unwrap_or_default();
+        let mut found = Vec::<(String, String, String)>::new();
+        for variant in yomi_variants(yomi) {
+            for (cand, source, _) in self.lookup_numeric(&variant, okuri).unwrap_or_default() {
+                let word = split_annotation(&cand).0;
+                if ignored.iter().any(|i| i == word)
+                    || found.iter().any(|(c, ..)| split_annotation(c).0 == word)
+                {
+                    continue;
+                }
+                let cand = format!("{};読み:{}", cand, variant);
+                found.push((cand, source, yomi.to_string()));
+            }
+        }
+        (!found.is_empty()).then_some(found)
+    }
+
+    // 読みに数字があれば、数字を#にした見出し語の候補（#0〜#3のまま）を後ろに続ける
+    fn lookup_numeric(
+        &self,
+        yomi: &str,
+        okuri: Option<&str>,
+    ) -> Option<Vec<(String, String, String)>> {
+        let with_key = |found: Vec<(String, String)>, key: &str| {
+            found
+                .into_iter()
+                .map(|(cand, source)| (cand, source, key.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let exact = self.lookup_merged(yomi, okuri).map(|f| with_key(f, yomi));
+        let Some(key) = numeric_key(yomi) else {
+            return exact;
+        };
+        let numeric = self.lookup_merged(&key, okuri).map(|f| with_key(f, &key));
+        match (exact, numeric) {
+            (Some(mut exact), Some(numeric)) => {
+                for c in numeric {
+                    if !exact.iter().any(|(e, ..)| *e == c.0) {
+                        exact.push(c);
+                    }
+                }
+                Some(exact)
+            }
+            (exact, numeric) => exact.or(numeric),
+        }
+    }
+
+    // 同じ候補（concatを展開して比べる）は最初の位置にまとめ、註釈は辞書ごとに併記する
+    // 送り仮名（okuri）が分かっていれば、それに合う [送り仮名/候補/] の候補を先にする
+    fn lookup_merged(&self, yomi: &str, okuri: Option<&str>) -> Option<Vec<(String, String)>> {
+        let mut merged = Vec::<(String, Vec<(&str, String)>, usize)>::new();
+        // ユーザー辞書（n = 0）の候補が先頭
+        // 減衰して優先しなくなった学習済みの候補は最後
+        let (mut fresh, mut stale) = self
+            .user
+            .as_ref()
+            .map(|u| u.lookup(yomi, okuri))
+            .unwrap_or_default();
+        // 最初の辞書だけ引くなら、ユーザー辞書の候補はすべて先頭
+        let first_only = self.lookup == JisyoLookup::First;
+        if first_only {
+            fresh.append(&mut stale);
+        }
+        let ignored = self
+            .user
+            .as_ref()
+            .map(|u| u.ignored(yomi))
+            .unwrap_or_default();
+        let dicts = self
+            .dicts
+            .iter()
+            .map(|j| (j.tag.as_str(), j.lookup(yomi, okuri)));
+        // 組み込みの候補（今日の日付など）は辞書の後
+        let builtin = builtin::candidates(yomi).iter().map(|c| c.to_string());
+        let sources = std::iter::once((USER_TAG, Some(fresh)))
+            .chain(dicts)
+            .chain(std::iter::once((builtin::TAG, Some(builtin.collect()))))
+            .chain(std::iter::once((USER_TAG, Some(stale))));
+        for (n, (tag, candidates)) in sources.enumerate() {
+            if first_only && !merged.is_empty() {
+                break;
+            }
+            for c in candidates.unwrap_or_default() {
+                let (cand, annotation) = split_annotation(&c);
+                if ignored.iter().any(|i| i == cand)
+                    || gadget::evaluate(cand) == Some(Gadget::Unsupported)
+                {
+                    continue;
+                }
+                let (cand, annotation) = (cand.to_string(), annotation.map(str::to_string));
+                // (concat "...")で書いた辞書と、そのまま書いた辞書の同じ語もまとめる
+                let i = match merged
+                    .iter()
+                    .position(|(c, ..)| decode_candidate(c) == decode_candidate(&cand))
+                {
+                    Some(i) => i,
+                    None => {
+                        merged.push((cand, Vec::new(), n));
+                        merged.len() - 1
+                    }
+                };
+                let annotations = &mut merged[i].1;
+                if let Some(a) = annotation
+                    && !annotations.iter().any(|(_, b)| *b == a)
+                {
+                    annotations.push((tag, a));
+                }
+            }
+        }
+        if self.order == CandidateOrder::Length {
+            // 安定ソートなので、同じ長さなら辞書の記述順のまま（ユーザー辞書は学習順のまま）
+            merged.sort_by_key(|(cand, _, n)| match n {
+                0 => (0, 0),
+                n => (*n, decode_candidate(cand).chars().count()),
+            });
+        }
+        // 固定した候補は、学習や並べ方によらず先頭（辞書からなくなっていても出す）
+        if let Some(pin) = self.user.as_ref().and_then(|u| u.pinned(yomi)) {
+            let entry = match merged
+                .iter()
+                .position(|(c, ..)| decode_candidate(c) == decode_candidate(&pin))
+            {
+                Some(i) => merged.remove(i),
+                None => (pin, Vec::new(), 0),
+            };
+            merged.insert(0, entry);
+        }
+        if merged.is_empty() {
+            return None;
+        }
+        let ret = merged
+            .into_iter()
+            .map(|(mut cand, annotations, n)| {
+                match annotations.as_slice() {
+                    [] => (),
+                    [(_, a)] => {
+                        cand.push(';');
+                        cand.push_str(a);
+                    }
+                    // 註釈が食い違うときだけ出所の辞書名を付ける
+                    _ => {
+                        for (tag, a) in &annotations {
+                            cand.push(';');
+                            cand.push_str(tag);
+                            cand.push(':');
+                            cand.push_str(a);
+                        }
+                    }
+                }
+                // 前後のユーザー辞書（n = 0と最後）の間が辞書の指定順、その後が組み込みの候補
+                let source = match n.checked_sub(1).and_then(|i| self.dicts.get(i)) {
+                    Some(j) => j.tag.clone(),
+                    None if n == self.dicts.len() + 1 => String::from(builtin::TAG),
+                    None => String::from(USER_TAG),
+                };
+                (cand, source)
+            })
+            .collect();
+        Some(ret)
+    }
+}
+
+// 候補と註釈を分ける。(concat "...") のような式の中は区切りとみなさない
+pub fn split_annotation(cand: &str) -> (&str, Option<&str>) {
+    let start = gadget::end(cand).unwrap_or(0);
+    match cand[start..].find(';') {
+        Some(i) => (&cand[..start + i], Some(&cand[start + i + 1..])),
+        None => (cand, None),
+    }
+}
+
+// 候補に註釈を付ける。/ や ; を含む註釈は候補と同じように (concat "...") で包む
+pub fn join_annotation(cand: &str, annotation: &str) -> String {
+    if annotation.contains([';', '/']) {
+        format!("{};{}", cand, encode_candidate(annotation))
+    } else {
+        format!("{};{}", cand, annotation)
+    }
+}
+
+// 「/候補/.../」を / で分ける。(concat "http://...") のように式の文字列に
+// エスケープせずに書かれた / は区切りとみなさない（文字列が閉じていなければ単純に分ける）
+pub fn split_candidates(s: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let (mut start, mut depth, mut in_string, mut escaped) = (0, 0, false, false);
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' if in_string => in_string = false,
+            _ if in_string => (),
+            // 候補の先頭の ( から対応する ) までが式
+            b'(' if depth > 0 || i == start => depth += 1,
+            b')' if depth > 0 => depth -= 1,
+            b'"' if depth > 0 => in_string = true,
+            b'/' => {
+                out.push(&s[start..i]);
+                (start, depth) = (i + 1, 0);
+            }
+            _ => (),
+        }
+    }
+    if in_string {
+        return s.split('/').collect();
+    }
+    out.push(&s[start..]);
+    out
+}
+
+// (concat "a\073b") -> a;b
+// 評価できない式はそのまま（Jisyo::lookup_mergedで候補から外す）
+pub fn decode_candidate(cand: &str) -> Cow<'_, str> {
+    match gadget::evaluate(cand) {
+        Some(Gadget::Value(s)) => Cow::Owned(s),
+        _ => Cow::Borrowed(cand),
+    }
+}
+
+const CONCAT: &str = "(concat ";
+const IGNORE: &str = "skk-ignore-dic-word";
+const PIN: &str = "unskk-pinned-word";
+
+// ; や / を含む候補を (concat "...") で包む
+pub fn encode_candidate(cand: &str) -> String {
+    let mut out = String::from(CONCAT);
+    push_quoted(&mut out, cand);
+    out.push(')');
+    out
+}
+
+// 隠す候補の一覧（ddskkと同じ (skk-ignore-dic-word "..." ...) の書式）
+pub fn decode_ignored(cand: &str) -> Option<Vec<String>> {
+    gadget::string_args(cand, IGNORE)
+}
+
+// (unskk-pinned-word "候補") -> 候補
+pub fn decode_pinned(cand: &str) -> Option<String> {
+    match gadget::string_args(cand, PIN)?.as_slice() {
+        [word] => Some(word.clone()),
+        _ => None,
+    }
+}
+
+pub fn encode_pinned(word: &str) -> String {
+    let mut out = format!("({} ", PIN);
+    push_quoted(&mut out, word);
+    out.push(')');
+    out
+}
+
+pub fn encode_ignored(words: &[String]) -> String {
+    let mut out = format!("({}", IGNORE);
+    for w in words {
+        out.push(' ');
+        push_quoted(&mut out, w);
+    }
+    out.push(')');
+    out
+}
+
+fn push_quoted(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            ';' => out.push_str("\\073"),
+            '/' => out.push_str("\\057"),
+            '\n' => out.push_str("\\n"),
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// JISYO_PATHの各パスがディレクトリかワイルドカード（*）なら、中のファイルを名前の順に並べる
+// ?以降のオプションは展開したそれぞれのファイルに付ける（? はオプションの区切りなのでワイルドカードにしない）
+fn expand_pathes(pathes: &str) -> io::Result<Vec<String>> {
+    let mut specs = Vec::new();
+    for spec in pathes.split(':') {
+        let (path, options) = match spec.split_once('?') {
+            Some((path, options)) => (path, format!("?{}", options)),
+            None => (spec, String::new()),
+        };
+        let path = Path::new(path);
+        let (dir, pattern) = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.contains('*') => {
+                (path.parent().unwrap_or(Path::new("")), Some(name))
+            }
+            _ if path.is_dir() => (path, None),
+            _ => {
+                specs.push(spec.to_string());
+                continue;
+            }
+        };
+        // "*.utf8" のように親がなければカレントディレクトリ
+        let listed = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        let entries = std::fs::read_dir(listed)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+        let mut names = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            // 隠しファイルは、パターンが . で始まるときだけ
+            let matched = match pattern {
+                Some(p) => glob_match(p, name),
+                None => !name.starts_with('.'),
+            };
+            if matched && dir.join(name).is_file() {
+                names.push(name.to_string());
+            }
+        }
+        if names.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{}: no jisyo found", path.display()),
+            ));
+        }
+        names.sort();
+        for name in names {
+            specs.push(format!("{}{}", dir.join(name).display(), options));
+        }
+    }
+    Ok(specs)
+}
+
+// * は0文字以上の任意の文字。先頭の . には明示したときだけ一致する
+fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // 最後の * の位置と、そこから試している name の位置
+    let (mut pi, mut ni, mut star) = (0, 0, None);
+    while ni < n.len() {
+        match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ni));
+                pi += 1;
+            }
+            Some(&c) if c == n[ni] => {
+                pi += 1;
+                ni += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    pi = sp + 1;
+                    ni = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
 }
 
 impl SingleJisyo {
-    fn load(path: &str) -> io::Result<Self> {
-        let text = std::fs::read(path)?;
-        let mut line_starts = Vec::new();
+    // rebuildなら索引のキャッシュを使わない
+    fn load(spec: &str, rebuild: bool) -> io::Result<Self> {
+        let (text, annotation) = Self::read(spec)?;
+        let cached = (!rebuild).then(|| jisyocache::read(spec, &text)).flatten();
+        let (okuri_ari, okuri_nasi) = match cached {
+            Some(index) => index,
+            None => {
+                let path = Self::options(spec)?.path;
+                let (ari, nasi) = Self::index(&text)
+                    .map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))?;
+                // キャッシュに書けなくても辞書は使える
+                if !rebuild && text.len() >= CACHE_MIN_BYTES {
+                    let _ = jisyocache::write(spec, &text, &ari, &nasi);
+                }
+                (ari, nasi)
+            }
+        };
+
+        Ok(Self {
+            tag: Self::tag_of(spec.split_once('?').map_or(spec, |(path, _)| path)),
+            attached: false,
+            priority: Self::options(spec)?.priority,
+            annotation,
+            text,
+            okuri_ari,
+            okuri_nasi,
+        })
+    }
+
+    // path?key=value&... の形でオプションを指定できる
+    // 展開・変換したUTF-8のSKK辞書と、; を註釈の区切りとみなすか
+    fn read(spec: &str) -> io::Result<(Vec<u8>, bool)> {
+        let options = Self::options(spec)?;
+        let bytes = Self::read_bytes(options.path)?;
+        let mut text = Self::decode(options.path, bytes, options.encoding)?;
+        match options.format {
+            Format::Skk => (),
+            Format::Json => {
+                text = jsonjisyo::to_skk(&text)
+                    .map_err(|e| Error::new(e.kind(), format!("{}: {}", options.path, e)))?;
+            }
+            Format::Edict => text = edictjisyo::to_skk(&text),
+        }
+        Ok((text, options.annotation))
+    }
+
+    fn options(spec: &str) -> io::Result<Options<'_>> {
+        let (path, options) = spec.split_once('?').unwrap_or((spec, ""));
+        let mut ret = Options {
+            path,
+            annotation: true,
+            encoding: Encoding::Auto,
+            format: match path.trim_end_matches(".gz").ends_with(".json") {
+                true => Format::Json,
+                false => Format::Skk,
+            },
+            priority: 0,
+        };
+        let invalid = |option: &str| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid jisyo option: {}", option),
+            )
+        };
+        for option in options.split('&').filter(|o| !o.is_empty()) {
+            match option.split_once('=') {
+                Some(("annotation", "on")) => ret.annotation = true,
+                Some(("annotation", "off")) => ret.annotation = false,
+                Some(("encoding", "auto")) => ret.encoding = Encoding::Auto,
+                Some(("encoding", "utf-8")) => ret.encoding = Encoding::Utf8,
+                Some(("encoding", "euc-jp")) => ret.encoding = Encoding::EucJp,
+                Some(("format", "skk")) => ret.format = Format::Skk,
+                Some(("format", "json")) => ret.format = Format::Json,
+                Some(("format", "edict")) => ret.format = Format::Edict,
+                Some(("priority", n)) => match n.parse() {
+                    Ok(n) => ret.priority = n,
+                    Err(_) => return Err(invalid(option)),
+                },
+                _ => return Err(invalid(option)),
+            }
+        }
+        Ok(ret)
+    }
 
-        if Self::is_valid_line(Self::line_slice(&text, 0)) {
-            line_starts.push(0);
+    // .gz なら展開してから読む（展開したものはディスクに置かない）
+    fn read_bytes(path: &str) -> io::Result<Vec<u8>> {
+        let bytes = std::fs::read(path)?;
+        if !path.ends_with(".gz") {
+            return Ok(bytes);
         }
+        gzip::decompress(&bytes).map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
 
-        for (i, b) in text.iter().enumerate() {
-            if *b == b'\n' && Self::is_valid_line(Self::line_slice(&text, i as u32 + 1)) {
-                line_starts.push(i as u32 + 1);
+    // 行番号と誤り（まとめて変換できなければ、変換できない行も示す）
+    fn check(spec: &str) -> io::Result<Vec<Problem>> {
+        let options = Self::options(spec)?;
+        // JSONの誤りは変換のエラーに行番号が入る（EDICTは読めない行を読み飛ばす）
+        if options.format != Format::Skk {
+            return Self::read(spec).map(|_| Vec::new());
+        }
+        let bytes = Self::read_bytes(options.path)?;
+        let mut problems = Vec::new();
+        let text = match Self::decode(options.path, bytes.clone(), options.encoding) {
+            Ok(text) => text,
+            Err(_) => {
+                let lines = || bytes.split(|&b| b == b'\n');
+                // 自動判定では、UTF-8として読める非ASCIIの行があればUTF-8の辞書とみなす
+                let euc_jp = match options.encoding {
+                    Encoding::Auto => !lines().any(|l| !l.is_ascii() && str::from_utf8(l).is_ok()),
+                    encoding => matches!(encoding, Encoding::EucJp),
+                };
+                let mut text = Vec::with_capacity(bytes.len());
+                for (i, line) in lines().enumerate() {
+                    let decoded = match euc_jp {
+                        true => euc_jp_to_utf8(line).ok().map(String::into_bytes),
+                        false => str::from_utf8(line).ok().map(|_| line.to_vec()),
+                    };
+                    match decoded {
+                        Some(line) => text.extend(line),
+                        None if euc_jp => problems.push((i + 1, String::from("invalid EUC-JP"))),
+                        None => problems.push((i + 1, String::from("invalid UTF-8"))),
+                    }
+                    text.push(b'\n');
+                }
+                text
+            }
+        };
+        let lines = text.split(|&b| b == b'\n').enumerate();
+        for (i, line) in lines.filter(|(_, l)| Self::is_valid_line(l)) {
+            if let Some(problem) = Self::problem_of(line, options.annotation) {
+                problems.push((i + 1, problem.to_string()));
             }
         }
+        problems.sort_by_key(|(i, _)| *i);
+        Ok(problems)
+    }
 
-        line_starts.sort_unstable_by(|&a, &b| {
-            let ya = Self::yomi_at(&text[a as usize..]);
-            let yb = Self::yomi_at(&text[b as usize..]);
-            ya.cmp(yb)
-        });
+    fn stats(spec: &str) -> io::Result<JisyoStats> {
+        let start = Instant::now();
+        let (text, annotation) = Self::read(spec)?;
+        let read_time = start.elapsed();
+        let mut stats = JisyoStats {
+            okuri_ari: 0,
+            okuri_nasi: 0,
+            duplicates: Vec::new(),
+            malformed: Vec::new(),
+            read_time,
+            index_time: None,
+            cached: jisyocache::read(spec, &text).is_some(),
+        };
+        let lines = text.split(|&b| b == b'\n').enumerate();
+        for (i, line) in lines.filter(|(_, l)| Self::is_valid_line(l)) {
+            if let Some(problem) = Self::problem_of(line, annotation) {
+                stats.malformed.push((i + 1, problem.to_string()));
+            }
+        }
+        // 見出し語の後に空白のない行があると索引を作れない
+        if stats.malformed.iter().any(|(_, p)| p == NO_SPACE) {
+            return Ok(stats);
+        }
+        let start = Instant::now();
+        let (okuri_ari, okuri_nasi) = Self::index(&text)?;
+        stats.index_time = Some(start.elapsed());
+        (stats.okuri_ari, stats.okuri_nasi) = (okuri_ari.len(), okuri_nasi.len());
+        // 並べ替えた索引では、同じ見出し語は隣り合う
+        for starts in [&okuri_ari, &okuri_nasi] {
+            let yomi: Vec<&[u8]> = starts
+                .iter()
+                .map(|&s| Self::yomi_at(&text[s as usize..]))
+                .collect();
+            for pair in yomi.windows(2).filter(|p| p[0] == p[1]) {
+                let y = String::from_utf8_lossy(pair[0]).into_owned();
+                if stats.duplicates.last() != Some(&y) {
+                    stats.duplicates.push(y);
+                }
+            }
+        }
+        Ok(stats)
+    }
 
-        Ok(Self { text, line_starts })
+    // 「読み /候補/.../」の形になっていない行の誤り
+    fn problem_of(line: &[u8], annotation: bool) -> Option<&'static str> {
+        let Some(i) = line.iter().position(|&b| b == b' ') else {
+            return Some(NO_SPACE);
+        };
+        let rest = &line[i + 1..];
+        if !rest.starts_with(b"/") {
+            return Some("candidates must start with '/'");
+        } else if !rest.ends_with(b"/") {
+            return Some("missing '/' at the end");
+        } else if rest.iter().all(|&b| b == b'/') {
+            return Some("no candidates");
+        }
+        let rest = str::from_utf8(&rest[1..rest.len() - 1]).ok()?;
+        let mut in_block = false;
+        for s in split_candidates(rest) {
+            match s.strip_prefix('[') {
+                _ if s.is_empty() => return Some("empty candidate ('//')"),
+                Some(kana) if !in_block && !kana.is_empty() => in_block = true,
+                _ if s == "]" && !in_block => return Some("']' without an okuri block"),
+                _ if s == "]" => in_block = false,
+                _ if s.starts_with(CONCAT) && gadget::end(s).is_none() => {
+                    return Some("unterminated (concat ...)");
+                }
+                _ if !annotation => (),
+                _ => match split_annotation(s) {
+                    ("", _) => return Some("empty candidate before ';'"),
+                    (_, Some("")) => return Some("empty annotation after ';'"),
+                    _ => (),
+                },
+            }
+        }
+        in_block.then_some("okuri block '[' without ']'")
     }
 
-    fn lookup(&self, yomi: &str) -> Option<Vec<String>> {
+    // 送りあり・送りなしそれぞれの、見出し語の順に並べた行頭の位置
+    // 見出し語の後に空白のない行があれば、引けないので読み込みを失敗させる
+    fn index(text: &[u8]) -> io::Result<(Vec<u32>, Vec<u32>)> {
+        let (mut okuri_ari, mut okuri_nasi) = (Vec::new(), Vec::new());
+        // 見出しのコメントがあればそれに従い、なければ見出し語の形で分ける
+        let mut section = None;
+        let newlines = text.iter().enumerate().filter(|(_, b)| **b == b'\n');
+        let starts = std::iter::once(0).chain(newlines.map(|(i, _)| i as u32 + 1));
+        for (n, start) in starts.enumerate() {
+            let line = Self::line_slice(text, start);
+            if line.starts_with(OKURI_ARI_HEADER) {
+                section = Some(true);
+            } else if line.starts_with(OKURI_NASI_HEADER) {
+                section = Some(false);
+            }
+            if !Self::is_valid_line(line) {
+                continue;
+            }
+            if !line.contains(&b' ') {
+                let msg = format!("line {}: {}", n + 1, NO_SPACE);
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+            match section.unwrap_or_else(|| is_okuri_ari(Self::yomi_at(line))) {
+                true => okuri_ari.push(start),
+                false => okuri_nasi.push(start),
+            }
+        }
+
+        // 辞書ファイルは送りなしが昇順、送りありが降順に並んでいるので、
+        // 確かめて並んでいれば（そのままか、逆順にするだけで）並べ直さない
+        let yomi = |start: u32| Self::yomi_at(&text[start as usize..]);
+        for starts in [&mut okuri_ari, &mut okuri_nasi] {
+            if starts.is_sorted_by_key(|&s| yomi(s)) {
+                continue;
+            }
+            if starts.is_sorted_by(|&a, &b| yomi(a) >= yomi(b)) {
+                starts.reverse();
+                continue;
+            }
+            starts.sort_unstable_by(|&a, &b| yomi(a).cmp(yomi(b)));
+        }
+        Ok((okuri_ari, okuri_nasi))
+    }
+
+    // 候補はUTF-8として取り出すので、読み込み時に変換しておく
+    fn decode(path: &str, bytes: Vec<u8>, encoding: Encoding) -> io::Result<Vec<u8>> {
+        let euc_jp = |bytes: &[u8]| {
+            euc_jp_to_utf8(bytes)
+                .map(String::into_bytes)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))
+        };
+        match encoding {
+            Encoding::EucJp => euc_jp(&bytes),
+            Encoding::Auto if str::from_utf8(&bytes).is_err() => euc_jp(&bytes),
+            Encoding::Utf8 if str::from_utf8(&bytes).is_err() => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: not UTF-8", path),
+            )),
+            _ => Ok(bytes),
+        }
+    }
+
+    // SKK-JISYO.L -> L, user.jisyo -> user
+    fn tag_of(path: &str) -> String {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let name = name.strip_prefix("SKK-JISYO.").unwrap_or(name);
+        let name = name.trim_start_matches('.');
+        match name.split_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+            _ => name.to_string(),
+        }
+    }
+
+    fn lookup(&self, yomi: &str, okuri: Option<&str>) -> Option<Vec<String>> {
         let text = &self.text;
         let yomi = yomi.as_bytes();
+        let starts = match is_okuri_ari(yomi) {
+            true => &self.okuri_ari,
+            false => &self.okuri_nasi,
+        };
 
-        let idx = self
-            .line_starts
+        let idx = starts
             .binary_search_by(|&start| Self::yomi_at(&text[start as usize..]).cmp(yomi))
             .ok()?;
 
-        Self::candidates_at(Self::line_slice(text, self.line_starts[idx]))
+        let (mut candidates, blocks) = Self::candidates_at(Self::line_slice(text, starts[idx]))?;
+        // 重なる候補はJisyo::lookup_okuriで先の位置にまとめられる
+        if let Some(okuri) = okuri
+            && let Some((_, block)) = blocks.into_iter().find(|(kana, _)| kana == okuri)
+        {
+            candidates.splice(0..0, block);
+        }
+        if self.annotation {
+            return Some(candidates);
+        }
+        Some(
+            candidates
+                .into_iter()
+                .map(|c| {
+                    if gadget::end(&c).is_none() && c.contains(';') {
+                        encode_candidate(&c)
+                    } else {
+                        c
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    // prefixで始まる送りなしの見出し語（見出し語の順）
+    fn completions<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let text = &self.text;
+        let prefix = prefix.as_bytes();
+        let from = self
+            .okuri_nasi
+            .partition_point(|&start| Self::yomi_at(&text[start as usize..]) < prefix);
+        self.okuri_nasi[from..]
+            .iter()
+            .map(move |&start| Self::yomi_at(&text[start as usize..]))
+            .take_while(move |y| y.starts_with(prefix))
+            .filter_map(|y| str::from_utf8(y).ok())
+    }
+
+    // 候補にwordを含むかもしれない行の、見出し語と候補（送りありの[...]の中を含む）
+    fn containing<'a>(&'a self, word: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let text = &self.text;
+        let word = word.as_bytes();
+        self.okuri_nasi
+            .iter()
+            .chain(&self.okuri_ari)
+            .map(move |&start| Self::line_slice(text, start))
+            .filter(move |line| line.windows(word.len()).any(|w| w == word))
+            .filter_map(|line| str::from_utf8(line).ok()?.split_once(' '))
+            .flat_map(|(yomi, rest)| {
+                // [ で始まるのは送り仮名
+                split_candidates(rest)
+                    .into_iter()
+                    .filter(|s| !s.is_empty() && !s.starts_with('[') && *s != "]")
+                    .map(move |s| (yomi, s))
+            })
     }
 
     fn is_valid_line(line: &[u8]) -> bool {
@@ -78,31 +1455,177 @@ impl SingleJisyo {
         &text[start..end]
     }
 
+    // 索引にある行には空白がある（indexで確かめている）
     fn yomi_at(line: &[u8]) -> &[u8] {
-        for (i, b) in line.iter().enumerate() {
-            match *b {
-                b' ' => return &line[..i],
-                b'\n' => break,
-                _ => (),
+        let end = line.iter().position(|&b| b == b' ' || b == b'\n');
+        &line[..end.unwrap_or(line.len())]
+    }
+
+    // 候補と、[送り仮名/候補/...] のまとまり（候補には含めない）
+    fn candidates_at(line: &[u8]) -> Option<(Vec<String>, Vec<OkuriBlock>)> {
+        let line = str::from_utf8(line).expect("converting to utf8 failed");
+        let (_yomi, rest) = line.split_once(' ')?;
+        if !rest.starts_with('/') {
+            return None;
+        }
+        let mut candidates = Vec::new();
+        let mut blocks = Vec::<OkuriBlock>::new();
+        let mut in_block = false;
+        for s in split_candidates(rest).into_iter().filter(|s| !s.is_empty()) {
+            match s.strip_prefix('[') {
+                Some(kana) if !in_block && !kana.is_empty() => {
+                    blocks.push((kana.to_string(), Vec::new()));
+                    in_block = true;
+                }
+                _ if in_block && s == "]" => in_block = false,
+                _ if in_block => blocks.last_mut().unwrap().1.push(s.to_string()),
+                _ => candidates.push(s.to_string()),
             }
         }
-        panic!("jisyo entry line does not contain whitespace")
+        Some((candidates, blocks))
     }
+}
 
-    fn candidates_at(line: &[u8]) -> Option<Vec<String>> {
-        let line = str::from_utf8(line).expect("converting to utf8 failed");
+// 註釈を除いて同じ候補がなければ足す。あれば、註釈がないときだけ足す候補の註釈を付ける
+// annotation=offの辞書の ; は註釈の区切りではないので、(concat "...") で包んでから比べる
+fn merge_candidate(candidates: &mut Vec<String>, cand: &str, annotation: bool) {
+    let cand = match split_annotation(cand).1 {
+        Some(_) if !annotation => Cow::Owned(encode_candidate(cand)),
+        _ => Cow::Borrowed(cand),
+    };
+    let (word, note) = split_annotation(&cand);
+    match candidates
+        .iter_mut()
+        .find(|c| split_annotation(c).0 == word)
+    {
+        Some(c) if note.is_some() && split_annotation(c).1.is_none() => *c = cand.into_owned(),
+        Some(_) => (),
+        None => candidates.push(cand.into_owned()),
+    }
+}
 
-        if let Some((_yomi, rest)) = line.split_once(' ')
-            && rest.starts_with('/')
-        {
-            Some(
-                rest.split('/')
-                    .filter(|s| !s.is_empty())
-                    .map(|s| s.to_string())
-                    .collect(),
-            )
-        } else {
-            None
-        }
+// かなの後に送り仮名の子音が付いた見出し語（おおk）
+pub fn is_okuri_ari(yomi: &[u8]) -> bool {
+    !yomi.is_ascii() && yomi.last().is_some_and(|b| b.is_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testutil::TempPath;
+
+    // 辞書と（まだない）ユーザー辞書のパス。ユーザー辞書は辞書のdropで一緒に消える
+    fn temp_jisyo(text: &str) -> (TempPath, PathBuf) {
+        let path = TempPath::new("jisyo.utf8");
+        let user = path.with_extension("user");
+        fs::write(&path, text).unwrap();
+        (path, user)
+    }
+
+    fn load_with_user(path: &Path, user: &Path) -> Jisyo {
+        let mut jisyo = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary).unwrap();
+        jisyo.load_user(user.to_str().unwrap(), None).unwrap();
+        jisyo
+    }
+
+    #[test]
+    fn no_learning_keeps_the_user_jisyo_untouched() {
+        let (path, user) = temp_jisyo("ひみつ /秘密/\n");
+        let mut jisyo = load_with_user(&path, &user);
+        jisyo.set_learning(false);
+        assert!(!jisyo.has_user());
+        jisyo.record("ひみつ", "秘密", None);
+        jisyo.purge("ひみつ", "秘密");
+        assert!(!jisyo.toggle_pin("ひみつ", "秘密"));
+        jisyo.save_user().unwrap();
+        assert!(!user.exists());
+        assert_eq!(jisyo.lookup("ひみつ"), Some(vec![String::from("秘密")]));
+
+        jisyo.set_learning(true);
+        jisyo.record("ひみつ", "秘密", None);
+        jisyo.save_user().unwrap();
+        assert!(fs::read_to_string(&user).unwrap().contains("ひみつ /秘密/"));
+    }
+
+    #[test]
+    fn palette_is_decoded_like_the_jisyo() {
+        let (path, user) = temp_jisyo("");
+        let text = ";; 記号\nやじるし /→/←/\nまる /○/●/\n";
+        fs::write(&path, crate::encoding::utf8_to_euc_jp(text).unwrap()).unwrap();
+        let mut jisyo = load_with_user(&path, &user);
+        jisyo.load_palette(path.to_str().unwrap()).unwrap();
+        let names: Vec<&str> = jisyo.palette().iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["やじるし", "まる"]);
+        assert_eq!(jisyo.palette()[0].1, ["→", "←"]);
+    }
+
+    #[test]
+    fn palette_with_a_malformed_line_is_an_error() {
+        let (path, user) = temp_jisyo("");
+        let mut jisyo = load_with_user(&path, &user);
+        fs::write(&path, "やじるし /→/\nまる\n").unwrap();
+        let e = jisyo.load_palette(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.to_string().ends_with(":2: invalid entry line"));
+        // 途中まで読んだカテゴリは足さない
+        assert!(jisyo.palette().is_empty());
+    }
+
+    #[test]
+    fn line_without_a_space_fails_the_load() {
+        let (path, _) = temp_jisyo("あ /亜/\nいい\n");
+        let Err(e) = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary) else {
+            panic!("loaded a malformed jisyo");
+        };
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.to_string().ends_with(": line 2: no space after the yomi"));
+    }
+
+    #[test]
+    fn purged_dictionary_candidate_is_hidden_until_restored() {
+        let (path, user) = temp_jisyo("かんじ /漢字/感じ/\n");
+        let mut jisyo = load_with_user(&path, &user);
+        jisyo.purge("かんじ", "漢字;註釈");
+        assert_eq!(jisyo.lookup("かんじ"), Some(vec![String::from("感じ")]));
+        // 保存して読み直しても隠したまま
+        jisyo.save_user().unwrap();
+        jisyo.reload_user().unwrap();
+        assert_eq!(jisyo.lookup("かんじ"), Some(vec![String::from("感じ")]));
+        let restored = Some((String::from("かんじ"), String::from("漢字")));
+        assert_eq!(jisyo.restore_purged(), restored);
+        assert_eq!(jisyo.lookup("かんじ").unwrap(), ["漢字", "感じ"]);
+        assert_eq!(jisyo.restore_purged(), None);
+    }
+
+    #[test]
+    fn numeric_key_follows_the_exact_yomi() {
+        let (path, user) = temp_jisyo("#かい /#1回/一階/\n1かい /一階/\n");
+        let jisyo = load_with_user(&path, &user);
+        assert_eq!(jisyo.lookup("1かい").unwrap(), ["一階", "#1回"]);
+        assert_eq!(jisyo.lookup("3かい").unwrap(), ["#1回", "一階"]);
+        let keys: Vec<String> = jisyo
+            .lookup_with_sources("1かい", None)
+            .unwrap()
+            .into_iter()
+            .map(|(.., key)| key)
+            .collect();
+        assert_eq!(keys, ["1かい", "#かい"]);
+    }
+
+    #[test]
+    fn learned_okuri_is_looked_up_by_its_kana() {
+        let (path, user) = temp_jisyo("かk /欠/書/\n");
+        let mut jisyo = load_with_user(&path, &user);
+        jisyo.record("かk", "書", Some("き"));
+        jisyo.record("かk", "欠", Some("く"));
+        assert_eq!(jisyo.lookup_okuri("かk", Some("き")).unwrap(), ["書", "欠"]);
+        assert_eq!(jisyo.lookup_okuri("かk", Some("く")).unwrap(), ["欠", "書"]);
+        // 送り仮名が分からなければ最近確定した順
+        assert_eq!(jisyo.lookup_okuri("かk", None).unwrap(), ["欠", "書"]);
+        jisyo.save_user().unwrap();
+        let text = fs::read_to_string(&user).unwrap();
+        assert!(text.contains("かk /欠/書/[く/欠/]/[き/書/]/"));
     }
 }
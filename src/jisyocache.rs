@@ -0,0 +1,108 @@
+//! 辞書の索引（並べ替えた行頭の位置）のキャッシュ
+//!
+//! 大きな辞書は行の切り出しと並べ替えに時間がかかるので、結果を
+//! `$XDG_CACHE_HOME/unskk/<辞書の指定のハッシュ>.idx`（なければ`~/.cache/unskk/`）に置いておく。
+//! 辞書の内容（展開・変換後）の長さとハッシュが一致するときだけ使う。
+
+use std::{
+    env, fs,
+    io::{self, Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"UNSKKIX1";
+const HEADER_LEN: usize = 8 + 8 + 8 + 4 + 4;
+
+/// unskkのキャッシュの置き場（`$XDG_CACHE_HOME/unskk`、なければ`~/.cache/unskk`）
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("unskk"))
+}
+
+/// 辞書の指定（`path?option`）に対応するキャッシュファイル（キャッシュの置き場がなければNone）
+pub fn cache_path(spec: &str) -> Option<PathBuf> {
+    let dir = cache_dir()?;
+    // 作業ディレクトリが違っても同じファイルを使う
+    let (path, options) = spec.split_once('?').unwrap_or((spec, ""));
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let key = format!("{}?{}", path.display(), options);
+    Some(dir.join(format!("{:016x}.idx", fnv1a(key.as_bytes()))))
+}
+
+/// キャッシュした索引（送りあり, 送りなし）。ないか、textと合わなければNone
+pub fn read(spec: &str, text: &[u8]) -> Option<(Vec<u32>, Vec<u32>)> {
+    let data = fs::read(cache_path(spec)?).ok()?;
+    let header = data.get(..HEADER_LEN)?;
+    if &header[..8] != MAGIC
+        || u64_at(header, 8) != text.len() as u64
+        || u64_at(header, 16) != fnv1a(text)
+    {
+        return None;
+    }
+    let ari = u32_at(header, 24) as usize;
+    let nasi = u32_at(header, 28) as usize;
+    let body = &data[HEADER_LEN..];
+    if body.len() != (ari + nasi) * 4 {
+        return None;
+    }
+    let starts: Vec<u32> = (0..ari + nasi).map(|i| u32_at(body, i * 4)).collect();
+    // 壊れたキャッシュで範囲外を読まないように
+    if starts.iter().any(|&s| s as usize >= text.len()) {
+        return None;
+    }
+    let nasi_starts = starts[ari..].to_vec();
+    let mut ari_starts = starts;
+    ari_starts.truncate(ari);
+    Some((ari_starts, nasi_starts))
+}
+
+/// 索引をキャッシュに書く（書いたファイルを返す）
+pub fn write(
+    spec: &str,
+    text: &[u8],
+    okuri_ari: &[u32],
+    okuri_nasi: &[u32],
+) -> io::Result<PathBuf> {
+    let path = cache_path(spec)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no cache directory (HOME is not set)"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut data = Vec::with_capacity(HEADER_LEN + (okuri_ari.len() + okuri_nasi.len()) * 4);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&(text.len() as u64).to_le_bytes());
+    data.extend_from_slice(&fnv1a(text).to_le_bytes());
+    data.extend_from_slice(&(okuri_ari.len() as u32).to_le_bytes());
+    data.extend_from_slice(&(okuri_nasi.len() as u32).to_le_bytes());
+    for start in okuri_ari.iter().chain(okuri_nasi) {
+        data.extend_from_slice(&start.to_le_bytes());
+    }
+    write_atomic(&path, &data)?;
+    Ok(path)
+}
+
+// 他のインスタンスが読んでいる途中で中身が変わらないよう、別名で書いてから置き換える
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("idx.{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
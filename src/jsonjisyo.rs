@@ -0,0 +1,327 @@
+//! JSONで書かれた辞書の読み込み
+//!
+//! `{"かんじ": ["漢字", {"candidate": "感じ", "annotation": "feeling"}]}`のような、
+//! 見出し語から候補の配列への対応を、読み込むときにSKK辞書の行に書き直す。
+//! 候補と註釈の`/`や`;`は`(concat "...")`で包むので、生成する側はSKKのエスケープを気にしなくてよい。
+
+use std::io::{self, Error, ErrorKind};
+
+use crate::jisyo::{encode_candidate, join_annotation};
+
+enum Value {
+    Str(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>), // 書いた順
+    Other,                        // 数値、true、false、null（辞書では使わない）
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+/// JSONの辞書をSKK辞書の行（UTF-8）に変換する
+pub fn to_skk(text: &[u8]) -> io::Result<Vec<u8>> {
+    let text = str::from_utf8(text).map_err(|_| invalid(String::from("not UTF-8")))?;
+    let mut reader = Reader {
+        text: text.strip_prefix('\u{feff}').unwrap_or(text),
+        pos: 0,
+    };
+    let value = reader.value()?;
+    reader.skip_space();
+    if reader.pos != reader.text.len() {
+        return Err(reader.error("extra data after the dictionary"));
+    }
+    let Value::Object(entries) = value else {
+        return Err(invalid(String::from("the dictionary must be an object")));
+    };
+    // 同じ見出し語が何度あっても1行にまとめる
+    let mut lines = Vec::<(String, Vec<String>)>::new();
+    for (yomi, candidates) in entries {
+        if yomi.is_empty() || yomi.contains(|c: char| c.is_whitespace() || c == '/') {
+            return Err(invalid(format!("invalid yomi: {:?}", yomi)));
+        }
+        let Value::Array(candidates) = candidates else {
+            return Err(invalid(format!("{}: candidates must be an array", yomi)));
+        };
+        let i = match lines.iter().position(|(y, _)| *y == yomi) {
+            Some(i) => i,
+            None => {
+                lines.push((yomi.clone(), Vec::new()));
+                lines.len() - 1
+            }
+        };
+        for c in candidates {
+            let cand = candidate(c).map_err(|e| invalid(format!("{}: {}", yomi, e)))?;
+            if !lines[i].1.contains(&cand) {
+                lines[i].1.push(cand);
+            }
+        }
+    }
+    let mut out = String::new();
+    for (yomi, candidates) in lines.iter().filter(|(_, c)| !c.is_empty()) {
+        out.push_str(yomi);
+        out.push_str(" /");
+        for c in candidates {
+            out.push_str(c);
+            out.push('/');
+        }
+        out.push('\n');
+    }
+    Ok(out.into_bytes())
+}
+
+// "候補" または {"candidate": "候補", "annotation": "註釈"} を辞書の書式に
+fn candidate(value: Value) -> Result<String, String> {
+    let (word, annotation) = match value {
+        Value::Str(word) => (word, None),
+        Value::Object(fields) => {
+            let (mut word, mut annotation) = (None, None);
+            for (key, value) in fields {
+                let Value::Str(s) = value else {
+                    return Err(format!("{} must be a string", key));
+                };
+                match key.as_str() {
+                    "candidate" => word = Some(s),
+                    "annotation" => annotation = Some(s),
+                    _ => return Err(format!("unknown field: {}", key)),
+                }
+            }
+            (word.ok_or("missing candidate")?, annotation)
+        }
+        _ => return Err(String::from("a candidate must be a string or an object")),
+    };
+    if word.is_empty() {
+        return Err(String::from("empty candidate"));
+    }
+    let out = match word.contains(['/', ';', '\n']) {
+        true => encode_candidate(&word),
+        false => word,
+    };
+    // 註釈の / や ; は join_annotation で包む。改行は空白にする
+    let out = match annotation.filter(|a| !a.is_empty()) {
+        Some(a) => join_annotation(&out, &a.replace('\n', " ")),
+        None => out,
+    };
+    Ok(out)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Reader<'_> {
+    fn value(&mut self) -> io::Result<Value> {
+        self.skip_space();
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.close(']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.close(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.close('}') {
+                    loop {
+                        self.skip_space();
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        fields.push((key, self.value()?));
+                        if self.close('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Object(fields))
+            }
+            Some(c) if c == '-' || c.is_ascii_alphanumeric() => {
+                let len = self.text[self.pos..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+                    .unwrap_or(self.text.len() - self.pos);
+                let atom = &self.text[self.pos..self.pos + len];
+                if !matches!(atom, "true" | "false" | "null") && atom.parse::<f64>().is_err() {
+                    return Err(self.error("invalid value"));
+                }
+                self.pos += len;
+                Ok(Value::Other)
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        if self.peek() != Some('"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = self
+                .next()
+                .ok_or_else(|| self.error("unterminated string"))?;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let e = self
+                        .next()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    match e {
+                        'n' => out.push('\n'),
+                        't' => out.push('\t'),
+                        'r' => out.push('\r'),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'u' => out.push(self.unicode_escape()?),
+                        '"' | '\\' | '/' => out.push(e),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    // \uXXXX（サロゲートペアは2つ続けて1文字）
+    fn unicode_escape(&mut self) -> io::Result<char> {
+        let hi = self.hex4()?;
+        let code = match hi {
+            0xd800..=0xdbff => {
+                if !self.text[self.pos..].starts_with("\\u") {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                self.pos += 2;
+                let lo = self.hex4()?;
+                if !(0xdc00..=0xdfff).contains(&lo) {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+            }
+            _ => hi,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        let n = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(n)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    // 空白の後が閉じ括弧なら読み進めてtrue
+    fn close(&mut self, c: char) -> bool {
+        self.skip_space();
+        let closed = self.peek() == Some(c);
+        if closed {
+            self.pos += 1;
+        }
+        closed
+    }
+
+    fn expect(&mut self, c: char) -> io::Result<()> {
+        self.skip_space();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // 位置は行番号で示す
+    fn error(&self, message: &str) -> Error {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        invalid(format!("line {}: {}", line, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_skk_str(json: &str) -> io::Result<String> {
+        to_skk(json.as_bytes()).map(|out| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn converts_candidates_and_annotations() {
+        let json = r#"{
+            "かんじ": ["漢字", {"candidate": "a/b", "annotation": "x;y\nz"}],
+            "えもじ": ["\ud83d\ude00", "\u00e9"],
+            "かんじ": ["感じ", "漢字"],
+            "から": []
+        }"#;
+        assert_eq!(
+            to_skk_str(json).unwrap(),
+            "かんじ /漢字/(concat \"a\\057b\");(concat \"x\\073y z\")/感じ/\nえもじ /😀/é/\n"
+        );
+    }
+
+    #[test]
+    fn ignores_a_byte_order_mark() {
+        assert_eq!(
+            to_skk_str("\u{feff}{\"あ\": [\"亜\"]}").unwrap(),
+            "あ /亜/\n"
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_a_syntax_error() {
+        let e = to_skk_str("{\n\"あ\": [\"亜\",]\n}").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "line 2: expected a value");
+        let e = to_skk_str("{\"あ\": [\"\\ud83d\"]}").unwrap_err();
+        assert_eq!(e.to_string(), "line 1: unpaired surrogate");
+        let e = to_skk_str("{\"あ\": [\"亜\"]} []").unwrap_err();
+        assert_eq!(e.to_string(), "line 1: extra data after the dictionary");
+    }
+
+    #[test]
+    fn rejects_entries_that_do_not_fit_the_jisyo() {
+        let message = |json| to_skk_str(json).unwrap_err().to_string();
+        assert_eq!(message("[]"), "the dictionary must be an object");
+        assert_eq!(message("{\"あ い\": []}"), "invalid yomi: \"あ い\"");
+        assert_eq!(
+            message("{\"あ\": \"亜\"}"),
+            "あ: candidates must be an array"
+        );
+        assert_eq!(message("{\"あ\": [\"\"]}"), "あ: empty candidate");
+        assert_eq!(
+            message("{\"あ\": [{\"word\": \"亜\"}]}"),
+            "あ: unknown field: word"
+        );
+        assert_eq!(
+            message("{\"あ\": [1]}"),
+            "あ: a candidate must be a string or an object"
+        );
+    }
+}
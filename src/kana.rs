@@ -0,0 +1,198 @@
+//! かな・英数の文字種変換
+//!
+//! 入力の状態に依存しないので、単独でも使える。
+
+use crate::tables::HIRAGANA_TO_HALFWIDTH_KATAKANA;
+
+const KANA_OFFSET: u32 = 0x60; // ひらがなとカタカナのコードポイントの差
+const ZENKAKU_OFFSET: u32 = 0xFEE0; // 半角英数記号と全角英数記号の差
+const COMBINING_DAKUTEN: char = '\u{3099}';
+const COMBINING_HANDAKUTEN: char = '\u{309A}';
+const VARIANT_CHANGES: usize = 2; // 読みの変種で入れ替える文字の数の上限
+// 読みの変種：辞書によって書き方が違う文字（小さい仮名は大きい仮名でも引く）
+const VARIANTS: &[(char, &[&str])] = &[
+    ('ゔ', &["ヴ", "う゛"]),
+    ('づ', &["ず"]),
+    ('ず', &["づ"]),
+    ('ぢ', &["じ"]),
+    ('じ', &["ぢ"]),
+    ('ぁ', &["あ"]),
+    ('ぃ', &["い"]),
+    ('ぅ', &["う"]),
+    ('ぇ', &["え"]),
+    ('ぉ', &["お"]),
+    ('ゃ', &["や"]),
+    ('ゅ', &["ゆ"]),
+    ('ょ', &["よ"]),
+    ('ゎ', &["わ"]),
+];
+
+/// ひらがなをカタカナにする（ひらがな以外はそのまま）
+///
+/// `"かな"` → `"カナ"`
+pub fn convert_to_katakana(hiragana: &str) -> String {
+    hiragana
+        .chars()
+        .map(|c| {
+            if (0x3041..=0x3096).contains(&(c as u32)) {
+                char::from_u32(c as u32 + KANA_OFFSET).unwrap()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// ひらがなと一部の記号を半角カタカナにする（濁点・半濁点は別の文字になる）
+///
+/// `"がっこう。"` → `"ｶﾞｯｺｳ｡"`
+pub fn convert_to_halfwidth_katakana(hiragana: &str) -> String {
+    let mut result = String::with_capacity(hiragana.len());
+    for c in hiragana.chars() {
+        match HIRAGANA_TO_HALFWIDTH_KATAKANA.binary_search_by_key(&c, |&(k, _)| k) {
+            Ok(idx) => result.push_str(HIRAGANA_TO_HALFWIDTH_KATAKANA[idx].1),
+            Err(_) => result.push_str(&convert_to_katakana(&c.to_string())),
+        }
+    }
+    result
+}
+
+/// カタカナ（全角・半角）をひらがなにする（`convert_to_katakana`と`convert_to_halfwidth_katakana`の逆）
+///
+/// `"カナｶﾞｯｺｳ｡"` → `"かながっこう。"`
+pub fn convert_to_hiragana(katakana: &str) -> String {
+    let mut result = String::with_capacity(katakana.len());
+    let mut rest = katakana;
+    while let Some(c) = rest.chars().next() {
+        // 半角は濁点つきの2文字を優先して1文字に戻す
+        let halfwidth = HIRAGANA_TO_HALFWIDTH_KATAKANA
+            .iter()
+            .filter(|(_, h)| rest.starts_with(h))
+            .max_by_key(|(_, h)| h.len());
+        if let Some((hiragana, h)) = halfwidth {
+            result.push(*hiragana);
+            rest = &rest[h.len()..];
+            continue;
+        }
+        result.push(if (0x30A1..=0x30F6).contains(&(c as u32)) {
+            char::from_u32(c as u32 - KANA_OFFSET).unwrap()
+        } else {
+            c
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+/// 半角の英数記号と空白を全角にする
+///
+/// `'A'` → `'Ａ'`
+pub fn convert_to_zenkaku_ascii(c: char) -> char {
+    match c {
+        '!'..='~' => char::from_u32(c as u32 + ZENKAKU_OFFSET).unwrap(),
+        ' ' => '　',
+        _ => c,
+    }
+}
+
+/// 全角の英数記号と空白を半角にする（`convert_to_zenkaku_ascii`の逆）
+///
+/// `"Ａｂｃ　１"` → `"Abc 1"`
+pub fn convert_to_hankaku_ascii(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '！'..='～' => char::from_u32(c as u32 - ZENKAKU_OFFSET).unwrap(),
+            '　' => ' ',
+            _ => c,
+        })
+        .collect()
+}
+
+/// 辞書を引く見出し語にする（カタカナはひらがなに、全角英数記号は半角に）
+///
+/// `"カンジ"` → `"かんじ"`、`"ｆｉｌｅ"` → `"file"`
+pub fn convert_to_lookup_yomi(yomi: &str) -> String {
+    convert_to_hankaku_ascii(&convert_to_hiragana(yomi))
+}
+
+/// 書き方の違う辞書を引くための読みの変種（入れ替えた文字の少ない順、元の読みは含まない）
+///
+/// `"ずつう"` → `["づつう"]`、`"ゔぁ"` → `["ヴぁ", "う゛ぁ", "ゔあ", ...]`
+pub fn yomi_variants(yomi: &str) -> Vec<String> {
+    // (読み, 入れ替えた数)
+    let mut variants = vec![(String::new(), 0)];
+    for c in yomi.chars() {
+        let alternatives = VARIANTS.iter().find(|(v, _)| *v == c).map(|(_, a)| *a);
+        let mut next = Vec::new();
+        for (v, changes) in variants {
+            if changes < VARIANT_CHANGES {
+                for a in alternatives.unwrap_or_default() {
+                    next.push((format!("{}{}", v, a), changes + 1));
+                }
+            }
+            next.push((format!("{}{}", v, c), changes));
+        }
+        variants = next;
+    }
+    variants.sort_by_key(|(_, changes)| *changes);
+    variants
+        .into_iter()
+        .filter(|(_, changes)| *changes > 0)
+        .map(|(v, _)| v)
+        .collect()
+}
+
+/// 仮名と結合用の濁点・半濁点を1文字にまとめる（NFCのうち仮名に関わる部分）
+///
+/// `"か\u{3099}ハ\u{309A}"` → `"がパ"`
+pub fn compose_voiced_marks(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        let composed = match (out.chars().last(), c) {
+            (Some(base), COMBINING_DAKUTEN) => with_dakuten(base),
+            (Some(base), COMBINING_HANDAKUTEN) => with_handakuten(base),
+            _ => None,
+        };
+        match composed {
+            Some(v) => {
+                out.pop();
+                out.push(v);
+            }
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+fn with_dakuten(base: char) -> Option<char> {
+    let v = match base {
+        'う' => 'ゔ',
+        'ウ' => 'ヴ',
+        'ワ' => 'ヷ',
+        'ヰ' => 'ヸ',
+        'ヱ' => 'ヹ',
+        'ヲ' => 'ヺ',
+        'ゝ' => 'ゞ',
+        'ヽ' => 'ヾ',
+        // カ行・サ行・タ行・ハ行は清音の次のコードポイントが濁音
+        'か' | 'き' | 'く' | 'け' | 'こ' | 'さ' | 'し' | 'す' | 'せ' | 'そ' | 'た' | 'ち'
+        | 'つ' | 'て' | 'と' | 'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' => {
+            char::from_u32(base as u32 + 1)?
+        }
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ'
+        | 'ツ' | 'テ' | 'ト' | 'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => {
+            char::from_u32(base as u32 + 1)?
+        }
+        _ => return None,
+    };
+    Some(v)
+}
+
+fn with_handakuten(base: char) -> Option<char> {
+    match base {
+        'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' | 'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => {
+            char::from_u32(base as u32 + 2)
+        }
+        _ => None,
+    }
+}
@@ -10,6 +10,8 @@ pub enum Move {
     LineTail,
     SelectLeft,
     SelectRight,
+    WordLeft,
+    WordRight,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,6 +32,9 @@ pub enum KeyEvent {
     CommitUnconverted,
     Setsuji,
     StartYomiOrOkuri(char),
+    QuoteNext,
+    StartPalette,
+    StartLearned,
 
     // --- 変換 ---
     StartConversion,
@@ -43,4 +48,24 @@ pub enum KeyEvent {
     CommitCandidateWithStartYomi(char),
     CommitCandidateWithSetsubiji,
     CancelConversion,
+    StartEntryEdit,
+    PurgeCandidate,
+    PinCandidate,
+
+    // --- 辞書エントリ編集 ---
+    MoveCandidateUp,
+    MoveCandidateDown,
+    DeleteCandidate,
+    EditAnnotation,
+
+    // --- 記号パレット ---
+    NextCategory,
+    PrevCategory,
+
+    // --- 辞書登録 ---
+    CommitRegistration,
+
+    // --- 読みの補完 ---
+    Complete,
+    CompletePrev,
 }
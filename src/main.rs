@@ -1,34 +1,107 @@
 pub mod buffer;
+pub mod builtin;
+pub mod command;
+pub mod config;
+pub mod control;
+pub mod edictjisyo;
+pub mod encoding;
 pub mod engine;
+pub mod fetch;
 pub mod frontend;
+pub mod gadget;
+pub mod gzip;
+pub mod hook;
+pub mod import;
 pub mod jisyo;
+pub mod jisyocache;
+pub mod jsonjisyo;
+pub mod kana;
 pub mod key;
+pub mod numeric;
+pub mod palette;
 pub mod romaji;
+pub mod signal;
+pub mod skkserv;
 pub mod state;
+pub mod status;
 pub mod tables;
+pub mod terminal;
+pub mod transform;
+pub mod userjisyo;
 pub mod util;
+pub mod wrap;
 
-use std::io::Result;
-use std::{
-    env,
-    fs::{File, OpenOptions},
-    panic,
-};
-use termion::{
-    raw::{IntoRawMode, RawTerminal},
-    screen::{AlternateScreen, IntoAlternateScreen},
-};
+#[cfg(all(test, feature = "prop-tests"))]
+mod proptests;
+#[cfg(test)]
+mod testutil;
 
-const DEVICE: &str = "/dev/tty";
+use crate::romaji::RomajiPreset;
+use crate::terminal::{DEVICE, TermionTerminal};
+use std::io::{Read, Result};
+use std::{fs::OpenOptions, panic};
 
 fn main() -> Result<()> {
     install_panic_hook();
-    handle_args();
-    let ui = open_alt_raw_term()?;
-    let input = open_input()?;
-    let (sh, ct, cf, j) = handle_env();
-    let jisyo = crate::jisyo::Jisyo::load(&j)?;
-    frontend::run(ui, input, jisyo, &sh, &ct, &cf)
+    // 端末の入出力は/dev/ttyなので、標準入力から読み込んでもキー入力はできる
+    let args = handle_args();
+    let mut config = crate::config::Config::from_env();
+    config.print_on_exit = args.print_on_exit;
+    config.single_line = args.single_line;
+    config.masked = args.masked;
+    config.view_only = args.view_only;
+    crate::romaji::set_failure_policy(config.romaji_failure);
+    if config.romaji_table_path.is_some() || config.romaji_preset != RomajiPreset::Qwerty {
+        crate::romaji::load_table(config.romaji_preset, config.romaji_table_path.as_deref())?;
+    }
+    // 計測ではユーザー辞書を読まない（学習で回ごとに結果が変わらないように）
+    if let Some((script, runs)) = &args.bench {
+        let load = || crate::jisyo::Jisyo::from_config(&config);
+        print!("{}", frontend::replay::bench(script, *runs, &config, load)?);
+        return Ok(());
+    }
+    // 台本の再生は端末を使わない。結果が毎回同じになるよう、辞書を読み込んでから始める
+    if let Some(script) = &args.replay {
+        let mut jisyo = crate::jisyo::Jisyo::from_config(&config)?;
+        if let Some(path) = &config.user_jisyo_path {
+            jisyo.load_user(path, config.learn_half_life)?;
+        }
+        let initial = args.initial.unwrap_or_default();
+        let report = frontend::replay::replay(script, jisyo, &config, &initial)?;
+        print!("{}", report);
+        return Ok(());
+    }
+    let mut ui = TermionTerminal::open()?;
+    // 大きな辞書は読み込みに数秒かかるので、待たずに起動する（辞書はrunが別スレッドで読み込む）
+    let mut jisyo = crate::jisyo::Jisyo::loading(config.candidate_order);
+    if let Some(path) = &config.user_jisyo_path {
+        jisyo.load_user(path, config.learn_half_life)?;
+    }
+    // 伏せ字で入力したパスフレーズをユーザー辞書に残さない
+    jisyo.set_learning(!config.masked);
+    let exit = frontend::run(&mut ui, jisyo, &config, &args.initial.unwrap_or_default())?;
+    // 代替スクリーンを抜けてから出力する
+    drop(ui);
+    if let Err(e) = &exit.saved {
+        eprintln!("ユーザー辞書の保存に失敗: {}", e);
+    }
+    if config.print_on_exit {
+        print!("{}", exit.text);
+    }
+    Ok(())
+}
+
+const BENCH_RUNS: usize = 10;
+
+#[derive(Default)]
+struct Args {
+    initial: Option<String>, // バッファに読み込むテキスト
+    print_on_exit: bool,
+    single_line: bool,
+    masked: bool,
+    view_only: bool,
+    replay: Option<String>,         // 台本のパス
+    bench: Option<(String, usize)>, // 台本のパスと回数
 }
 
 fn install_panic_hook() {
@@ -40,26 +113,189 @@ fn install_panic_hook() {
     }));
 }
 
-fn open_alt_raw_term() -> Result<AlternateScreen<RawTerminal<File>>> {
-    OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(DEVICE)?
-        .into_raw_mode()?
-        .into_alternate_screen()
-}
-
-fn open_input() -> Result<File> {
-    OpenOptions::new().read(true).open(DEVICE)
-}
-
-fn handle_args() {
+// 起動時の指定を返す（それ以外のオプションは処理して終了する）
+fn handle_args() -> Args {
     use std::process::exit;
-    let mut args = std::env::args();
-    let arg1 = args.nth(1);
+    let mut ret = Args::default();
+    let mut args = std::env::args().skip(1);
 
-    if let Some(arg) = arg1 {
+    while let Some(arg) = args.next() {
         match arg.as_str() {
+            "--import-jisyo" => {
+                let (Some(format), Some(src)) = (args.next(), args.next()) else {
+                    eprintln!("usage: unskk --import-jisyo <mozc|skk> <file>");
+                    exit(1);
+                };
+                let Ok(user_path) = std::env::var("USER_JISYO_PATH") else {
+                    eprintln!("missing environment variable: USER_JISYO_PATH");
+                    exit(1);
+                };
+                match crate::import::import(&format, &src, &user_path) {
+                    Ok(n) => {
+                        println!("imported {} candidates into {}", n, user_path);
+                        exit(0);
+                    }
+                    Err(e) => {
+                        eprintln!("import failed: {}", e);
+                        exit(1);
+                    }
+                }
+            }
+            "--check-romaji" => {
+                let Some(path) = args.next() else {
+                    eprintln!("usage: unskk --check-romaji <file>");
+                    exit(1);
+                };
+                let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path, e);
+                    exit(1);
+                });
+                let preset = crate::config::romaji_preset_from_env();
+                let (_, problems) = crate::romaji::check_table(preset, &text);
+                for p in &problems {
+                    println!("{}:{}: {}", path, p.line, p.message);
+                }
+                if problems.is_empty() {
+                    println!("{}: ok", path);
+                    exit(0);
+                }
+                exit(1);
+            }
+            "--check-jisyo" => {
+                let Some(pathes) = args.next() else {
+                    eprintln!("usage: unskk --check-jisyo <path[:path...]>");
+                    exit(1);
+                };
+                let checked = crate::jisyo::Jisyo::check(&pathes).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", pathes, e);
+                    exit(1);
+                });
+                let mut ok = true;
+                for (spec, problems) in checked {
+                    // エディタで開けるよう、オプションを除いたパスで示す
+                    let path = spec.split_once('?').map_or(spec.as_str(), |(p, _)| p);
+                    match problems {
+                        Ok(problems) if problems.is_empty() => println!("{}: ok", path),
+                        Ok(problems) => {
+                            for (line, problem) in problems {
+                                println!("{}:{}: {}", path, line, problem);
+                            }
+                            ok = false;
+                        }
+                        // 展開や変換のエラーにはパスが入っている
+                        Err(e) if e.to_string().starts_with(path) => {
+                            println!("{}", e);
+                            ok = false;
+                        }
+                        Err(e) => {
+                            println!("{}: {}", path, e);
+                            ok = false;
+                        }
+                    }
+                }
+                exit(if ok { 0 } else { 1 });
+            }
+            "--compile-jisyo" => {
+                let Some(pathes) = args.next() else {
+                    eprintln!("usage: unskk --compile-jisyo <path[:path...]>");
+                    exit(1);
+                };
+                match crate::jisyo::Jisyo::compile(&pathes) {
+                    Ok(written) => {
+                        for path in written {
+                            println!("{}", path.display());
+                        }
+                        exit(0);
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", pathes, e);
+                        exit(1);
+                    }
+                }
+            }
+            "--merge-jisyo" => {
+                let Some(out) = args.next() else {
+                    eprintln!("usage: unskk --merge-jisyo <out> <path...>");
+                    exit(1);
+                };
+                let inputs: Vec<String> = args.by_ref().collect();
+                if inputs.is_empty() {
+                    eprintln!("usage: unskk --merge-jisyo <out> <path...>");
+                    exit(1);
+                }
+                let merged = crate::jisyo::Jisyo::merge(&inputs).unwrap_or_else(|e| {
+                    eprintln!("merge failed: {}", e);
+                    exit(1);
+                });
+                // 読み飛ばした行は--check-jisyoと同じ形で示す
+                for (path, (line, problem)) in &merged.skipped {
+                    eprintln!("{}:{}: skipped: {}", path, line, problem);
+                }
+                if let Err(e) = std::fs::write(&out, &merged.text) {
+                    eprintln!("{}: {}", out, e);
+                    exit(1);
+                }
+                println!(
+                    "merged {} entries ({} candidates) into {}",
+                    merged.entries, merged.candidates, out
+                );
+                exit(0);
+            }
+            "--fetch-jisyo" => {
+                let names: Vec<String> = args.by_ref().collect();
+                if names.is_empty() {
+                    eprintln!("usage: unskk --fetch-jisyo <name...> (e.g. L jinmei)");
+                    exit(1);
+                }
+                match crate::fetch::fetch(&names) {
+                    Ok(pathes) => {
+                        let pathes: Vec<String> =
+                            pathes.iter().map(|p| p.display().to_string()).collect();
+                        println!("{}", pathes.join(":"));
+                        exit(0);
+                    }
+                    Err(e) => {
+                        eprintln!("fetch failed: {}", e);
+                        exit(1);
+                    }
+                }
+            }
+            "--jisyo-stats" => {
+                let Ok(jisyo_path) = std::env::var("JISYO_PATH") else {
+                    eprintln!("missing environment variable: JISYO_PATH");
+                    exit(1);
+                };
+                let stats = crate::jisyo::Jisyo::stats(&jisyo_path).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", jisyo_path, e);
+                    exit(1);
+                });
+                let mut ok = true;
+                for (spec, stats) in stats {
+                    ok &= print_jisyo_stats(&spec, stats);
+                }
+                exit(if ok { 0 } else { 1 });
+            }
+            "--serve" => {
+                let Some(addr) = args.next() else {
+                    eprintln!("usage: unskk --serve <addr>");
+                    exit(1);
+                };
+                let Ok(jisyo_path) = std::env::var("JISYO_PATH") else {
+                    eprintln!("missing environment variable: JISYO_PATH");
+                    exit(1);
+                };
+                let order = crate::config::candidate_order_from_env();
+                let mut jisyo = crate::jisyo::Jisyo::load(&jisyo_path, order).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", jisyo_path, e);
+                    exit(1);
+                });
+                jisyo.set_lookup(crate::config::jisyo_lookup_from_env());
+                jisyo.set_yomi_variants(crate::config::yomi_variants_from_env());
+                if let Err(e) = crate::skkserv::serve(&addr, jisyo) {
+                    eprintln!("serve failed: {}: {}", addr, e);
+                }
+                exit(1);
+            }
             "--version" | "-v" | "-V" => {
                 println!(
                     "{} | version: {} | target: {}",
@@ -69,21 +305,113 @@ fn handle_args() {
                 );
                 exit(0);
             }
+            "--view" => {
+                let Some(path) = args.next() else {
+                    eprintln!("usage: unskk --view <file>");
+                    exit(1);
+                };
+                ret.initial = Some(read_text(&path));
+                ret.view_only = true;
+            }
+            "--replay" => {
+                let Some(path) = args.next() else {
+                    eprintln!("usage: unskk --replay <script>");
+                    exit(1);
+                };
+                ret.replay = Some(path);
+            }
+            "--bench" => {
+                let Some(path) = args.next() else {
+                    eprintln!("usage: unskk --bench <script> [runs]");
+                    exit(1);
+                };
+                let runs = match args.next().map(|n| n.parse::<usize>()) {
+                    None => BENCH_RUNS,
+                    Some(Ok(n)) if n > 0 => n,
+                    Some(_) => {
+                        eprintln!("usage: unskk --bench <script> [runs]");
+                        exit(1);
+                    }
+                };
+                ret.bench = Some((path, runs));
+            }
+            path if path == "-" || !path.starts_with('-') => ret.initial = Some(read_text(path)),
+            "--print-on-exit" => ret.print_on_exit = true,
+            "--single-line" => ret.single_line = true,
+            "--masked" => ret.masked = true,
             _ => {
                 eprintln!("unknown option: {}", arg);
                 exit(1);
             }
         }
     }
+    // 伏せ字で入力したものは送出先にだけ渡し、画面や標準出力には出さない
+    if ret.masked && ret.print_on_exit {
+        eprintln!("--masked cannot be used with --print-on-exit");
+        exit(1);
+    }
+    if ret.masked && ret.replay.is_some() {
+        eprintln!("--masked cannot be used with --replay");
+        exit(1);
+    }
+    ret
+}
+
+// --jisyo-stats の1つの辞書の結果（問題がなければtrue）
+fn print_jisyo_stats(spec: &str, stats: Result<crate::jisyo::JisyoStats>) -> bool {
+    const SHOW_MAX: usize = 10; // 重複と誤りはこれだけ表示する
+    println!("{}", spec);
+    let s = match stats {
+        Ok(s) => s,
+        Err(e) => {
+            println!("  error: {}", e);
+            return false;
+        }
+    };
+    // 索引を作れない辞書は起動時の読み込みにも失敗する
+    let index = match s.index_time {
+        Some(t) => {
+            println!(
+                "  entries: {} (okuri-ari {}, okuri-nasi {})",
+                s.okuri_ari + s.okuri_nasi,
+                s.okuri_ari,
+                s.okuri_nasi
+            );
+            format!("{:.1?}", t)
+        }
+        None => String::from("failed"),
+    };
+    let cache = if s.cached { "cached" } else { "not cached" };
+    println!(
+        "  time: read {:.1?}, index {} ({})",
+        s.read_time, index, cache
+    );
+    if !s.duplicates.is_empty() {
+        let mut shown = s.duplicates[..s.duplicates.len().min(SHOW_MAX)].join(" ");
+        if s.duplicates.len() > SHOW_MAX {
+            shown.push_str(" ...");
+        }
+        println!("  duplicate yomi: {} ({})", s.duplicates.len(), shown);
+    }
+    if !s.malformed.is_empty() {
+        println!("  malformed lines: {}", s.malformed.len());
+        for (line, problem) in s.malformed.iter().take(SHOW_MAX) {
+            println!("    line {}: {}", line, problem);
+        }
+    }
+    s.malformed.is_empty()
 }
 
-fn handle_env() -> (String, String, String, String) {
-    const ENV_ERR: &str = "missing environment variable: ";
-    let (sh, ct, cf, j) = ("SHELL", "CPY_TO", "CPY_FROM", "JISYO_PATH");
-    (
-        env::var(sh).unwrap_or_else(|_| panic!("{}{}", ENV_ERR, sh)),
-        env::var(ct).unwrap_or_else(|_| panic!("{}{}", ENV_ERR, ct)),
-        env::var(cf).unwrap_or_else(|_| panic!("{}{}", ENV_ERR, cf)),
-        env::var(j).unwrap_or_else(|_| panic!("{}{}", ENV_ERR, j)),
-    )
+// 起動時にバッファへ読み込む内容（"-"なら標準入力）
+fn read_text(path: &str) -> String {
+    let mut text = String::new();
+    let r = match path {
+        "-" => std::io::stdin().read_to_string(&mut text).map(|_| ()),
+        _ => std::fs::read_to_string(path).map(|t| text = t),
+    };
+    if let Err(e) = r {
+        eprintln!("{}: {}", if path == "-" { "stdin" } else { path }, e);
+        std::process::exit(1);
+    }
+    text
 }
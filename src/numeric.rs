@@ -0,0 +1,151 @@
+//! 数値変換（読みの数字を`#`にした見出し語を引き、候補の`#0`〜`#3`に数字を入れる）
+//!
+//! `1かい` → 見出し語`#かい` → 候補`#1回` → `１回`
+
+use std::borrow::Cow;
+
+use crate::kana::convert_to_zenkaku_ascii;
+
+const KANJI_DIGITS: [char; 10] = ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const SMALL_UNITS: [&str; 4] = ["", "十", "百", "千"];
+const LARGE_UNITS: [&str; 5] = ["", "万", "億", "兆", "京"];
+
+/// 読みの数字の並びをそれぞれ`#`にした見出し語（数字がなければNone）
+///
+/// `"1かい2"` → `"#かい#"`
+pub fn numeric_key(yomi: &str) -> Option<String> {
+    if !yomi.contains(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut key = String::with_capacity(yomi.len());
+    for c in yomi.chars() {
+        match c.is_ascii_digit() {
+            true if key.ends_with('#') => (),
+            true => key.push('#'),
+            false => key.push(c),
+        }
+    }
+    Some(key)
+}
+
+/// 候補の`#0`〜`#3`を、前から順に読みの数字の並びで置き換える
+///
+/// 読みの数字が足りないときや、それ以外の`#`はそのまま残す。
+pub fn expand<'a>(cand: &'a str, yomi: &str) -> Cow<'a, str> {
+    if !cand.contains('#') {
+        return Cow::Borrowed(cand);
+    }
+    let mut numbers = yomi
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty());
+    let mut out = String::with_capacity(cand.len());
+    let mut rest = cand;
+    while let Some(at) = rest.find('#') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let kind = rest[1..].chars().next().filter(|c| ('0'..='3').contains(c));
+        match kind.zip(numbers.next()) {
+            Some((kind, n)) => {
+                push_number(&mut out, kind, n);
+                rest = &rest[2..];
+            }
+            None => {
+                out.push('#');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+// #0：そのまま、#1：全角、#2：漢数字（一〇二四）、#3：位取りの漢数字（千二十四）
+fn push_number(out: &mut String, kind: char, n: &str) {
+    match kind {
+        '0' => out.push_str(n),
+        '1' => out.extend(n.chars().map(convert_to_zenkaku_ascii)),
+        '2' => out.extend(n.chars().map(kanji_digit)),
+        _ => push_positional(out, n),
+    }
+}
+
+fn kanji_digit(c: char) -> char {
+    KANJI_DIGITS[c.to_digit(10).unwrap_or(0) as usize]
+}
+
+// 4桁ごとに万・億…を付ける（十・百・千の前の一は省く）
+// 京を超える桁は位取りできないので、1桁ずつの漢数字にする
+fn push_positional(out: &mut String, n: &str) {
+    let digits = n.trim_start_matches('0');
+    if digits.is_empty() {
+        out.push(KANJI_DIGITS[0]);
+        return;
+    }
+    if digits.len() > LARGE_UNITS.len() * 4 {
+        out.extend(digits.chars().map(kanji_digit));
+        return;
+    }
+    let len = digits.len();
+    let mut group_used = false;
+    for (i, c) in digits.chars().enumerate() {
+        let place = len - 1 - i;
+        let (small, large) = (place % 4, place / 4);
+        let d = c.to_digit(10).unwrap_or(0) as usize;
+        if d != 0 {
+            if d != 1 || small == 0 {
+                out.push(KANJI_DIGITS[d]);
+            }
+            out.push_str(SMALL_UNITS[small]);
+            group_used = true;
+        }
+        if small == 0 && group_used {
+            out.push_str(LARGE_UNITS[large]);
+            group_used = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positional(n: &str) -> String {
+        let mut out = String::new();
+        push_positional(&mut out, n);
+        out
+    }
+
+    #[test]
+    fn numeric_key_joins_each_run_of_digits() {
+        assert_eq!(numeric_key("1かい2").as_deref(), Some("#かい#"));
+        assert_eq!(numeric_key("2024ねん").as_deref(), Some("#ねん"));
+        assert_eq!(numeric_key("かい"), None);
+    }
+
+    #[test]
+    fn positional_kanji_numbers() {
+        assert_eq!(positional("0"), "〇");
+        assert_eq!(positional("10"), "十");
+        assert_eq!(positional("1024"), "千二十四");
+        assert_eq!(positional("10000"), "一万");
+        assert_eq!(positional("20010"), "二万十");
+        assert_eq!(positional("100010000"), "一億一万");
+        assert_eq!(positional("0012"), "十二");
+        // 京を超える桁は1桁ずつ
+        assert_eq!(
+            positional(&format!("1{}", "0".repeat(20))),
+            format!("一{}", "〇".repeat(20))
+        );
+    }
+
+    #[test]
+    fn expand_fills_each_placeholder_in_order() {
+        assert_eq!(expand("#0回", "12かい"), "12回");
+        assert_eq!(expand("#1回", "12かい"), "１２回");
+        assert_eq!(expand("#2年#3月", "2024ねん11がつ"), "二〇二四年十一月");
+        // 数字が足りなければ、それ以降の#はそのまま
+        assert_eq!(expand("#1/#1", "3"), "３/#1");
+        assert_eq!(expand("#9", "3"), "#9");
+        assert!(matches!(expand("回", "1かい"), Cow::Borrowed("回")));
+    }
+}
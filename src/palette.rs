@@ -0,0 +1,76 @@
+//! 記号パレットの定義ファイル（`PALETTE_PATH`）
+//!
+//! 数学記号・罫線・顔文字などのパレットを、SKK辞書の書式によらずに書く。
+//! `[名前]`の行から次の`[名前]`の行までが1つのパレットで、記号は空白で区切って並べる。
+//! 読み込んだパレットは`SYMBOL_JISYO_PATH`のカテゴリの後ろに並び、`Ctrl+K`で選べる。
+
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+};
+
+use crate::jisyo::{PaletteCategory, encode_candidate};
+
+/// パレットを書いた順に読み込む（同じ名前のパレットは1つにまとめる）
+pub fn load(path: &str) -> io::Result<Vec<PaletteCategory>> {
+    let text = fs::read_to_string(path)?;
+    parse(&text).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}:{}", path, e)))
+}
+
+// 空行と # で始まる行は読み飛ばす。記号の空白は \s、\ に続く文字はその文字そのもの（\# \[ \\）
+fn parse(text: &str) -> Result<Vec<PaletteCategory>, String> {
+    let mut palettes = Vec::<PaletteCategory>::new();
+    let mut current = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(format!("{}: invalid palette name: {:?}", i + 1, name));
+            }
+            current = Some(match palettes.iter().position(|(n, _)| n == name) {
+                Some(p) => p,
+                None => {
+                    palettes.push((name.to_string(), Vec::new()));
+                    palettes.len() - 1
+                }
+            });
+            continue;
+        }
+        let Some(p) = current else {
+            return Err(format!("{}: symbols before a [name] line", i + 1));
+        };
+        for symbol in line.split_whitespace().map(unescape) {
+            // 候補と同じく表示・確定のときに ; を註釈、(...) を式とみなすので包んでおく
+            let symbol = match symbol.contains([';', '/']) || symbol.starts_with('(') {
+                true => encode_candidate(&symbol),
+                false => symbol,
+            };
+            if !palettes[p].1.contains(&symbol) {
+                palettes[p].1.push(symbol);
+            }
+        }
+    }
+    palettes.retain(|(_, symbols)| !symbols.is_empty());
+    Ok(palettes)
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
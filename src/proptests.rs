@@ -0,0 +1,336 @@
+// Buffer と handle_key にランダムなキー列を与え、どの時点でも成り立つべき条件を確かめる
+// cargo test --features prop-tests
+
+use std::{collections::BTreeMap, fs};
+
+use proptest::prelude::*;
+
+use crate::{
+    buffer::Buffer,
+    engine::handle_key,
+    jisyo::{
+        CandidateOrder, Jisyo, decode_candidate, encode_candidate, join_annotation,
+        split_annotation,
+    },
+    key::{KeyEvent, Move},
+    state::{InputState, SubModes},
+    testutil::TempPath,
+    util::ClosedInterval,
+};
+
+const JISYO: &str = "\
+かんじ /漢字/感じ/幹事/
+かn /書/
+おおk /大/
+あ /亜;註/(concat \"a\\073b\")/
+>てき /的/
+";
+
+#[derive(Debug, Clone)]
+enum Op {
+    Char(char),
+    Text(String),
+    Backspace,
+    Delete,
+    Move(Move),
+    Clear,
+}
+
+fn arb_move() -> impl Strategy<Value = Move> {
+    prop_oneof![
+        Just(Move::Left),
+        Just(Move::Right),
+        Just(Move::Up),
+        Just(Move::Down),
+        Just(Move::RapidUp),
+        Just(Move::RapidDown),
+        Just(Move::LineHead),
+        Just(Move::LineTail),
+        Just(Move::SelectLeft),
+        Just(Move::SelectRight),
+        Just(Move::WordLeft),
+        Just(Move::WordRight),
+    ]
+}
+
+// 改行と全角文字を多めに混ぜる
+fn arb_char() -> impl Strategy<Value = char> {
+    prop_oneof![
+        proptest::char::range('a', 'z'),
+        Just('\n'),
+        Just(' '),
+        Just('あ'),
+        Just('漢'),
+        Just('-'),
+        Just(','),
+        Just('1'),
+    ]
+}
+
+fn arb_op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => arb_char().prop_map(Op::Char),
+        1 => proptest::collection::vec(arb_char(), 0..8)
+            .prop_map(|v| Op::Text(v.into_iter().collect())),
+        2 => Just(Op::Backspace),
+        2 => Just(Op::Delete),
+        4 => arb_move().prop_map(Op::Move),
+        1 => Just(Op::Clear),
+    ]
+}
+
+fn arb_key() -> impl Strategy<Value = KeyEvent> {
+    use KeyEvent::*;
+    prop_oneof![
+        8 => arb_char().prop_map(Char),
+        2 => Just(Backspace),
+        1 => Just(Delete),
+        3 => arb_move().prop_map(Navigation),
+        1 => Just(ToggleLatin),
+        1 => Just(ToggleKatakana),
+        1 => Just(ToggleHankakuZenkaku),
+        1 => Just(CommitUnconverted),
+        1 => Just(Setsuji),
+        2 => proptest::char::range('a', 'z').prop_map(StartYomiOrOkuri),
+        1 => Just(QuoteNext),
+        1 => Just(StartPalette),
+        1 => Just(StartLearned),
+        2 => Just(StartConversion),
+        1 => Just(StartAbbrev),
+        1 => Just(NextCandidate),
+        1 => Just(PrevCandidate),
+        1 => Just(CommitCandidate),
+        1 => proptest::char::range('a', 'z').prop_map(CommitCandidateWithChar),
+        1 => proptest::char::range('a', 'z').prop_map(CommitCandidateWithStartYomi),
+        1 => Just(CommitCandidateWithSetsubiji),
+        1 => Just(CancelConversion),
+        1 => Just(StartEntryEdit),
+        1 => Just(PinCandidate),
+    ]
+}
+
+fn apply(b: &mut Buffer, op: &Op) {
+    match op {
+        Op::Char(c) => b.insert_char(*c),
+        Op::Text(s) => b.insert_text(s),
+        Op::Backspace => b.backspace(),
+        Op::Delete => b.delete(),
+        Op::Move(Move::Left) => _ = b.move_left(),
+        Op::Move(Move::Right) => _ = b.move_right(),
+        Op::Move(Move::Up) => _ = b.move_up(),
+        Op::Move(Move::Down) => _ = b.move_down(),
+        Op::Move(Move::RapidUp) => b.rapid_up(),
+        Op::Move(Move::RapidDown) => b.rapid_down(),
+        Op::Move(Move::LineHead) => b.to_line_head(),
+        Op::Move(Move::LineTail) => b.to_line_tail(),
+        Op::Move(Move::SelectLeft) => b.select_left(),
+        Op::Move(Move::SelectRight) => b.select_right(),
+        Op::Move(Move::WordLeft) => _ = b.word_left(),
+        Op::Move(Move::WordRight) => _ = b.word_right(),
+        Op::Clear => b.clear(),
+    }
+}
+
+fn check_invariants(b: &Buffer) -> Result<(), TestCaseError> {
+    let (row, col) = b.cursor();
+    prop_assert!(b.line_count() >= 1);
+    prop_assert!(
+        row < b.line_count(),
+        "row {} / {} lines",
+        row,
+        b.line_count()
+    );
+    prop_assert!(
+        col <= b.line(row).len(),
+        "col {} / len {}",
+        col,
+        b.line(row).len()
+    );
+
+    // 選択範囲は現在行の文字の上にある（カーソル1文字分の選択は行末を指してよい）
+    let ClosedInterval(start, end) = b.selection();
+    prop_assert!(start <= end);
+    prop_assert!(start <= col && col <= end);
+    if start < end {
+        prop_assert!(
+            end < b.line(row).len(),
+            "selection {}..={} / len {}",
+            start,
+            end,
+            b.line(row).len()
+        );
+        prop_assert!(b.selected_as_string().is_some());
+    }
+
+    // as_stringは行を'\n'でつないだもので、貼り付け直すと同じ内容になる
+    let text = b.as_string();
+    prop_assert_eq!(text.split('\n').count(), b.line_count());
+    let mut copy = Buffer::default();
+    copy.insert_text(&text);
+    prop_assert_eq!(copy.as_string(), text);
+    prop_assert_eq!(b.is_empty(), b.as_string().is_empty());
+    Ok(())
+}
+
+fn test_jisyo() -> Jisyo {
+    let path = TempPath::new("proptest.jisyo");
+    fs::write(&path, JISYO).unwrap();
+    Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary).unwrap()
+}
+
+// 送りなし（かな）と送りあり（かな＋子音）の見出し語
+fn arb_yomi() -> impl Strategy<Value = String> {
+    ("[あいうかきくさしす]{1,3}", proptest::option::of("[ksm]"))
+        .prop_map(|(kana, okuri)| kana + okuri.as_deref().unwrap_or_default())
+}
+
+// 見出し語ごとの候補（註釈の付いたものもある）
+fn arb_dict() -> impl Strategy<Value = BTreeMap<String, Vec<String>>> {
+    let cand = ("[字語]{1,2}", proptest::option::of("[註a]{1,2}"))
+        .prop_map(|(word, note)| note.map_or(word.clone(), |n| join_annotation(&word, &n)));
+    proptest::collection::btree_map(arb_yomi(), proptest::collection::vec(cand, 1..4), 1..8)
+}
+
+proptest! {
+    #[test]
+    fn buffer_ops_keep_invariants(ops in proptest::collection::vec(arb_op(), 0..64)) {
+        let mut b = Buffer::default();
+        for op in &ops {
+            apply(&mut b, op);
+            check_invariants(&b)?;
+        }
+    }
+
+    // 1文字ずつの挿入と、まとめての挿入（貼り付け）は同じ結果になる
+    #[test]
+    fn insert_text_matches_insert_char(
+        ops in proptest::collection::vec(arb_op(), 0..16),
+        text in proptest::collection::vec(arb_char(), 0..16),
+    ) {
+        let mut a = Buffer::default();
+        for op in &ops {
+            apply(&mut a, op);
+        }
+        let mut b = a.clone();
+        let text: String = text.into_iter().collect();
+        a.insert_str(&text);
+        b.insert_text(&text);
+        prop_assert_eq!(a.as_string(), b.as_string());
+        prop_assert_eq!(a.cursor(), b.cursor());
+    }
+
+    // 行がどう並んでいても（昇順・降順・ばらばら）、どの見出し語も引ける
+    #[test]
+    fn jisyo_lookup_ignores_line_order(
+        yomis in proptest::collection::btree_set(arb_yomi(), 1..32),
+        order in 0..3,
+    ) {
+        let mut yomis: Vec<String> = yomis.into_iter().collect();
+        match order {
+            0 => (),
+            1 => yomis.reverse(),
+            _ => {
+                let half = yomis.len() / 2;
+                yomis.rotate_left(half);
+            }
+        }
+        let text: String = yomis.iter().map(|y| format!("{} /{}/\n", y, y.len())).collect();
+        let path = TempPath::new("proptest-order.jisyo");
+        fs::write(&path, text).unwrap();
+        let jisyo = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary).unwrap();
+        for y in &yomis {
+            let found = jisyo.lookup_with_sources(y, None);
+            prop_assert_eq!(found.map(|f| f[0].0.clone()), Some(y.len().to_string()));
+        }
+    }
+
+    // 候補から引いた読みで引き直すと、その候補が出る（候補そのものの見出し語が先頭）
+    #[test]
+    fn jisyo_reverse_lookup_round_trips(
+        yomis in proptest::collection::btree_set(arb_yomi(), 1..32),
+    ) {
+        let text: String = yomis.iter().map(|y| format!("{} /字{}/\n", y, y)).collect();
+        let path = TempPath::new("proptest-reverse.jisyo");
+        fs::write(&path, text).unwrap();
+        let jisyo = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary).unwrap();
+        for y in &yomis {
+            let word = format!("字{}", y);
+            let found = jisyo.reverse_lookup(&word);
+            prop_assert_eq!(found.first(), Some(&(y.clone(), word.clone())));
+            for (yomi, cand) in &found {
+                prop_assert!(cand.contains(&word));
+                prop_assert!(jisyo.lookup(yomi).is_some_and(|c| c.contains(cand)));
+            }
+        }
+    }
+
+    // / や ; を含む候補と註釈は、書き出して引き直しても元に戻る
+    // （エスケープせずに式の文字列に書かれた / も候補の区切りにしない）
+    #[test]
+    fn jisyo_candidate_escapes_round_trip(
+        word in "[a/;:\"\\\\あ]{1,8}",
+        annotation in "[a/;:\"\\\\あ]{1,8}",
+        host in "[a:/.]{0,8}",
+    ) {
+        let url = format!("http://{}", host);
+        let cand = join_annotation(&encode_candidate(&word), &annotation);
+        let text = format!("あ /{}/(concat \"{}\")/\n", cand, url);
+        let path = TempPath::new("proptest-escape.jisyo");
+        fs::write(&path, text).unwrap();
+        let jisyo = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary).unwrap();
+        let found = jisyo.lookup("あ").unwrap();
+        prop_assert_eq!(found.len(), 2);
+        let (w, a) = split_annotation(&found[0]);
+        prop_assert_eq!(decode_candidate(w), word);
+        prop_assert_eq!(a.map(decode_candidate), Some(annotation.into()));
+        prop_assert_eq!(decode_candidate(&found[1]), url);
+    }
+
+    // まとめた辞書からは、どの辞書の候補も1つずつ引ける（まとめ直しても変わらない）
+    #[test]
+    fn jisyo_merge_keeps_every_candidate(
+        dicts in proptest::collection::vec(arb_dict(), 1..4),
+    ) {
+        let dir = TempPath::new("proptest-merge");
+        fs::create_dir_all(&dir).unwrap();
+        let mut pathes = Vec::new();
+        for (n, dict) in dicts.iter().enumerate() {
+            let line = |(y, c): (&String, &Vec<String>)| format!("{} /{}/\n", y, c.join("/"));
+            let text: String = dict.iter().map(line).collect();
+            let path = dir.join(format!("{}.jisyo", n));
+            fs::write(&path, text).unwrap();
+            pathes.push(path.to_str().unwrap().to_string());
+        }
+        let merged = Jisyo::merge(&pathes).unwrap();
+        let path = dir.join("merged.jisyo");
+        fs::write(&path, &merged.text).unwrap();
+        let again = Jisyo::merge(&[path.to_str().unwrap().to_string()]).unwrap();
+        let jisyo = Jisyo::load(path.to_str().unwrap(), CandidateOrder::Dictionary);
+        prop_assert!(merged.skipped.is_empty());
+        prop_assert_eq!(&again.text, &merged.text);
+        let jisyo = jisyo.unwrap();
+        for dict in &dicts {
+            for (y, cands) in dict {
+                let found = jisyo.lookup(y).unwrap();
+                for c in cands {
+                    let word = split_annotation(c).0;
+                    let n = found.iter().filter(|f| split_annotation(f).0 == word).count();
+                    prop_assert_eq!(n, 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn handle_key_keeps_invariants(keys in proptest::collection::vec(arb_key(), 0..64)) {
+        let mut jisyo = test_jisyo();
+        let mut b = Buffer::default();
+        let mut sub = SubModes::default();
+        let mut is = InputState::new_kana();
+        for key in keys {
+            is = handle_key(is, &mut b, &mut jisyo, &mut sub, key);
+            check_invariants(&b)?;
+            let _ = is.status_as_string(&sub);
+        }
+    }
+}
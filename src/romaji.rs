@@ -1,32 +1,50 @@
+use std::{
+    fs, io,
+    sync::{Arc, LazyLock, RwLock},
+};
+
 use crate::tables::ROMAJI_TO_HIRAGANA;
 
-pub enum KanaMatch<'a> {
-    Success(KanaConverted<'a>),
+pub enum KanaMatch {
+    Success(KanaConverted),
     PrefixMatch,
     Failure,
 }
 
-pub struct KanaConverted<'a> {
-    pub commit: &'a str,
-    pub pushback: &'a str,
+pub struct KanaConverted {
+    pub commit: String,
+    pub pushback: String,
 }
 
-pub fn search_lookup_table(romaji: &str) -> KanaMatch<'static> {
+pub type RomajiTable = Vec<(String, String)>;
+
+// 組み込みの表か、ROMAJI_TABLE_PATHで上書きした表（読み直すときは表ごと差し替える）
+static TABLE: LazyLock<RwLock<Arc<RomajiTable>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(builtin_table())));
+
+// かな入力で別の操作に割り当てられていて、ローマ字として打てない文字
+const RESERVED: &[char] = &['q', '/', '>'];
+
+pub fn search_lookup_table(romaji: &str) -> KanaMatch {
     if romaji.is_empty() {
         return KanaMatch::Failure;
     }
 
-    let i = ROMAJI_TO_HIRAGANA.partition_point(|(k, _)| k < &romaji);
+    let table = Arc::clone(&TABLE.read().unwrap());
+    let i = table.partition_point(|(k, _)| k.as_str() < romaji);
 
-    if let Some((k, conv)) = ROMAJI_TO_HIRAGANA.get(i) {
-        if *k == romaji {
+    if let Some((k, conv)) = table.get(i) {
+        if k == romaji {
             let last = conv.len() - 1;
             let (commit, pushback) = if conv.as_bytes()[last].is_ascii_lowercase() {
                 (&conv[0..last], &conv[last..])
             } else {
-                (*conv, "")
+                (conv.as_str(), "")
             };
-            return KanaMatch::Success(KanaConverted { commit, pushback });
+            return KanaMatch::Success(KanaConverted {
+                commit: commit.to_string(),
+                pushback: pushback.to_string(),
+            });
         }
         if k.starts_with(romaji) {
             return KanaMatch::PrefixMatch;
@@ -34,3 +52,224 @@ pub fn search_lookup_table(romaji: &str) -> KanaMatch<'static> {
     }
     KanaMatch::Failure
 }
+
+// ユーザーのローマ字表（組み込みの表への追加・上書き）の1行
+// 書式：ローマ字<TAB>かな[<TAB>押し戻す文字]、かなが空なら組み込みの定義を削除
+struct Rule {
+    line: usize,
+    romaji: String,
+    kana: String,
+    pushback: String,
+}
+
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+// ROMAJI_TIMEOUT_ACTION：途中まで打ったローマ字が時間切れになったときの扱い
+#[derive(Clone, Copy)]
+pub enum TimeoutAction {
+    Flush,   // 打ったままの文字で確定（nだけなら「ん」）
+    Discard, // 捨てる
+}
+
+// ROMAJI_FAILURE：ローマ字として続かない文字を打ったときの扱い
+#[derive(Clone, Copy)]
+pub enum FailurePolicy {
+    Drop,    // 捨てる（途中のローマ字は残す）
+    Insert,  // 途中のローマ字をそのまま確定し、その文字から打ち直す
+    Beep,    // 捨ててベルを鳴らす
+    Pending, // 途中のローマ字に加えて残す（Backspaceで直す）
+}
+
+static FAILURE: RwLock<FailurePolicy> = RwLock::new(FailurePolicy::Drop);
+
+pub fn set_failure_policy(policy: FailurePolicy) {
+    *FAILURE.write().unwrap() = policy;
+}
+
+pub fn failure_policy() -> FailurePolicy {
+    *FAILURE.read().unwrap()
+}
+
+// ROMAJI_PRESET：組み込みの表に重ねる、キー配列ごとの追加定義
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RomajiPreset {
+    Qwerty,
+    Dvorak,
+    Colemak,
+}
+
+impl RomajiPreset {
+    fn rules(self) -> &'static str {
+        match self {
+            RomajiPreset::Qwerty => "",
+            RomajiPreset::Dvorak => include_str!("../presets/dvorak.tsv"),
+            RomajiPreset::Colemak => include_str!("../presets/colemak.tsv"),
+        }
+    }
+}
+
+// プリセットとユーザーの表（あれば）を検査し、問題がなければ以後の変換に使う
+pub fn load_table(preset: RomajiPreset, path: Option<&str>) -> io::Result<()> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let (table, problems) = check_table(preset, &text);
+    if let Some(p) = problems.first() {
+        let path = path.unwrap_or_default();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}:{}: {} (see unskk --check-romaji {})",
+                path, p.line, p.message, path
+            ),
+        ));
+    }
+    // 引いている途中の表は、引き終わるまで残る
+    *TABLE.write().unwrap() = Arc::new(table);
+    Ok(())
+}
+
+// 組み込みの表（とプリセット）に合わせた表と、見つかった問題（行番号順）を返す
+pub fn check_table(preset: RomajiPreset, text: &str) -> (RomajiTable, Vec<Problem>) {
+    let mut table = builtin_table();
+    // プリセットは検査済みなので、問題は報告しない
+    overlay(&mut table, &parse_rules(preset.rules(), &mut Vec::new()));
+
+    let mut problems = Vec::new();
+    let rules = parse_rules(text, &mut problems);
+    overlay(&mut table, &rules);
+
+    let line_of = |romaji: &str| {
+        rules
+            .iter()
+            .rev()
+            .find(|r| r.romaji == romaji)
+            .map(|r| r.line)
+    };
+    for (i, r) in rules.iter().enumerate() {
+        if let Some(first) = rules[..i].iter().find(|p| p.romaji == r.romaji) {
+            problems.push(Problem {
+                line: r.line,
+                message: format!("duplicate entry '{}' (also line {})", r.romaji, first.line),
+            });
+        }
+        if r.kana.is_empty() {
+            continue;
+        }
+        if let Some(c) = r
+            .romaji
+            .chars()
+            .find(|c| RESERVED.contains(c) || c.is_ascii_uppercase())
+        {
+            problems.push(Problem {
+                line: r.line,
+                message: format!(
+                    "unreachable entry '{}': '{}' cannot be typed as romaji",
+                    r.romaji, c
+                ),
+            });
+        }
+        // 完全一致した時点で確定するので、より短い定義があると長い定義には届かない
+        for (end, _) in r.romaji.char_indices().skip(1) {
+            let prefix = &r.romaji[..end];
+            if table.iter().any(|(k, _)| k == prefix) {
+                let at = match line_of(prefix) {
+                    Some(n) => format!("line {}", n),
+                    None => String::from("built-in"),
+                };
+                problems.push(Problem {
+                    line: r.line,
+                    message: format!(
+                        "unreachable entry '{}': '{}' ({}) matches first",
+                        r.romaji, prefix, at
+                    ),
+                });
+                break;
+            }
+        }
+        // 組み込み（プリセットを含む）の長い定義を隠してしまう場合（ユーザーの定義ならその行で報告済み）
+        if let Some((k, _)) = table.iter().find(|(k, _)| {
+            k.len() > r.romaji.len() && k.starts_with(&r.romaji) && line_of(k).is_none()
+        }) {
+            problems.push(Problem {
+                line: r.line,
+                message: format!("entry '{}' makes built-in '{}' unreachable", r.romaji, k),
+            });
+        }
+        if !r.pushback.is_empty() && !table.iter().any(|(k, _)| k.starts_with(&r.pushback)) {
+            problems.push(Problem {
+                line: r.line,
+                message: format!("pushback '{}' does not start any romaji", r.pushback),
+            });
+        }
+    }
+    problems.sort_by_key(|p| p.line);
+    (table, problems)
+}
+
+fn builtin_table() -> RomajiTable {
+    ROMAJI_TO_HIRAGANA
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn overlay(table: &mut RomajiTable, rules: &[Rule]) {
+    for r in rules {
+        table.retain(|(k, _)| *k != r.romaji);
+        if !r.kana.is_empty() {
+            table.push((r.romaji.clone(), r.kana.clone() + &r.pushback));
+        }
+    }
+    table.sort();
+}
+
+fn parse_rules(text: &str, problems: &mut Vec<Problem>) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut cols = line.split('\t');
+        let (romaji, kana, pushback) = (
+            cols.next().unwrap_or_default(),
+            cols.next(),
+            cols.next().unwrap_or_default(),
+        );
+        let message = match kana {
+            _ if romaji.is_empty() => Some(String::from("empty romaji")),
+            None => Some(String::from("missing tab after romaji")),
+            _ if cols.next().is_some() => Some(String::from("too many columns")),
+            Some(k) if k.ends_with(|c: char| c.is_ascii_lowercase()) => Some(String::from(
+                "kana must not end with a lowercase letter (use the pushback column)",
+            )),
+            _ if pushback.len() > 1 || pushback.chars().any(|c| !c.is_ascii_lowercase()) => {
+                Some(format!(
+                    "invalid pushback '{}': must be one lowercase letter",
+                    pushback
+                ))
+            }
+            Some("") if !pushback.is_empty() => Some(String::from("pushback without kana")),
+            _ => None,
+        };
+        if let Some(message) = message {
+            problems.push(Problem {
+                line: line_no,
+                message,
+            });
+            continue;
+        }
+        rules.push(Rule {
+            line: line_no,
+            romaji: romaji.to_string(),
+            kana: kana.unwrap_or_default().to_string(),
+            pushback: pushback.to_string(),
+        });
+    }
+    rules
+}
@@ -0,0 +1,91 @@
+//! シグナルの受け取り（SIGUSR1：辞書の再読み込み、SIGTSTP：端末を戻してから一時停止）
+//!
+//! ハンドラではパイプにシグナルの番号を1バイト書くだけにして（self-pipe）、読む側のスレッドで処理する。
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    os::fd::FromRawFd,
+    sync::{
+        OnceLock,
+        atomic::{AtomicI32, Ordering},
+    },
+};
+
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+static PIPE_READ: OnceLock<io::Result<File>> = OnceLock::new();
+
+// 受け取るシグナル
+pub enum Signal {
+    Usr1,
+    Tstp,
+}
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGUSR1, libc::SIGTSTP];
+
+extern "C" fn notify(sig: libc::c_int) {
+    let fd = PIPE_WRITE.load(Ordering::Relaxed);
+    let byte = sig as u8;
+    // SAFETY: writeはシグナルハンドラから呼んでよい（パイプが一杯なら通知は既に溜まっている）
+    unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+}
+
+// パイプを作ってハンドラを登録する（プロセスで1度だけ）
+fn install() -> io::Result<File> {
+    let mut fds = [0; 2];
+    // SAFETY: 2要素の配列を渡している
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for fd in fds {
+        // SAFETY: 作ったばかりのfdにフラグを設定するだけ
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    // ハンドラの中で止まらないよう、書く側はブロックしない
+    // SAFETY: 同上
+    unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) };
+    PIPE_WRITE.store(fds[1], Ordering::Relaxed);
+
+    for sig in SIGNALS {
+        // SAFETY: sigactionはゼロ初期化してから必要な項目だけ設定する
+        let r = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = notify as *const () as libc::sighandler_t;
+            // 他のスレッドのキー入力の読み込みを中断させない
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(sig, &action, std::ptr::null_mut())
+        };
+        if r != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // SAFETY: 読む側のfdはここで作ったもので、このFileだけが持つ
+    Ok(unsafe { File::from_raw_fd(fds[0]) })
+}
+
+/// 受け取ったシグナルを順に返すイテレータ（次のシグナルまでブロックする）
+///
+/// 初めて呼んだときにハンドラを登録する。以降、SIGUSR1やSIGTSTPでプロセスは終了・停止しない。
+pub fn signals() -> io::Result<impl Iterator<Item = Signal>> {
+    let read = match PIPE_READ.get_or_init(install) {
+        Ok(f) => f.try_clone()?,
+        Err(e) => return Err(io::Error::new(e.kind(), e.to_string())),
+    };
+    Ok(BufReader::new(read)
+        .bytes()
+        .map_while(Result::ok)
+        .filter_map(|b| match b as libc::c_int {
+            libc::SIGUSR1 => Some(Signal::Usr1),
+            libc::SIGTSTP => Some(Signal::Tstp),
+            _ => None,
+        }))
+}
+
+/// プロセスを止める（シェルのfgやbgで続きから動く）
+///
+/// SIGTSTPにはハンドラを登録しているので、止めるのはSIGSTOPで行う。
+pub fn stop() {
+    // SAFETY: 自分自身にシグナルを送るだけ
+    unsafe { libc::raise(libc::SIGSTOP) };
+}
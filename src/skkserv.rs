@@ -0,0 +1,154 @@
+//! skkservプロトコルのサーバー（--serve）
+//!
+//! 読み込んだ辞書を、ddskkなど他のSKKクライアントからも引けるようにする。
+//! 要求の読みがUTF-8として読めればUTF-8で、そうでなければEUC-JPとみなしてEUC-JPで応答する。
+
+use std::{
+    ffi::CStr,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use crate::{
+    encoding::{euc_jp_to_utf8, utf8_to_euc_jp},
+    jisyo::Jisyo,
+};
+
+const VERSION: &str = concat!("unskk-", env!("CARGO_PKG_VERSION"), " ");
+const READ_CHUNK: usize = 4096;
+const MAX_REQUEST: usize = 4096; // これより長い読みは壊れた要求とみなして切断する
+
+enum Request<'a> {
+    Disconnect,           // "0"
+    Lookup(&'a [u8]),     // "1<読み> "
+    Version,              // "2"
+    Host,                 // "3"
+    Completion(&'a [u8]), // "4<読み> "（補完には対応しない）
+    Skip,                 // 要求の間の空白や改行
+}
+
+/// addrで待ち受け、接続ごとにスレッドを立てて応答する（戻るのは待ち受けに失敗したときだけ）
+pub fn serve(addr: &str, jisyo: Jisyo) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let jisyo = Arc::new(jisyo);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("skkserv: {}", e);
+                continue;
+            }
+        };
+        let jisyo = Arc::clone(&jisyo);
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &jisyo) {
+                eprintln!("skkserv: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_client(mut stream: TcpStream, jisyo: &Jisyo) -> io::Result<()> {
+    let mut pending = Vec::new();
+    let mut chunk = [0u8; READ_CHUNK];
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&chunk[..n]);
+        // 1度に複数の要求が届くこともある
+        while let Some((request, used)) = parse(&pending) {
+            let response = match request {
+                Request::Disconnect => return Ok(()),
+                Request::Lookup(key) => lookup(jisyo, key),
+                Request::Version => VERSION.as_bytes().to_vec(),
+                Request::Host => host(&stream),
+                Request::Completion(key) => not_found(key),
+                Request::Skip => Vec::new(),
+            };
+            stream.write_all(&response)?;
+            pending.drain(..used);
+        }
+        if pending.len() > MAX_REQUEST {
+            return Ok(());
+        }
+    }
+}
+
+// 要求と、その長さ（読みの区切りがまだ届いていなければNone）
+fn parse(data: &[u8]) -> Option<(Request<'_>, usize)> {
+    let (&command, rest) = data.split_first()?;
+    let key = || {
+        let end = rest.iter().position(|&b| b == b' ' || b == b'\n')?;
+        Some((&rest[..end], end + 2))
+    };
+    Some(match command {
+        b'0' => (Request::Disconnect, 1),
+        b'1' => key().map(|(k, n)| (Request::Lookup(k), n))?,
+        b'2' => (Request::Version, 1),
+        b'3' => (Request::Host, 1),
+        b'4' => key().map(|(k, n)| (Request::Completion(k), n))?,
+        _ => (Request::Skip, 1),
+    })
+}
+
+// "1/候補1/候補2/\n"（なければ"4<読み>\n"）
+fn lookup(jisyo: &Jisyo, key: &[u8]) -> Vec<u8> {
+    let (yomi, euc) = match std::str::from_utf8(key) {
+        Ok(s) => (s.to_string(), false),
+        Err(_) => match euc_jp_to_utf8(key) {
+            Ok(s) => (s, true),
+            Err(_) => return not_found(key),
+        },
+    };
+    let Some(candidates) = jisyo.lookup(&yomi) else {
+        return not_found(key);
+    };
+    // EUC-JPにない文字を含む候補は送らない
+    let candidates: Vec<Vec<u8>> = candidates
+        .iter()
+        .filter_map(|c| match euc {
+            true => utf8_to_euc_jp(c).ok(),
+            false => Some(c.as_bytes().to_vec()),
+        })
+        .collect();
+    if candidates.is_empty() {
+        return not_found(key);
+    }
+    let mut out = b"1/".to_vec();
+    for c in candidates {
+        out.extend_from_slice(&c);
+        out.push(b'/');
+    }
+    out.push(b'\n');
+    out
+}
+
+fn not_found(key: &[u8]) -> Vec<u8> {
+    let mut out = b"4".to_vec();
+    out.extend_from_slice(key);
+    out.push(b'\n');
+    out
+}
+
+// "ホスト名:アドレス: "
+fn host(stream: &TcpStream) -> Vec<u8> {
+    let mut name = [0u8; 256];
+    // SAFETY: 長さを渡したバッファに書き込まれる（切り詰められても'\0'は探す）
+    let r = unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len() - 1) };
+    let name = match r {
+        0 => CStr::from_bytes_until_nul(&name)
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    let addr = stream
+        .local_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_default();
+    format!("{}:{}: ", name, addr).into_bytes()
+}
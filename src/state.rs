@@ -1,19 +1,51 @@
-use crate::jisyo::Jisyo;
+use crate::kana::convert_to_lookup_yomi;
+use crate::numeric;
+use std::borrow::Cow;
+
+use crate::buffer::Buffer;
+use crate::hook::EngineEvent;
+use crate::jisyo::{Jisyo, PaletteCategory, decode_candidate, split_annotation};
+use crate::romaji::{KanaMatch, search_lookup_table};
 use crate::util::push_itoa_usize_to_string;
 
 const HANKAKU: &str = "半角";
 const ZENKAKU: &str = "全角";
+const HIRAGANA: &str = "かな";
+const KATAKANA: &str = "カナ";
+
+// ステータス行の部品を、幅が足りないときに縮める順（先のものから縮め、Keepは縮めない）
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Shrink {
+    Detail,  // 註釈や候補の出所、本文の位置（なくしてもよい）
+    Preview, // 次の候補の先読み（なくしてもよい）
+    Message, // 一時的な知らせ
+    Text,    // 読みや候補、登録する語
+    Keep,    // モードや候補の位置（[2/5]）
+}
+
+pub type StatusPart = (String, Shrink);
+
+// 半角／全角のサブモード（モード切替や変換を跨いで保持する）
+#[derive(Default, Clone)]
+pub struct SubModes {
+    pub zenkaku_latin: bool,
+    pub zenkaku_symbol: bool,
+    pub hankaku_katakana: bool,
+    pub show_source: bool, // 変換中の候補に出所の辞書名を付ける（CANDIDATE_SOURCE=show）
+    pub preview: usize,    // 変換中の候補の後ろに並べる次の候補の数（CANDIDATE_PREVIEW）
+    pub events: Vec<EngineEvent>, // フロントエンドが取り出して外に知らせる出来事
+}
 
 #[derive(Clone)]
 pub enum KanaState {
-    Hiragana(bool), // contains zenkaku flag for ascii characters
-    Katakana(bool), // contains hankaku flag
-    ToBeConverted(String),
+    Hiragana,
+    Katakana,
+    ToBeConverted(String, bool), // contains katakana flag
 }
 
 #[derive(Clone)]
 pub enum InputState {
-    Latin(bool), // contains zenkaku flag
+    Latin,
     Kana {
         romaji: String,
         state: KanaState,
@@ -21,67 +53,197 @@ pub enum InputState {
     Converting {
         yomi: String,
         candidates: Vec<String>,
+        sources: Vec<String>, // 候補ごとの出所の辞書名（ユーザー辞書はuser）
+        keys: Vec<String>,    // 候補ごとの引いた見出し語（削除や固定はこれに記録する）
         selected_index: usize,
+        katakana: bool,
     },
     Abbrev(String),
+    Quoted(KanaState), // 次の1文字をそのまま入力
+    EntryEdit {
+        yomi: String,
+        candidates: Vec<String>,
+        selected_index: usize,
+        katakana: bool,
+        annotation: Option<String>, // 註釈の入力中
+    },
+    Palette {
+        categories: Vec<PaletteCategory>,
+        category: usize,
+        selected_index: usize,
+        katakana: bool,
+    },
+    Learned {
+        entries: Vec<(String, String)>, // 学習した見出し語と候補（最近使った順）
+        selected_index: usize,
+        katakana: bool,
+    },
+    Register {
+        yomi: String, // 候補のなかった読み
+        katakana: bool,
+        word: Buffer,           // 登録する語（この中でも変換できる）
+        inner: Box<InputState>, // 登録する語を入力している状態
+    },
+    Completing {
+        yomi: String, // Tabを押したときの読み（取り消すとこれに戻る）
+        completions: Vec<String>,
+        selected_index: usize,
+        katakana: bool,
+    },
 }
 
 impl KanaState {
-    pub fn new_hiragana() -> Self {
-        Self::Hiragana(false)
+    pub fn new(katakana: bool) -> Self {
+        if katakana {
+            Self::Katakana
+        } else {
+            Self::Hiragana
+        }
     }
-    pub fn new_katakana() -> Self {
-        Self::Katakana(false)
+    pub fn is_katakana(&self) -> bool {
+        matches!(self, Self::Katakana | Self::ToBeConverted(_, true))
     }
-    pub fn status_as_string(&self) -> String {
-        let mut out = String::new();
+    pub fn status_parts(&self, sub: &SubModes) -> Vec<StatusPart> {
         match self {
-            Self::Hiragana(zenkaku) => {
-                out.push_str("かな/");
-                out.push_str(if *zenkaku { ZENKAKU } else { HANKAKU });
-                out.push_str("記号 ");
+            Self::Hiragana => {
+                let symbol = if sub.zenkaku_symbol { ZENKAKU } else { HANKAKU };
+                vec![(format!("かな/{}記号 ", symbol), Shrink::Keep)]
             }
-            Self::Katakana(hankaku) => {
-                out.push_str("カナ/");
-                out.push_str(if *hankaku { HANKAKU } else { ZENKAKU });
-                out.push(' ');
+            Self::Katakana => {
+                let kana = if sub.hankaku_katakana {
+                    HANKAKU
+                } else {
+                    ZENKAKU
+                };
+                vec![(format!("カナ/{} ", kana), Shrink::Keep)]
             }
-            Self::ToBeConverted(yomi) => {
-                out.push_str("かな ▽");
-                out.push_str(yomi);
-            }
-        };
-        out
+            Self::ToBeConverted(yomi, katakana) => vec![
+                (format!("{} ▽", mode_label(*katakana)), Shrink::Keep),
+                (yomi.clone(), Shrink::Text),
+            ],
+        }
     }
 }
 
 impl InputState {
-    pub fn new_latin() -> Self {
-        Self::Latin(false)
-    }
     pub fn new_kana() -> Self {
         Self::Kana {
             romaji: String::new(),
-            state: KanaState::new_hiragana(),
+            state: KanaState::Hiragana,
         }
     }
     pub fn new_abbrev() -> Self {
         Self::Abbrev(String::new())
     }
-    pub fn new_converting(yomi: &str, jisyo: &Jisyo) -> Option<Self> {
+    pub fn new_converting(yomi: &str, jisyo: &Jisyo, katakana: bool) -> Option<Self> {
+        // 全角英字の読みはASCIIに、カタカナの読みはひらがなに正規化して引く
+        let okuri = Self::okuri(yomi).and_then(okuri_kana);
+        let found = jisyo.lookup_with_sources(&convert_to_lookup_yomi(yomi), okuri.as_deref())?;
+        let (candidates, (sources, keys)) = found
+            .into_iter()
+            .map(|(cand, source, key)| (cand, (source, key)))
+            .unzip();
         Some(Self::Converting {
             yomi: yomi.to_string(),
-            candidates: jisyo.lookup(yomi)?,
+            candidates,
+            sources,
+            keys,
+            selected_index: 0,
+            katakana,
+        })
+    }
+    pub fn new_register(yomi: &str, katakana: bool) -> Self {
+        Self::Register {
+            yomi: yomi.to_string(),
+            katakana,
+            word: Buffer::default(),
+            inner: Box::new(Self::Kana {
+                romaji: String::new(),
+                state: KanaState::new(katakana),
+            }),
+        }
+    }
+    pub fn new_completing(yomi: &str, jisyo: &Jisyo, katakana: bool) -> Option<Self> {
+        let completions = jisyo.complete(&convert_to_lookup_yomi(yomi));
+        if completions.is_empty() {
+            return None;
+        }
+        Some(Self::Completing {
+            yomi: yomi.to_string(),
+            completions,
+            selected_index: 0,
+            katakana,
+        })
+    }
+    // 読みも候補も途中のローマ字もない（登録の確定や取り消しを受け付ける）
+    pub fn is_idle(&self) -> bool {
+        match self {
+            Self::Latin => true,
+            Self::Kana { romaji, state } => {
+                romaji.is_empty() && !matches!(state, KanaState::ToBeConverted(..))
+            }
+            _ => false,
+        }
+    }
+    // 外から見える入力モードの名前（制御用ソケットのget-mode）
+    pub fn mode_name(&self) -> &'static str {
+        match self {
+            Self::Latin => "latin",
+            Self::Kana { state, .. } => match state {
+                KanaState::Hiragana => "kana",
+                KanaState::Katakana => "katakana",
+                KanaState::ToBeConverted(..) => "yomi",
+            },
+            Self::Converting { .. } => "converting",
+            Self::Abbrev(_) => "abbrev",
+            Self::Quoted(_) => "quoted",
+            Self::EntryEdit { .. } => "entry-edit",
+            Self::Palette { .. } => "palette",
+            Self::Learned { .. } => "learned",
+            Self::Register { .. } => "register",
+            Self::Completing { .. } => "completing",
+        }
+    }
+    // 途中まで打ったローマ字がある（登録中は登録する語の入力を見る）
+    pub fn has_pending_romaji(&self) -> bool {
+        match self {
+            Self::Kana { romaji, .. } => !romaji.is_empty(),
+            Self::Register { inner, .. } => inner.has_pending_romaji(),
+            _ => false,
+        }
+    }
+    pub fn new_palette(jisyo: &Jisyo, katakana: bool) -> Option<Self> {
+        if jisyo.palette().is_empty() {
+            return None;
+        }
+        Some(Self::Palette {
+            categories: jisyo.palette().to_vec(),
+            category: 0,
+            selected_index: 0,
+            katakana,
+        })
+    }
+    pub fn new_learned(jisyo: &Jisyo, katakana: bool) -> Option<Self> {
+        let entries = jisyo.learned();
+        if entries.is_empty() {
+            return None;
+        }
+        Some(Self::Learned {
+            entries,
             selected_index: 0,
+            katakana,
         })
     }
-    pub fn candidate(candidates: &[String], selected_index: usize) -> (&str, Option<&str>) {
-        let cand = &candidates
+    pub fn candidate(
+        candidates: &[String],
+        selected_index: usize,
+    ) -> (Cow<'_, str>, Option<Cow<'_, str>>) {
+        let cand = candidates
             .get(selected_index)
             .map(|s| s.as_str())
             .expect("failed to get the candidate");
-        let mut it = cand.splitn(2, ';');
-        (it.next().unwrap(), it.next())
+        let (cand, annotation) = split_annotation(cand);
+        (decode_candidate(cand), annotation.map(decode_candidate))
     }
     pub fn okuri(yomi: &str) -> Option<char> {
         if yomi.is_ascii() {
@@ -93,44 +255,182 @@ impl InputState {
         }
     }
 
-    pub fn status_as_string(&self) -> String {
-        let mut out = String::new();
+    pub fn status_as_string(&self, sub: &SubModes) -> String {
+        self.status_parts(sub).into_iter().map(|(s, _)| s).collect()
+    }
+    // ステータス行の表示を、幅が足りないときの縮め方ごとに分けたもの
+    pub fn status_parts(&self, sub: &SubModes) -> Vec<StatusPart> {
+        let mut parts = Vec::new();
+        let mut push = |s: &str, shrink: Shrink| parts.push((s.to_string(), shrink));
         match self {
             Self::Abbrev(s) => {
-                out.push_str(" aあ ▽");
-                out.push_str(s);
+                push(" aあ ▽", Shrink::Keep);
+                push(s, Shrink::Text);
             }
-            Self::Latin(zenkaku) => {
-                out.push_str("無変換/");
-                out.push_str(if *zenkaku { ZENKAKU } else { HANKAKU });
+            Self::Latin => {
+                let latin = if sub.zenkaku_latin { ZENKAKU } else { HANKAKU };
+                push(&format!("無変換/{}", latin), Shrink::Keep);
             }
             Self::Kana { romaji, state } => {
-                out.push_str(&state.status_as_string());
-                out.push_str(romaji);
+                parts = state.status_parts(sub);
+                parts.push((romaji.clone(), Shrink::Keep));
+            }
+            Self::Quoted(state) => {
+                parts = state.status_parts(sub);
+                parts.push((String::from(" 引用"), Shrink::Keep));
             }
             Self::Converting {
                 yomi,
                 candidates,
+                sources,
                 selected_index,
+                katakana,
+                ..
             } => {
                 let (cand, annotation) = InputState::candidate(candidates, *selected_index);
-                out.push_str("かな ▼");
-                out.push_str(cand);
+                push(&format!("{} ▼", mode_label(*katakana)), Shrink::Keep);
+                let cand = numeric::expand(&cand, &convert_to_lookup_yomi(yomi));
+                push(&cand, Shrink::Text);
                 if let Some(c) = InputState::okuri(yomi) {
-                    out.push('*');
-                    out.push(c);
+                    match okuri_kana(c) {
+                        Some(kana) => push(&kana, Shrink::Keep),
+                        None => push(&format!("*{}", c), Shrink::Keep),
+                    }
+                }
+                if sub.show_source {
+                    push(&format!("[{}]", sources[*selected_index]), Shrink::Detail);
+                }
+                if let Some(p) = preview(yomi, candidates, *selected_index, sub.preview) {
+                    push(&p, Shrink::Preview);
+                }
+                push(&counter(*selected_index, candidates.len()), Shrink::Keep);
+                if let Some(annotation) = annotation {
+                    push(&format!(" 註:{}", annotation), Shrink::Detail);
+                }
+            }
+            Self::EntryEdit {
+                yomi,
+                candidates,
+                selected_index,
+                annotation,
+                ..
+            } => {
+                push("編集 ", Shrink::Keep);
+                push(yomi, Shrink::Text);
+                if candidates.is_empty() {
+                    push(" (候補なし)", Shrink::Keep);
+                    return parts;
+                }
+                let (cand, current) = InputState::candidate(candidates, *selected_index);
+                push(" ▼", Shrink::Keep);
+                push(&cand, Shrink::Text);
+                push(&counter(*selected_index, candidates.len()), Shrink::Keep);
+                // 入力中の註釈は縮めても末尾（入力している所）を残す
+                if let Some(a) = annotation {
+                    push(&format!(" 註:{}_", a), Shrink::Text);
+                } else if let Some(a) = current {
+                    push(&format!(" 註:{}", a), Shrink::Detail);
+                }
+            }
+            Self::Palette {
+                categories,
+                category,
+                selected_index,
+                ..
+            } => {
+                let (name, symbols) = &categories[*category];
+                let (symbol, annotation) = InputState::candidate(symbols, *selected_index);
+                push("記号 ", Shrink::Keep);
+                push(name, Shrink::Text);
+                push(" ▼", Shrink::Keep);
+                push(&symbol, Shrink::Text);
+                push(&counter(*selected_index, symbols.len()), Shrink::Keep);
+                if let Some(annotation) = annotation {
+                    push(&format!(" 註:{}", annotation), Shrink::Detail);
                 }
-                out.push_str(" [");
-                push_itoa_usize_to_string(&mut out, *selected_index + 1, 10);
-                out.push('/');
-                push_itoa_usize_to_string(&mut out, candidates.len(), 10);
-                out.push(']');
+            }
+            Self::Learned {
+                entries,
+                selected_index,
+                ..
+            } => {
+                let (yomi, cand) = &entries[*selected_index];
+                let (cand, annotation) = split_annotation(cand);
+                let annotation = annotation.map(decode_candidate);
+                push("学習 ", Shrink::Keep);
+                push(yomi, Shrink::Text);
+                push(" ▼", Shrink::Keep);
+                push(&decode_candidate(cand), Shrink::Text);
+                push(&counter(*selected_index, entries.len()), Shrink::Keep);
                 if let Some(annotation) = annotation {
-                    out.push_str(" 註:");
-                    out.push_str(annotation);
+                    push(&format!(" 註:{}", annotation), Shrink::Detail);
                 }
             }
+            Self::Completing {
+                completions,
+                selected_index,
+                katakana,
+                ..
+            } => {
+                push(&format!("{} ▽", mode_label(*katakana)), Shrink::Keep);
+                push(&completions[*selected_index], Shrink::Text);
+                push(&counter(*selected_index, completions.len()), Shrink::Keep);
+            }
+            Self::Register {
+                yomi, word, inner, ..
+            } => {
+                push("登録 ", Shrink::Keep);
+                push(yomi, Shrink::Text);
+                push(" ▶", Shrink::Keep);
+                push(&word.as_string(), Shrink::Text);
+                push(" | ", Shrink::Keep);
+                parts.extend(inner.status_parts(sub));
+            }
         };
-        out
+        parts
+    }
+}
+
+fn mode_label(katakana: bool) -> &'static str {
+    if katakana { KATAKANA } else { HIRAGANA }
+}
+
+// 候補の位置（ [2/5]）
+// 選択中の候補に続く候補（" |感じ 幹事…"、まだ後ろにあれば…を付ける）
+fn preview(yomi: &str, candidates: &[String], selected_index: usize, n: usize) -> Option<String> {
+    let next = candidates.get(selected_index + 1..)?;
+    if n == 0 || next.is_empty() {
+        return None;
+    }
+    let mut out = String::from(" |");
+    for (i, c) in next.iter().take(n).enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let c = decode_candidate(split_annotation(c).0);
+        out.push_str(&numeric::expand(&c, &convert_to_lookup_yomi(yomi)));
+    }
+    if next.len() > n {
+        out.push('…');
+    }
+    Some(out)
+}
+
+fn counter(selected_index: usize, len: usize) -> String {
+    let mut out = String::from(" [");
+    push_itoa_usize_to_string(&mut out, selected_index + 1, 10);
+    out.push('/');
+    push_itoa_usize_to_string(&mut out, len, 10);
+    out.push(']');
+    out
+}
+
+// 送り仮名は子音しか確定していないので、ウ段（終止形）とみなす（表示と、辞書の[く/...]を選ぶのに使う）
+fn okuri_kana(c: char) -> Option<String> {
+    let mut romaji = String::from(c);
+    romaji.push('u');
+    match search_lookup_table(&romaji) {
+        KanaMatch::Success(kana) if kana.pushback.is_empty() => Some(kana.commit),
+        _ => None,
     }
 }
@@ -0,0 +1,74 @@
+//! ステータス行の右端に出す時計・電池残量
+//!
+//! 全画面で使うとデスクトップの時計が隠れるので、その代わりに表示する。
+
+use crate::gadget::localtime;
+use std::{
+    fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MINUTE_MS: u128 = 60 * 1000;
+
+// STATUS_SEGMENTS：カンマ区切りで指定した順に並べる
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Clock,   // 時:分（ローカル時刻）
+    Battery, // 電池残量（Linuxのみ、電池がなければ出さない）
+}
+
+/// 表示する文字列（区切りは空白、出すものがなければ空）
+///
+/// `[Clock, Battery]` → `"12:34 電池85%"`
+pub fn segments_as_string(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .filter_map(|s| match s {
+            Segment::Clock => clock(),
+            Segment::Battery => battery(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 次に表示を更新する時刻（時計の分が変わるところ）
+pub fn next_tick() -> Instant {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() % MINUTE_MS)
+        .unwrap_or_default();
+    Instant::now() + Duration::from_millis((MINUTE_MS - elapsed) as u64)
+}
+
+fn clock() -> Option<String> {
+    let tm = localtime()?;
+    Some(format!("{:02}:{:02}", tm.tm_hour, tm.tm_min))
+}
+
+// 最初に見つかった電池の残量（充電中は「充電」）
+#[cfg(target_os = "linux")]
+fn battery() -> Option<String> {
+    let read = |path: &std::path::Path, name| {
+        fs::read_to_string(path.join(name))
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| read(p, "type").as_deref() == Some("Battery"))
+        .find_map(|p| {
+            let capacity = read(&p, "capacity")?;
+            let label = match read(&p, "status").as_deref() {
+                Some("Charging") => "充電",
+                _ => "電池",
+            };
+            Some(format!("{}{}%", label, capacity))
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn battery() -> Option<String> {
+    None
+}
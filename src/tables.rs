@@ -1,386 +1,2 @@
-pub const ROMAJI_TO_HIRAGANA: &[(&str, &str)] = &[
-    ("\t", "\t"),
-    ("\n", "\n"),
-    (" ", " "),
-    (",", "、"),
-    ("-", "ー"),
-    (".", "。"),
-    ("[", "「"),
-    ("]", "」"),
-    ("a", "あ"),
-    ("ba", "ば"),
-    ("bb", "っb"),
-    ("be", "べ"),
-    ("bi", "び"),
-    ("bo", "ぼ"),
-    ("bu", "ぶ"),
-    ("bya", "びゃ"),
-    ("bye", "びぇ"),
-    ("byo", "びょ"),
-    ("byu", "びゅ"),
-    ("ca", "か"),
-    ("cc", "っc"),
-    ("ce", "せ"),
-    ("cha", "ちゃ"),
-    ("che", "ちぇ"),
-    ("chi", "ち"),
-    ("cho", "ちょ"),
-    ("chu", "ちゅ"),
-    ("ci", "し"),
-    ("co", "こ"),
-    ("cu", "く"),
-    ("cya", "ちゃ"),
-    ("cye", "ちぇ"),
-    ("cyo", "ちょ"),
-    ("cyu", "ちゅ"),
-    ("da", "だ"),
-    ("dd", "っd"),
-    ("de", "で"),
-    ("dha", "でゃ"),
-    ("dhe", "でぇ"),
-    ("dhi", "でぃ"),
-    ("dho", "でょ"),
-    ("dhu", "でゅ"),
-    ("di", "ぢ"),
-    ("do", "ど"),
-    ("du", "づ"),
-    ("dwa", "どぁ"),
-    ("dwe", "どぇ"),
-    ("dwi", "どぃ"),
-    ("dwo", "どぉ"),
-    ("dwu", "どぅ"),
-    ("dya", "ぢゃ"),
-    ("dye", "ぢぇ"),
-    ("dyo", "ぢょ"),
-    ("dyu", "ぢゅ"),
-    ("e", "え"),
-    ("fa", "ふぁ"),
-    ("fe", "ふぇ"),
-    ("ff", "っf"),
-    ("fi", "ふぃ"),
-    ("fo", "ふぉ"),
-    ("fu", "ふ"),
-    ("fya", "ふゃ"),
-    ("fye", "ふぃぇ"),
-    ("fyo", "ふょ"),
-    ("fyu", "ふゅ"),
-    ("ga", "が"),
-    ("ge", "げ"),
-    ("gg", "っg"),
-    ("gi", "ぎ"),
-    ("go", "ご"),
-    ("gu", "ぐ"),
-    ("gwa", "ぐぁ"),
-    ("gwe", "ぐぇ"),
-    ("gwi", "ぐぃ"),
-    ("gwo", "ぐぉ"),
-    ("gya", "ぎゃ"),
-    ("gye", "ぎぇ"),
-    ("gyo", "ぎょ"),
-    ("gyu", "ぎゅ"),
-    ("ha", "は"),
-    ("he", "へ"),
-    ("hh", "っh"),
-    ("hi", "ひ"),
-    ("ho", "ほ"),
-    ("hu", "ふ"),
-    ("hya", "ひゃ"),
-    ("hye", "ひぇ"),
-    ("hyo", "ひょ"),
-    ("hyu", "ひゅ"),
-    ("i", "い"),
-    ("ja", "じゃ"),
-    ("je", "じぇ"),
-    ("ji", "じ"),
-    ("jj", "っj"),
-    ("jo", "じょ"),
-    ("ju", "じゅ"),
-    ("jya", "じゃ"),
-    ("jye", "じぇ"),
-    ("jyo", "じょ"),
-    ("jyu", "じゅ"),
-    ("ka", "か"),
-    ("ke", "け"),
-    ("ki", "き"),
-    ("kk", "っk"),
-    ("ko", "こ"),
-    ("ku", "く"),
-    ("kwa", "くぁ"),
-    ("kwe", "くぇ"),
-    ("kwi", "くぃ"),
-    ("kwo", "くぉ"),
-    ("kya", "きゃ"),
-    ("kye", "きぇ"),
-    ("kyo", "きょ"),
-    ("kyu", "きゅ"),
-    ("la", "ぁ"),
-    ("le", "ぇ"),
-    ("li", "ぃ"),
-    ("lka", "ゕ"),
-    ("lke", "ゖ"),
-    ("lo", "ぉ"),
-    ("ltsu", "っ"),
-    ("ltu", "っ"),
-    ("lu", "ぅ"),
-    ("lwa", "ゎ"),
-    ("lya", "ゃ"),
-    ("lye", "ぇ"),
-    ("lyo", "ょ"),
-    ("lyu", "ゅ"),
-    ("ma", "ま"),
-    ("me", "め"),
-    ("mi", "み"),
-    ("mm", "っm"),
-    ("mo", "も"),
-    ("mu", "む"),
-    ("mya", "みゃ"),
-    ("mye", "みぇ"),
-    ("myo", "みょ"),
-    ("myu", "みゅ"),
-    ("n\n", "ん\n"),
-    ("n'", "ん"),
-    ("n,", "ん、"),
-    ("n-", "んー"),
-    ("n.", "ん。"),
-    ("na", "な"),
-    ("nb", "んb"),
-    ("nc", "んc"),
-    ("nd", "んd"),
-    ("ne", "ね"),
-    ("nf", "んf"),
-    ("ng", "んg"),
-    ("nh", "んh"),
-    ("ni", "に"),
-    ("nj", "んj"),
-    ("nk", "んk"),
-    ("nl", "んl"),
-    ("nm", "んm"),
-    ("nn", "ん"),
-    ("no", "の"),
-    ("np", "んp"),
-    ("nq", "んq"),
-    ("nr", "んr"),
-    ("ns", "んs"),
-    ("nt", "んt"),
-    ("nu", "ぬ"),
-    ("nv", "んv"),
-    ("nw", "んw"),
-    ("nx", "んx"),
-    ("nya", "にゃ"),
-    ("nye", "にぇ"),
-    ("nyo", "にょ"),
-    ("nyu", "にゅ"),
-    ("nz", "んz"),
-    ("o", "お"),
-    ("pa", "ぱ"),
-    ("pe", "ぺ"),
-    ("pi", "ぴ"),
-    ("po", "ぽ"),
-    ("pp", "っp"),
-    ("pu", "ぷ"),
-    ("pya", "ぴゃ"),
-    ("pye", "ぴぇ"),
-    ("pyo", "ぴょ"),
-    ("pyu", "ぴゅ"),
-    ("qa", "くぁ"),
-    ("qe", "くぇ"),
-    ("qi", "くぃ"),
-    ("qo", "くぉ"),
-    ("qu", "く"),
-    ("ra", "ら"),
-    ("re", "れ"),
-    ("ri", "り"),
-    ("ro", "ろ"),
-    ("rr", "っr"),
-    ("ru", "る"),
-    ("rya", "りゃ"),
-    ("rye", "りぇ"),
-    ("ryo", "りょ"),
-    ("ryu", "りゅ"),
-    ("sa", "さ"),
-    ("se", "せ"),
-    ("sha", "しゃ"),
-    ("she", "しぇ"),
-    ("shi", "し"),
-    ("sho", "しょ"),
-    ("shu", "しゅ"),
-    ("si", "し"),
-    ("so", "そ"),
-    ("ss", "っs"),
-    ("su", "す"),
-    ("sya", "しゃ"),
-    ("sye", "しぇ"),
-    ("syo", "しょ"),
-    ("syu", "しゅ"),
-    ("ta", "た"),
-    ("tcha", "ちゃ"),
-    ("tche", "ちぇ"),
-    ("tchi", "ち"),
-    ("tcho", "ちょ"),
-    ("tchu", "ちゅ"),
-    ("te", "て"),
-    ("tha", "てゃ"),
-    ("the", "てぇ"),
-    ("thi", "てぃ"),
-    ("tho", "てょ"),
-    ("thu", "てゅ"),
-    ("ti", "ち"),
-    ("to", "と"),
-    ("tsa", "つぁ"),
-    ("tse", "つぇ"),
-    ("tsi", "つぃ"),
-    ("tso", "つぉ"),
-    ("tsu", "つ"),
-    ("tt", "っt"),
-    ("tu", "つ"),
-    ("twa", "とぁ"),
-    ("twe", "とぇ"),
-    ("twi", "とぃ"),
-    ("two", "とぉ"),
-    ("tya", "ちゃ"),
-    ("tye", "ちぇ"),
-    ("tyo", "ちょ"),
-    ("tyu", "ちゅ"),
-    ("u", "う"),
-    ("va", "ゔぁ"),
-    ("ve", "ゔぇ"),
-    ("vi", "ゔぃ"),
-    ("vo", "ゔぉ"),
-    ("vu", "ゔ"),
-    ("vv", "っv"),
-    ("vya", "ゔゃ"),
-    ("vye", "ゔぃぇ"),
-    ("vyi", "ゔぃ"),
-    ("vyo", "ゔょ"),
-    ("vyu", "ゔゅ"),
-    ("wa", "わ"),
-    ("we", "うぇ"),
-    ("wha", "うぁ"),
-    ("whe", "うぇ"),
-    ("whi", "うぃ"),
-    ("who", "うぉ"),
-    ("whu", "う"),
-    ("wi", "うぃ"),
-    ("wo", "を"),
-    ("ww", "っw"),
-    ("wye", "ゑ"),
-    ("wyi", "ゐ"),
-    ("xa", "ぁ"),
-    ("xe", "ぇ"),
-    ("xi", "ぃ"),
-    ("xka", "ゕ"),
-    ("xke", "ゖ"),
-    ("xo", "ぉ"),
-    ("xtsu", "っ"),
-    ("xtu", "っ"),
-    ("xu", "ぅ"),
-    ("xwa", "ゎ"),
-    ("xya", "ゃ"),
-    ("xye", "ぇ"),
-    ("xyo", "ょ"),
-    ("xyu", "ゅ"),
-    ("ya", "や"),
-    ("ye", "いぇ"),
-    ("yi", "い"),
-    ("yo", "よ"),
-    ("yu", "ゆ"),
-    ("za", "ざ"),
-    ("ze", "ぜ"),
-    ("zi", "じ"),
-    ("zo", "ぞ"),
-    ("zu", "ず"),
-    ("zya", "じゃ"),
-    ("zye", "じぇ"),
-    ("zyo", "じょ"),
-    ("zyu", "じゅ"),
-    ("zz", "っz"),
-];
-
-pub const HIRAGANA_TO_HALFWIDTH_KATAKANA: &[(char, &str)] = &[
-    ('、', "､"),
-    ('。', "｡"),
-    ('「', "｢"),
-    ('」', "｣"),
-    ('ぁ', "ｧ"),
-    ('あ', "ｱ"),
-    ('ぃ', "ｨ"),
-    ('い', "ｲ"),
-    ('ぅ', "ｩ"),
-    ('う', "ｳ"),
-    ('ぇ', "ｪ"),
-    ('え', "ｴ"),
-    ('ぉ', "ｫ"),
-    ('お', "ｵ"),
-    ('か', "ｶ"),
-    ('が', "ｶﾞ"),
-    ('き', "ｷ"),
-    ('ぎ', "ｷﾞ"),
-    ('く', "ｸ"),
-    ('ぐ', "ｸﾞ"),
-    ('け', "ｹ"),
-    ('げ', "ｹﾞ"),
-    ('こ', "ｺ"),
-    ('ご', "ｺﾞ"),
-    ('さ', "ｻ"),
-    ('ざ', "ｻﾞ"),
-    ('し', "ｼ"),
-    ('じ', "ｼﾞ"),
-    ('す', "ｽ"),
-    ('ず', "ｽﾞ"),
-    ('せ', "ｾ"),
-    ('ぜ', "ｾﾞ"),
-    ('そ', "ｿ"),
-    ('ぞ', "ｿﾞ"),
-    ('た', "ﾀ"),
-    ('だ', "ﾀﾞ"),
-    ('ち', "ﾁ"),
-    ('ぢ', "ﾁﾞ"),
-    ('っ', "ｯ"),
-    ('つ', "ﾂ"),
-    ('づ', "ﾂﾞ"),
-    ('て', "ﾃ"),
-    ('で', "ﾃﾞ"),
-    ('と', "ﾄ"),
-    ('ど', "ﾄﾞ"),
-    ('な', "ﾅ"),
-    ('に', "ﾆ"),
-    ('ぬ', "ﾇ"),
-    ('ね', "ﾈ"),
-    ('の', "ﾉ"),
-    ('は', "ﾊ"),
-    ('ば', "ﾊﾞ"),
-    ('ぱ', "ﾊﾟ"),
-    ('ひ', "ﾋ"),
-    ('び', "ﾋﾞ"),
-    ('ぴ', "ﾋﾟ"),
-    ('ふ', "ﾌ"),
-    ('ぶ', "ﾌﾞ"),
-    ('ぷ', "ﾌﾟ"),
-    ('へ', "ﾍ"),
-    ('べ', "ﾍﾞ"),
-    ('ぺ', "ﾍﾟ"),
-    ('ほ', "ﾎ"),
-    ('ぼ', "ﾎﾞ"),
-    ('ぽ', "ﾎﾟ"),
-    ('ま', "ﾏ"),
-    ('み', "ﾐ"),
-    ('む', "ﾑ"),
-    ('め', "ﾒ"),
-    ('も', "ﾓ"),
-    ('ゃ', "ｬ"),
-    ('や', "ﾔ"),
-    ('ゅ', "ｭ"),
-    ('ゆ', "ﾕ"),
-    ('ょ', "ｮ"),
-    ('よ', "ﾖ"),
-    ('ら', "ﾗ"),
-    ('り', "ﾘ"),
-    ('る', "ﾙ"),
-    ('れ', "ﾚ"),
-    ('ろ', "ﾛ"),
-    ('わ', "ﾜ"),
-    ('を', "ｦ"),
-    ('ん', "ﾝ"),
-    ('ゔ', "ｳﾞ"),
-    ('ー', "ｰ"),
-];
+// tables/*.tsv からbuild.rsで生成する
+include!(concat!(env!("OUT_DIR"), "/tables.rs"));
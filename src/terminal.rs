@@ -0,0 +1,209 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    sync::mpsc::Sender,
+};
+
+use termion::{
+    event::{Event, Key},
+    input::TermReadEventsAndRaw,
+    raw::{IntoRawMode, RawTerminal},
+    screen::{AlternateScreen, IntoAlternateScreen},
+};
+
+use crate::control::{Reply, Request};
+
+pub const DEVICE: &str = "/dev/tty";
+
+// キー入力（termionが解釈できないシーケンスは、受け取ったバイト列のまま）
+pub enum Input {
+    Key(Key),
+    Unknown(Vec<u8>),
+    Control(Request, Sender<Reply>), // 台本の制御コマンド（キー入力と同じ順に処理する）
+}
+
+pub type Keys = Box<dyn Iterator<Item = Input> + Send>;
+
+// 画面への出力・サイズの取得・キー入力をまとめた端末（termion以外の実装やテスト用の差し替え口）
+pub trait Terminal: Write {
+    // (幅, 高さ)
+    fn size(&self) -> io::Result<(usize, usize)>;
+    // 外部のプログラムに端末を渡す間などにrawモードを外す
+    fn set_raw_mode(&mut self, raw: bool) -> io::Result<()>;
+    // キー入力（別スレッドで読み続ける）
+    fn keys(&mut self) -> io::Result<Keys>;
+}
+
+// /dev/tty を代替スクリーン・rawモードで使う（標準入出力はパイプでもよい）
+pub struct TermionTerminal {
+    out: AlternateScreen<RawTerminal<File>>,
+    tty: File, // 端末の設定を変える用（outと同じ端末）
+}
+
+impl TermionTerminal {
+    pub fn open() -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(DEVICE)?;
+        let tty = file.try_clone()?;
+        let out = file.into_raw_mode()?.into_alternate_screen()?;
+        disable_flow_control(&tty)?;
+        Ok(Self { out, tty })
+    }
+}
+
+// Ctrl+S（送出）とCtrl+Q（終了）が端末のフロー制御（XON/XOFF）に取られないようにする
+// termionのrawモード（cfmakeraw）はIXONしか外さないので、IXOFFとIXANYも外す
+fn disable_flow_control(tty: &File) -> io::Result<()> {
+    let fd = tty.as_raw_fd();
+    // SAFETY: termiosはtcgetattrが書き込む出力先で、fdは開いている端末
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl Write for TermionTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Terminal for TermionTerminal {
+    fn size(&self) -> io::Result<(usize, usize)> {
+        let (w, h) = termion::terminal_size()?;
+        Ok((w as usize, h as usize))
+    }
+
+    fn set_raw_mode(&mut self, raw: bool) -> io::Result<()> {
+        if raw {
+            self.out.activate_raw_mode()?;
+            disable_flow_control(&self.tty)
+        } else {
+            self.out.suspend_raw_mode()
+        }
+    }
+
+    fn keys(&mut self) -> io::Result<Keys> {
+        let input = OpenOptions::new().read(true).open(DEVICE)?;
+        Ok(read_keys(input))
+    }
+}
+
+/// 読み込んだバイト列をキー入力にする（マウスの操作は捨てる）
+///
+/// termionが解釈できない修飾キー付きの矢印などは、[`parse_modified_key`]で読み替える。
+pub fn read_keys<R: Read + Send + 'static>(input: R) -> Keys {
+    let mut events = input.events_and_raw().flatten().peekable();
+    Box::new(std::iter::from_fn(move || {
+        loop {
+            let (event, mut raw) = events.next()?;
+            match event {
+                Event::Key(k) => return Some(Input::Key(k)),
+                Event::Mouse(_) => continue,
+                Event::Unsupported(_) => (),
+            }
+            // termionはESC Oの後に数字が来るとそこで読むのをやめ、残りを普通の文字として返す
+            if raw.starts_with(b"\x1bO")
+                && raw[2..].iter().all(|b| b.is_ascii_digit() || *b == b';')
+            {
+                while let Some((_, next)) = events.next_if(|(_, r)| is_sequence_rest(r)) {
+                    raw.extend_from_slice(&next);
+                    if next[0].is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            return Some(match parse_modified_key(&raw) {
+                Some(k) => Input::Key(k),
+                None => Input::Unknown(raw),
+            });
+        }
+    }))
+}
+
+fn is_sequence_rest(raw: &[u8]) -> bool {
+    matches!(raw, [b] if b.is_ascii_digit() || *b == b';' || b.is_ascii_alphabetic())
+}
+
+/// termionが解釈しないシーケンスのうち、矢印・Home・Endとして読めるもの
+///
+/// - `^[[a`〜`^[[d`／`^[Oa`〜`^[Od`：rxvtのShift+矢印／Ctrl+矢印
+/// - `^[OA`〜`^[OD`, `^[OH`, `^[OF`：アプリケーションカーソルキーモード（tmuxなど）
+/// - `^[O5C`, `^[O1;5C`, `^[[5C`, `^[[1;6C`など：修飾の番号付き
+///
+/// 修飾が重なるときはCtrl、Shift、Altの順に1つだけ採る（Ctrl+Shift+→はCtrl+→）。
+pub fn parse_modified_key(raw: &[u8]) -> Option<Key> {
+    let (&intro, rest) = raw.strip_prefix(b"\x1b")?.split_first()?;
+    let (&last, params) = rest.split_last()?;
+    let modifier = match (intro, params, last) {
+        (b'[', [], b'a'..=b'd') => return arrow(last - b'a' + b'A', Modifier::Shift),
+        (b'O', [], b'a'..=b'd') => return arrow(last - b'a' + b'A', Modifier::Ctrl),
+        (b'O', [], _) => 1,
+        (b'[' | b'O', _, _) => {
+            let p = str::from_utf8(params).ok()?;
+            p.strip_prefix("1;").unwrap_or(p).parse::<u8>().ok()?
+        }
+        _ => return None,
+    };
+    // 修飾の番号は1＋（Shift:1, Alt:2, Ctrl:4, Meta:8の和）
+    let bits = modifier.checked_sub(1)?;
+    let modifier = match bits {
+        0 => Modifier::None,
+        _ if bits & 4 != 0 => Modifier::Ctrl,
+        _ if bits & 1 != 0 => Modifier::Shift,
+        _ if bits & 2 != 0 => Modifier::Alt,
+        _ => return None,
+    };
+    arrow(last, modifier)
+}
+
+enum Modifier {
+    None,
+    Shift,
+    Alt,
+    Ctrl,
+}
+
+fn arrow(last: u8, modifier: Modifier) -> Option<Key> {
+    use Key::*;
+    let keys = match last {
+        b'A' => [Up, ShiftUp, AltUp, CtrlUp],
+        b'B' => [Down, ShiftDown, AltDown, CtrlDown],
+        b'C' => [Right, ShiftRight, AltRight, CtrlRight],
+        b'D' => [Left, ShiftLeft, AltLeft, CtrlLeft],
+        b'H' => [Home, Home, Home, CtrlHome],
+        b'F' => [End, End, End, CtrlEnd],
+        _ => return None,
+    };
+    Some(keys[modifier as usize])
+}
+
+/// 制御文字を`^[`のように表したシーケンス（ログやステータス行に出す）
+///
+/// `b"\x1b[1;5C"` → `"^[[1;5C"`
+pub fn caret_notation(raw: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in raw {
+        match b {
+            0x00..=0x1f => {
+                out.push('^');
+                out.push((b + 0x40) as char);
+            }
+            0x7f => out.push_str("^?"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out
+}
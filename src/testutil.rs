@@ -0,0 +1,52 @@
+//! テスト用の一時ファイル
+//!
+//! テストは並列に動くので、番号を振った一時ディレクトリにファイルを置いて分ける。
+//! ユーザー辞書のロックや統計のように横に作られるファイルもあるので、dropでディレクトリごと消す。
+
+use std::{
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    // 一時ディレクトリの下に、まだないnameのパスを用意する
+    pub fn new(name: &str) -> Self {
+        let n = RUNS.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("unskk-test-{}-{}", process::id(), n));
+        fs::create_dir_all(&dir).unwrap();
+        TempPath(dir.join(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Some(dir) = self.0.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
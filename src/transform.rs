@@ -0,0 +1,63 @@
+//! 送出するテキストの変換（CPY_TO_TRANSFORMで指定した順に適用する）
+
+use crate::kana::compose_voiced_marks;
+
+// 行頭にあるとMarkdownの記法になる文字
+const MARKDOWN_LINE_HEAD: &[char] = &['#', '>', '-', '+', '='];
+// どこにあってもMarkdownの記法になりうる文字
+const MARKDOWN_INLINE: &[char] = &['\\', '`', '*', '_', '[', ']', '<', '~', '|'];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Crlf,     // 改行をCRLFにする
+    Chomp,    // 末尾の改行を取り除く
+    Nfc,      // 仮名と結合用の濁点・半濁点をまとめる
+    Markdown, // Markdownの記法にならないようエスケープする
+}
+
+impl Transform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "crlf" => Some(Self::Crlf),
+            "chomp" => Some(Self::Chomp),
+            "nfc" => Some(Self::Nfc),
+            "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    fn apply(self, text: &str) -> String {
+        match self {
+            Self::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+            Self::Chomp => text.trim_end_matches(['\r', '\n']).to_string(),
+            Self::Nfc => compose_voiced_marks(text),
+            Self::Markdown => escape_markdown(text),
+        }
+    }
+}
+
+/// 変換を順に適用する
+pub fn apply(chain: &[Transform], text: &str) -> String {
+    chain
+        .iter()
+        .fold(text.to_string(), |text, t| t.apply(&text))
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_start_matches(' ');
+        out.push_str(&line[..line.len() - body.len()]);
+        // 「1. 」のような番号付きリスト
+        let digits = body.chars().take_while(char::is_ascii_digit).count();
+        for (i, c) in body.chars().enumerate() {
+            let head = i == 0 && MARKDOWN_LINE_HEAD.contains(&c);
+            let ordered = i == digits && digits > 0 && matches!(c, '.' | ')');
+            if head || ordered || MARKDOWN_INLINE.contains(&c) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,795 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::jisyo::{
+    decode_ignored, decode_pinned, encode_ignored, encode_pinned, is_okuri_ari, split_candidates,
+};
+
+const HEADER: &str = ";; unskk user jisyo (UTF-8)\n";
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(3);
+const LOCK_STALE: Duration = Duration::from_secs(30); // 異常終了で残ったロックとみなす
+const STATS_SUFFIX: &str = ".stats";
+const FORGET_WEIGHT: f64 = 0.25; // 減衰してこれを下回った候補は優先しない
+const TRASH_MAX: usize = 10; // 削除を取り消せる候補の数
+
+pub type UserEntry = (String, Vec<String>);
+
+// 学習結果を書き込むユーザー辞書（最近使った見出し語ほど先頭）
+pub struct UserJisyo {
+    path: String,
+    entries: Vec<UserEntry>,
+    touched: Vec<(String, Merge)>, // このセッションで変更した見出し語（古い順）
+    stats: Stats,
+    half_life: Option<Duration>,
+    by_frequency: bool, // 最近確定した順ではなく、確定した回数の多い順に並べる
+    trash: Vec<Purged>, // このセッションで削除した候補（古い順）
+}
+
+// 削除した候補と、戻すときに使う削除前の位置と統計
+struct Purged {
+    yomi: String,
+    cand: String,
+    index: Option<usize>, // ユーザー辞書の候補だった場合の位置
+    stat: Option<Stat>,
+}
+
+// 候補ごとの使用回数（減衰後）と最後に使った時刻（UNIX秒）
+#[derive(Clone, Copy)]
+struct Stat {
+    weight: f64,
+    time: u64,
+}
+
+type Stats = HashMap<(String, String), Stat>;
+
+// 保存時にファイル側の候補をどう扱うか
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Merge {
+    Union,   // 学習：ファイル側の候補も残す
+    Replace, // 編集：こちらの内容で置き換える（削除を反映するため）
+}
+
+impl UserJisyo {
+    // ファイルがなければ空の辞書として始める
+    pub fn load(path: &str, half_life: Option<Duration>, by_frequency: bool) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            entries: read_entries(path)?,
+            touched: Vec::new(),
+            stats: read_stats(&stats_path(path))?,
+            half_life,
+            by_frequency,
+            trash: Vec::new(),
+        })
+    }
+
+    fn candidates(&self, yomi: &str) -> Option<&[String]> {
+        self.entries
+            .iter()
+            .find(|(y, _)| y == yomi)
+            .map(|(_, c)| c.as_slice())
+    }
+
+    // 優先する候補と、減衰して優先しなくなった候補に分けて返す
+    // 送り仮名（okuri）のまとまりがあれば、その候補を先にする
+    pub fn lookup(&self, yomi: &str, okuri: Option<&str>) -> (Vec<String>, Vec<String>) {
+        let all = self.candidates(yomi).unwrap_or_default();
+        let block = all
+            .iter()
+            .find(|c| okuri.is_some() && block_kana(c) == okuri)
+            .map(|c| block_words(c).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let mut candidates: Vec<String> = block.iter().map(|w| w.to_string()).collect();
+        for c in all.iter().filter(|c| is_word(c)) {
+            if !block.contains(&c.as_str()) {
+                candidates.push(c.clone());
+            }
+        }
+        if self.half_life.is_none() && !self.by_frequency {
+            return (candidates, Vec::new());
+        }
+        let now = unix_now();
+        // 記録のない候補（編集で並べたものなど）は使ったばかりとみなす
+        // 減衰しなければ回数は1以上なので、優先しなくなる候補はない
+        let mut weighted: Vec<(f64, &String)> = candidates
+            .iter()
+            .map(|c| {
+                let stat = self.stats.get(&(yomi.to_string(), c.clone()));
+                let weight = match (stat, self.half_life) {
+                    (Some(s), Some(half_life)) => decayed(s, now, half_life),
+                    (Some(s), None) => s.weight,
+                    (None, _) => 1.0,
+                };
+                (weight, c)
+            })
+            .collect();
+        // 安定ソートなので、同じ回数なら最近確定した順のまま（まとまりの候補は先のまま）
+        let in_block = |c: &String| block.contains(&c.as_str());
+        weighted.sort_by(|a, b| in_block(b.1).cmp(&in_block(a.1)).then(b.0.total_cmp(&a.0)));
+        let (fresh, stale): (Vec<_>, Vec<_>) =
+            weighted.into_iter().partition(|(w, _)| *w >= FORGET_WEIGHT);
+        (
+            fresh.into_iter().map(|(_, c)| c.clone()).collect(),
+            stale.into_iter().map(|(_, c)| c.clone()).collect(),
+        )
+    }
+
+    // prefixで始まる見出し語（最近使った順、隠した候補しかないものは除く）
+    pub fn completions<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(y, c)| y.starts_with(prefix) && c.iter().any(|c| is_word(c)))
+            .map(|(y, _)| y.as_str())
+    }
+
+    // 見出し語と候補の組（隠した候補などの印は除く）
+    pub fn words(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .flat_map(|(y, c)| c.iter().map(move |c| (y.as_str(), c.as_str())))
+            .filter(|(_, c)| is_word(c))
+    }
+
+    // 他の辞書にあっても出さない候補
+    pub fn ignored(&self, yomi: &str) -> Vec<String> {
+        self.candidates(yomi)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|c| decode_ignored(c))
+            .unwrap_or_default()
+    }
+
+    // 先頭に固定した候補
+    pub fn pinned(&self, yomi: &str) -> Option<String> {
+        self.candidates(yomi)?.iter().find_map(|c| decode_pinned(c))
+    }
+
+    // 先頭に固定する候補を記録する（Noneなら固定をやめる）
+    pub fn pin(&mut self, yomi: &str, cand: Option<&str>) {
+        let mut candidates = match self.entries.iter().position(|(y, _)| y == yomi) {
+            Some(i) => self.entries.remove(i).1,
+            None => Vec::new(),
+        };
+        set_pinned(&mut candidates, cand);
+        if !candidates.is_empty() {
+            self.entries.insert(0, (yomi.to_string(), candidates));
+        }
+        // ファイル側に残っている固定を戻さないよう置き換える
+        self.touch(yomi, Merge::Replace);
+    }
+
+    // 確定した候補を先頭へ移し、見出し語も先頭へ移す（隠していた候補なら隠すのをやめる）
+    // 送り仮名（okuri）が分かれば、[送り仮名/候補/] のまとまりの先頭にも置く
+    pub fn record(&mut self, yomi: &str, cand: &str, okuri: Option<&str>) {
+        let mut candidates = match self.entries.iter().position(|(y, _)| y == yomi) {
+            Some(i) => self.entries.remove(i).1,
+            None => Vec::new(),
+        };
+        candidates.retain(|c| c != cand);
+        set_ignored(&mut candidates, |ignored| ignored.retain(|c| c != cand));
+        candidates.insert(0, cand.to_string());
+        if let Some(kana) = okuri {
+            set_block(&mut candidates, kana, |words| {
+                words.retain(|w| w != cand);
+                words.insert(0, cand.to_string());
+            });
+        }
+        self.entries.insert(0, (yomi.to_string(), candidates));
+        self.touch(yomi, Merge::Union);
+
+        let now = unix_now();
+        let key = (yomi.to_string(), cand.to_string());
+        let weight = match (self.stats.get(&key), self.half_life) {
+            (Some(s), Some(half_life)) => decayed(s, now, half_life),
+            (Some(s), None) => s.weight,
+            (None, _) => 0.0,
+        };
+        self.stats.insert(
+            key,
+            Stat {
+                weight: weight + 1.0,
+                time: now,
+            },
+        );
+    }
+
+    // 取り込み用：既存の候補より後ろに追加する（追加したらtrue）
+    pub fn add(&mut self, yomi: &str, cand: &str) -> bool {
+        match self.entries.iter_mut().find(|(y, _)| y == yomi) {
+            Some((_, candidates)) if candidates.iter().any(|c| c == cand) => return false,
+            Some((_, candidates)) => candidates.push(cand.to_string()),
+            None => self
+                .entries
+                .push((yomi.to_string(), vec![cand.to_string()])),
+        }
+        self.touch(yomi, Merge::Union);
+        true
+    }
+
+    // 候補を外し、他の辞書にあっても出さないように記録する
+    pub fn purge(&mut self, yomi: &str, cand: &str) {
+        let mut candidates = match self.entries.iter().position(|(y, _)| y == yomi) {
+            Some(i) => self.entries.remove(i).1,
+            None => Vec::new(),
+        };
+        let index = candidates.iter().position(|c| c == cand);
+        candidates.retain(|c| c != cand);
+        retain_in_blocks(&mut candidates, |w| w != cand);
+        if candidates
+            .iter()
+            .any(|c| decode_pinned(c).as_deref() == Some(cand))
+        {
+            set_pinned(&mut candidates, None);
+        }
+        set_ignored(&mut candidates, |ignored| {
+            if !ignored.iter().any(|c| c == cand) {
+                ignored.push(cand.to_string());
+            }
+        });
+        self.entries.insert(0, (yomi.to_string(), candidates));
+        let stat = self.stats.remove(&(yomi.to_string(), cand.to_string()));
+        // ファイル側に残っている同じ候補を戻さないよう置き換える
+        self.touch(yomi, Merge::Replace);
+        self.throw_away(yomi, cand, index, stat);
+    }
+
+    // 学習した候補を忘れる（他の辞書にあれば、そこでの位置に出る）
+    pub fn forget(&mut self, yomi: &str, cand: &str) {
+        let Some(i) = self.entries.iter().position(|(y, _)| y == yomi) else {
+            return;
+        };
+        let candidates = &mut self.entries[i].1;
+        let index = candidates.iter().position(|c| c == cand);
+        candidates.retain(|c| c != cand);
+        retain_in_blocks(candidates, |w| w != cand);
+        if candidates
+            .iter()
+            .any(|c| decode_pinned(c).as_deref() == Some(cand))
+        {
+            set_pinned(candidates, None);
+        }
+        if candidates.is_empty() {
+            self.entries.remove(i);
+        }
+        let stat = self.stats.remove(&(yomi.to_string(), cand.to_string()));
+        self.touch(yomi, Merge::Replace);
+        self.throw_away(yomi, cand, index, stat);
+    }
+
+    // Alt+Xで戻せるように覚えておく
+    fn throw_away(&mut self, yomi: &str, cand: &str, index: Option<usize>, stat: Option<Stat>) {
+        self.trash.push(Purged {
+            yomi: yomi.to_string(),
+            cand: cand.to_string(),
+            index,
+            stat,
+        });
+        if self.trash.len() > TRASH_MAX {
+            self.trash.remove(0);
+        }
+    }
+
+    // 学習した見出し語と候補の組（最近使った見出し語から、最大max個）
+    pub fn learned(&self, max: usize) -> Vec<(String, String)> {
+        self.entries
+            .iter()
+            .flat_map(|(y, c)| c.iter().map(move |c| (y, c)))
+            .filter(|(_, c)| is_word(c))
+            .take(max)
+            .map(|(y, c)| (y.clone(), c.clone()))
+            .collect()
+    }
+
+    // 最後に削除した候補を戻す（戻した見出し語と候補を返す）
+    pub fn restore(&mut self) -> Option<(String, String)> {
+        let Purged {
+            yomi,
+            cand,
+            index,
+            stat,
+        } = self.trash.pop()?;
+        let mut candidates = match self.entries.iter().position(|(y, _)| *y == yomi) {
+            Some(i) => self.entries.remove(i).1,
+            None => Vec::new(),
+        };
+        if let Some(i) = index.filter(|_| !candidates.contains(&cand)) {
+            candidates.insert(i.min(candidates.len()), cand.clone());
+        }
+        set_ignored(&mut candidates, |ignored| ignored.retain(|c| *c != cand));
+        if let Some(stat) = stat {
+            self.stats.insert((yomi.clone(), cand.clone()), stat);
+        }
+        if !candidates.is_empty() {
+            self.entries.insert(0, (yomi.clone(), candidates));
+        }
+        self.touch(&yomi, Merge::Replace);
+        Some((yomi, cand))
+    }
+
+    // 見出し語の候補をまるごと置き換える（空なら見出し語ごと削除、隠した候補はそのまま）
+    // 固定した候補は、残っていれば固定のまま
+    // 送り仮名のまとまりは、残った候補だけにして残す
+    pub fn set(&mut self, yomi: &str, mut candidates: Vec<String>) {
+        let ignored = self.ignored(yomi);
+        let pinned = self.pinned(yomi).filter(|p| candidates.contains(p));
+        let words = candidates.clone();
+        let blocks = self.candidates(yomi).unwrap_or_default();
+        candidates.extend(blocks.iter().filter(|c| block_kana(c).is_some()).cloned());
+        retain_in_blocks(&mut candidates, |w| words.iter().any(|c| c == w));
+        set_pinned(&mut candidates, pinned.as_deref());
+        set_ignored(&mut candidates, |i| *i = ignored);
+        self.entries.retain(|(y, _)| y != yomi);
+        self.stats.retain(|(y, _), _| y != yomi);
+        if !candidates.is_empty() {
+            self.entries.insert(0, (yomi.to_string(), candidates));
+        }
+        self.touch(yomi, Merge::Replace);
+    }
+
+    fn touch(&mut self, yomi: &str, merge: Merge) {
+        // 一度置き換えた見出し語は、その後の学習でも置き換えのまま
+        let merge = match self.touched.iter().position(|(y, _)| y == yomi) {
+            Some(i) => self.touched.remove(i).1.max(merge),
+            None => merge,
+        };
+        self.touched.push((yomi.to_string(), merge));
+    }
+
+    // ロックを取ってからファイルを読み直し、このセッションで変更した見出し語だけを上書きする
+    // （他のインスタンスが先に保存した学習結果を消さないため）
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.touched.is_empty() {
+            return Ok(());
+        }
+        let _lock = Lock::acquire(&self.path)?;
+        let (merged, merged_stats) = self.merged()?;
+        let stats = format_stats(&merged_stats);
+        write_atomic(&stats_path(&self.path), stats.as_bytes())?;
+        write_atomic(&self.path, format_entries(&merged).as_bytes())?;
+        self.entries = merged;
+        self.stats = merged_stats;
+        self.touched.clear();
+        Ok(())
+    }
+
+    // ファイルを読み直す（外で編集された内容を取り込む）
+    // まだ保存していない変更はそのまま残し、次の保存で書き込む
+    pub fn reload(&mut self) -> io::Result<()> {
+        let (merged, merged_stats) = self.merged()?;
+        self.entries = merged;
+        self.stats = merged_stats;
+        Ok(())
+    }
+
+    // ファイルの内容に、このセッションで変更した見出し語を重ねたもの
+    fn merged(&self) -> io::Result<(Vec<UserEntry>, Stats)> {
+        let mut merged = read_entries(&self.path)?;
+        let mut merged_stats = read_stats(&stats_path(&self.path))?;
+        for (yomi, merge) in &self.touched {
+            if *merge == Merge::Replace {
+                merged_stats.retain(|(y, _), _| y != yomi);
+            }
+            for (key, stat) in self.stats.iter().filter(|((y, _), _)| y == yomi) {
+                merged_stats.insert(key.clone(), *stat);
+            }
+            let mut candidates = self.candidates(yomi).unwrap_or_default().to_vec();
+            if let Some(i) = merged.iter().position(|(y, _)| y == yomi) {
+                let theirs = merged.remove(i).1;
+                if *merge == Merge::Union {
+                    for c in theirs {
+                        // 同じ送り仮名のまとまりはこちらのものを使う
+                        let same_block = block_kana(&c)
+                            .is_some_and(|k| candidates.iter().any(|o| block_kana(o) == Some(k)));
+                        // 固定もこちらのものがあればそれを使う
+                        let same_pin = decode_pinned(&c).is_some()
+                            && candidates.iter().any(|o| decode_pinned(o).is_some());
+                        if !candidates.contains(&c) && !same_block && !same_pin {
+                            candidates.push(c);
+                        }
+                    }
+                }
+            }
+            if !candidates.is_empty() {
+                merged.insert(0, (yomi.clone(), candidates));
+            }
+        }
+        Ok((merged, merged_stats))
+    }
+}
+
+// 候補として出すもの（隠す候補の一覧、固定の印と送り仮名のまとまりを除く）
+fn is_word(c: &str) -> bool {
+    decode_ignored(c).is_none() && decode_pinned(c).is_none() && block_kana(c).is_none()
+}
+
+// 送りありの見出し語の [き/大/] のまとまりは1つの要素として持つ
+fn block_kana(c: &str) -> Option<&str> {
+    let body = c.strip_prefix('[')?.strip_suffix("/]")?;
+    body.split_once('/').map(|(kana, _)| kana)
+}
+
+fn block_words(c: &str) -> impl Iterator<Item = &str> {
+    split_candidates(&c[1..c.len() - 2]).into_iter().skip(1)
+}
+
+fn format_block(kana: &str, words: &[String]) -> String {
+    let mut block = format!("[{}/", kana);
+    for w in words {
+        block.push_str(w);
+        block.push('/');
+    }
+    block.push(']');
+    block
+}
+
+// kanaのまとまりの候補を書き換え、候補の後ろ（まとまりの先頭）に置く（空になれば取り除く）
+fn set_block(candidates: &mut Vec<String>, kana: &str, edit: impl FnOnce(&mut Vec<String>)) {
+    let mut words = Vec::new();
+    if let Some(i) = candidates.iter().position(|c| block_kana(c) == Some(kana)) {
+        words = block_words(&candidates.remove(i))
+            .map(str::to_string)
+            .collect();
+    }
+    edit(&mut words);
+    if !words.is_empty() {
+        let at = candidates
+            .iter()
+            .position(|c| !is_word(c))
+            .unwrap_or(candidates.len());
+        candidates.insert(at, format_block(kana, &words));
+    }
+}
+
+// まとまりの中の候補のうちkeepを満たすものだけを残す（空になったまとまりは取り除く）
+fn retain_in_blocks(candidates: &mut Vec<String>, keep: impl Fn(&str) -> bool) {
+    candidates.retain_mut(|c| {
+        let Some(kana) = block_kana(c) else {
+            return true;
+        };
+        let words: Vec<String> = block_words(c)
+            .filter(|w| keep(w))
+            .map(str::to_string)
+            .collect();
+        *c = format_block(kana, &words);
+        !words.is_empty()
+    });
+}
+
+// 固定の印は候補の後ろ（まとまりや隠す候補の一覧の前）に置く
+fn set_pinned(candidates: &mut Vec<String>, cand: Option<&str>) {
+    candidates.retain(|c| decode_pinned(c).is_none());
+    if let Some(cand) = cand {
+        let at = candidates
+            .iter()
+            .position(|c| !is_word(c))
+            .unwrap_or(candidates.len());
+        candidates.insert(at, encode_pinned(cand));
+    }
+}
+
+// 候補の末尾に置く、隠す候補の一覧を書き換える（空になれば取り除く）
+fn set_ignored(candidates: &mut Vec<String>, edit: impl FnOnce(&mut Vec<String>)) {
+    let mut ignored = Vec::new();
+    candidates.retain(|c| match decode_ignored(c) {
+        Some(words) => {
+            ignored = words;
+            false
+        }
+        None => true,
+    });
+    edit(&mut ignored);
+    if !ignored.is_empty() {
+        candidates.push(encode_ignored(&ignored));
+    }
+}
+
+fn read_entries(path: &str) -> io::Result<Vec<UserEntry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with(';'))
+    {
+        if let Some((yomi, rest)) = line.split_once(' ')
+            && rest.starts_with('/')
+        {
+            let okuri_ari = is_okuri_ari(yomi.as_bytes());
+            let mut candidates = Vec::<String>::new();
+            let mut in_block = false;
+            for s in split_candidates(rest).into_iter().filter(|s| !s.is_empty()) {
+                match s.strip_prefix('[') {
+                    Some(kana) if okuri_ari && !in_block && !kana.is_empty() => {
+                        candidates.push(format!("{}/", s));
+                        in_block = true;
+                    }
+                    _ if in_block => {
+                        let block = candidates.last_mut().unwrap();
+                        block.push_str(s);
+                        if s == "]" {
+                            in_block = false;
+                        } else {
+                            block.push('/');
+                        }
+                    }
+                    _ => candidates.push(s.to_string()),
+                }
+            }
+            entries.push((yomi.to_string(), candidates));
+        }
+    }
+    Ok(entries)
+}
+
+fn format_entries(entries: &[UserEntry]) -> String {
+    let mut text = String::from(HEADER);
+    for (yomi, candidates) in entries {
+        text.push_str(yomi);
+        text.push_str(" /");
+        for c in candidates {
+            text.push_str(c);
+            text.push('/');
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// 学習の統計はSKK辞書の書式を崩さないよう別ファイル（<path>.stats）に置く
+// 書式：読み<TAB>候補<TAB>回数<TAB>時刻
+fn stats_path(path: &str) -> String {
+    format!("{}{}", path, STATS_SUFFIX)
+}
+
+fn read_stats(path: &str) -> io::Result<Stats> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Stats::new()),
+        Err(e) => return Err(e),
+    };
+    let mut stats = Stats::new();
+    for line in text.lines() {
+        let mut it = line.split('\t');
+        if let (Some(yomi), Some(cand), Some(weight), Some(time)) =
+            (it.next(), it.next(), it.next(), it.next())
+            && let (Ok(weight), Ok(time)) = (weight.parse(), time.parse())
+        {
+            stats.insert((yomi.to_string(), cand.to_string()), Stat { weight, time });
+        }
+    }
+    Ok(stats)
+}
+
+fn format_stats(stats: &Stats) -> String {
+    let mut text = String::new();
+    for ((yomi, cand), stat) in stats {
+        text.push_str(&format!(
+            "{}\t{}\t{:.3}\t{}\n",
+            yomi, cand, stat.weight, stat.time
+        ));
+    }
+    text
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// 半減期ごとに重みが半分になる
+fn decayed(stat: &Stat, now: u64, half_life: Duration) -> f64 {
+    let age = now.saturating_sub(stat.time) as f64;
+    stat.weight * 0.5f64.powf(age / half_life.as_secs_f64())
+}
+
+// 一時ファイルに書いてからrenameで置き換える（途中で落ちても元のファイルは壊れない）
+fn write_atomic(path: &str, data: &[u8]) -> io::Result<()> {
+    let tmp = format!("{}.{}.tmp", path, process::id());
+    let r = write_and_sync(&tmp, data).and_then(|_| fs::rename(&tmp, path));
+    if r.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    r
+}
+
+fn write_and_sync(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    f.write_all(data)?;
+    f.sync_all()
+}
+
+// <path>.lock を排他的に作成できたインスタンスだけが書き込む
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: &str) -> io::Result<Self> {
+        let lock = PathBuf::from(format!("{}.lock", path));
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(mut f) => {
+                    let _ = writeln!(f, "{}", process::id());
+                    return Ok(Self(lock));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&lock) {
+                        let _ = fs::remove_file(&lock);
+                        continue;
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(Error::new(
+                            ErrorKind::WouldBlock,
+                            format!("user jisyo is locked: {}", lock.display()),
+                        ));
+                    }
+                    thread::sleep(LOCK_RETRY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn is_stale(lock: &Path) -> bool {
+    fs::metadata(lock)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_some_and(|age| age > LOCK_STALE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempPath;
+
+    fn load(path: &str) -> UserJisyo {
+        UserJisyo::load(path, None, false).unwrap()
+    }
+
+    fn lines(path: &str) -> Vec<String> {
+        let text = fs::read_to_string(path).unwrap();
+        text.lines().skip(1).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn lock_waits_for_the_holder_and_is_released() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let lock = Lock::acquire(path).unwrap();
+        let lock_path = format!("{}.lock", path);
+        assert!(Path::new(&lock_path).exists());
+        let waiter = {
+            let path = path.to_string();
+            thread::spawn(move || Lock::acquire(&path).map(|_| ()))
+        };
+        thread::sleep(LOCK_RETRY * 2);
+        drop(lock);
+        waiter.join().unwrap().unwrap();
+        assert!(!Path::new(&lock_path).exists());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let stale = File::create(format!("{}.lock", path)).unwrap();
+        let old = SystemTime::now() - LOCK_STALE * 2;
+        stale.set_modified(old).unwrap();
+        let started = Instant::now();
+        drop(Lock::acquire(path).unwrap());
+        assert!(started.elapsed() < LOCK_TIMEOUT);
+    }
+
+    #[test]
+    fn save_keeps_what_another_instance_saved() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let (mut a, mut b) = (load(path), load(path));
+        a.record("あ", "亜", None);
+        b.record("い", "井", None);
+        b.record("あ", "阿", None);
+        a.save().unwrap();
+        b.save().unwrap();
+        // 後に保存した方の見出し語が先頭、学習した候補はどちらも残る
+        assert_eq!(lines(path), ["あ /阿/亜/", "い /井/"]);
+        // 読み直すと、もう一方の学習も引ける
+        a.reload().unwrap();
+        assert_eq!(a.lookup("い", None).0, ["井"]);
+    }
+
+    #[test]
+    fn purge_replaces_what_another_instance_saved() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let (mut a, mut b) = (load(path), load(path));
+        a.record("あ", "亜", None);
+        a.record("あ", "阿", None);
+        a.save().unwrap();
+        b.purge("あ", "亜");
+        b.save().unwrap();
+        let (fresh, _) = load(path).lookup("あ", None);
+        assert_eq!(fresh, Vec::<String>::new());
+        assert_eq!(load(path).ignored("あ"), ["亜"]);
+    }
+
+    #[test]
+    fn merge_keeps_our_okuri_block() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let (mut a, mut b) = (load(path), load(path));
+        a.record("かk", "欠", Some("く"));
+        a.save().unwrap();
+        b.record("かk", "書", Some("く"));
+        b.save().unwrap();
+        assert_eq!(lines(path), ["かk /書/[く/書/]/欠/"]);
+        let (fresh, _) = load(path).lookup("かk", Some("く"));
+        assert_eq!(fresh, ["書", "欠"]);
+    }
+
+    #[test]
+    fn reload_keeps_unsaved_changes() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let (mut a, mut b) = (load(path), load(path));
+        a.record("あ", "亜", None);
+        b.record("い", "井", None);
+        b.save().unwrap();
+        a.reload().unwrap();
+        assert_eq!(a.lookup("あ", None).0, ["亜"]);
+        assert_eq!(a.lookup("い", None).0, ["井"]);
+        a.save().unwrap();
+        assert_eq!(lines(path), ["あ /亜/", "い /井/"]);
+    }
+
+    #[test]
+    fn purge_takes_the_word_out_of_its_block_and_restore_puts_it_back() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let mut user = load(path);
+        user.record("かk", "欠", None);
+        user.record("かk", "書", Some("き"));
+        user.purge("かk", "書");
+        assert_eq!(user.lookup("かk", Some("き")).0, ["欠"]);
+        assert_eq!(user.ignored("かk"), ["書"]);
+        user.save().unwrap();
+        assert_eq!(lines(path), ["かk /欠/(skk-ignore-dic-word \"書\")/"]);
+        // まとまりは戻さないが、候補の位置と隠す印は戻す
+        let restored = Some((String::from("かk"), String::from("書")));
+        assert_eq!(user.restore(), restored);
+        assert_eq!(user.lookup("かk", None).0, ["書", "欠"]);
+        assert!(user.ignored("かk").is_empty());
+    }
+
+    #[test]
+    fn pin_is_not_a_candidate_and_purge_unpins() {
+        let tmp = TempPath::new("jisyo.user");
+        let path = tmp.as_str();
+        let mut user = load(path);
+        user.record("あ", "亜", None);
+        user.pin("あ", Some("阿"));
+        user.record("あ", "吾", None);
+        assert_eq!(user.pinned("あ").as_deref(), Some("阿"));
+        // 固定の印は候補には出さない
+        assert_eq!(user.lookup("あ", None).0, ["吾", "亜"]);
+        user.purge("あ", "阿");
+        assert_eq!(user.pinned("あ"), None);
+    }
+}
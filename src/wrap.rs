@@ -0,0 +1,62 @@
+//! 段落の折り返し（メール本文など、送り先の1行の幅に合わせて改行を入れる）
+
+use crate::frontend::cell_width;
+
+// 行頭に置かない文字（句読点・閉じ括弧・小書きの仮名・長音）
+const NO_LINE_START: &str = "、。，．・：；？！ー～…‥」』）］｝〕〉》】ぁぃぅぇぉっゃゅょゎァィゥェォッャュョヮヵヶ,.:;?!)]}";
+// 行末に置かない文字（開き括弧）
+const NO_LINE_END: &str = "「『（［｛〔〈《【([{";
+
+/// 1行がwidthセル以内になるように折り返す（禁則を守れない場合だけ幅を超える）
+///
+/// 英単語の途中では折らず、折った位置の空白は取り除く。
+pub fn wrap(text: &[char], width: usize) -> Vec<Vec<char>> {
+    let mut lines = Vec::new();
+    let mut cur: Vec<char> = Vec::new();
+    let mut used = 0;
+    for &c in text {
+        let w = cell_width(c);
+        if used + w > width && !cur.is_empty() {
+            let at = break_point(&cur, c);
+            let mut next = cur.split_off(at);
+            trim_end(&mut cur);
+            let spaces = next.iter().take_while(|c| **c == ' ').count();
+            next.drain(..spaces);
+            lines.push(std::mem::replace(&mut cur, next));
+            used = cur.iter().map(|c| cell_width(*c)).sum();
+        }
+        if c == ' ' && cur.is_empty() && !lines.is_empty() {
+            continue;
+        }
+        cur.push(c);
+        used += w;
+    }
+    trim_end(&mut cur);
+    lines.push(cur);
+    lines
+}
+
+// curの後ろにnextが続くとき、折ってよい位置のうち最も後ろ（なければ末尾で強制的に折る）
+fn break_point(cur: &[char], next: char) -> usize {
+    (1..=cur.len())
+        .rev()
+        .find(|&at| {
+            let before = cur[at - 1];
+            let after = cur.get(at).copied().unwrap_or(next);
+            can_break(before, after)
+        })
+        .unwrap_or(cur.len())
+}
+
+fn can_break(before: char, after: char) -> bool {
+    let in_word = |c: char| c.is_ascii_graphic() && !NO_LINE_START.contains(c);
+    let kinsoku = NO_LINE_START.contains(after) || NO_LINE_END.contains(before);
+    let mid_word = in_word(before) && in_word(after);
+    !kinsoku && !mid_word
+}
+
+fn trim_end(line: &mut Vec<char>) {
+    while line.last() == Some(&' ') {
+        line.pop();
+    }
+}